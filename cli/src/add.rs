@@ -24,23 +24,26 @@ pub fn add_subcommand(mut hupas: Vec<Hupa>, config: &Config, sub_m: &ArgMatches)
             println!("- {}", category);
         }
         let category = read_line("Categories (ex: os/linux): ", true);
-        let origin = read_line("Origin path: ", true);
-                #[cfg(unix)]
-        let origin = origin.replace('~', env!("HOME"));
+        let origins = read_origins();
         let autobackup = read_line_bool("Enable autobackup (y/n)? ");
-        let needed_vars = read_line("Needed vars: ", false)
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect();
-        let hupa = Hupa::new(
+        let needed_vars = read_needed_vars("Needed vars (ex: gentoo && !laptop): ");
+        let rules = read_rules();
+        let compression = read_compression();
+        let encrypted = read_line_bool("Encrypt backed up files (y/n)? ");
+        let mut hupa = Hupa::new(
             name.clone(),
             desc,
             category.split('/').map(|s| s.to_string()).collect(),
             Hupa::get_default_backup_parent().expect("Can't get default backup parent"),
-            origin,
+            &origins[0],
             autobackup,
             needed_vars,
         );
+        hupa.set_origins(origins);
+        hupa.set_rules(rules);
+        hupa.set_compression(compression);
+        hupa.set_encrypted(encrypted);
+        hupa.set_secret(config.secret.clone());
         for hupa_stored in &hupas {
             if hupa_stored.get_name() == hupa.get_name() &&
                 hupa_stored.get_category() == hupa.get_category() &&
@@ -4,13 +4,20 @@ use libhupa::*;
 
 /// Backup subcommand
 pub fn backup_subcommand(hupas: &[Hupa], vars: &VarsHandler, sub_m: &ArgMatches) {
-    if sub_m.is_present("all") {
-        backup(hupas, vars);
+    let hupas = if sub_m.is_present("all") {
+        hupas.to_vec()
     } else if let Some(hupas_names) = sub_m.values_of("hupa") {
         let hupas_names: Vec<String> = hupas_names.map(|s| s.to_string()).collect();
-        backup(&resolve_names(&hupas_names, hupas), vars);
+        resolve_names(&hupas_names, hupas)
+    } else {
+        select_hupas(hupas, "Select hupas to backup")
+    };
+    if sub_m.is_present("dry_run") {
+        for hupa in &hupas {
+            let backup_dir = hupa.latest_backup_dir().unwrap_or_else(|| hupa.backup_dir());
+            print_plan(hupa, |h| h.plan_backup(vars), &backup_dir, "Backing up");
+        }
     } else {
-        let hupas = select_hupas(hupas, "Select hupas to backup");
         backup(&hupas, vars);
     }
 }
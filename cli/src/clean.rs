@@ -4,25 +4,82 @@ use libhupa::*;
 
 /// Clean subcommand
 pub fn clean_subcommand(hupas: &[Hupa], sub_m: &ArgMatches) {
-    if sub_m.is_present("all") {
-        clean(hupas);
+    let keep = sub_m
+        .value_of("keep")
+        .map(|k| match k.parse::<usize>() {
+            Ok(0) => panic!("At least one generation must be kept"),
+            k => k.expect("Invalid number of generations"),
+        });
+    let rollback = sub_m.is_present("rollback");
+    let hupas = if sub_m.is_present("all") {
+        hupas.to_vec()
     } else if let Some(hupas_names) = sub_m.values_of("hupa") {
         let hupas_names: Vec<String> = hupas_names.map(|s| s.to_string()).collect();
-        clean(&resolve_names(&hupas_names, hupas));
+        resolve_names(&hupas_names, hupas)
     } else {
-        let hupas = select_hupas(hupas, "Select hupas to clean");
-        clean(&hupas);
+        select_hupas(hupas, "Select hupas to clean")
+    };
+    if sub_m.is_present("dry_run") {
+        plan_clean(&hupas, keep, rollback);
+    } else {
+        clean(&hupas, keep, rollback);
+    }
+}
+
+/// Print what `clean` would delete
+pub fn plan_clean(hupas: &[Hupa], keep: Option<usize>, rollback: bool) {
+    for hupa in hupas {
+        if rollback {
+            let rollback_dir = hupa.rollback_dir().expect("Can't get rollback directory");
+            print_plan(
+                hupa,
+                |h| h.plan_delete_rollback(),
+                &rollback_dir,
+                "Cleaning rollback of",
+            );
+            continue;
+        }
+        let backup_dir = hupa.backup_dir();
+        match keep {
+            Some(k) => print_plan(hupa, |h| h.plan_prune_generations(k), &backup_dir, "Pruning"),
+            None => print_plan(hupa, |h| h.plan_delete_backup(), &backup_dir, "Cleaning"),
+        }
     }
 }
 
 /// Clean hupas with interface
-pub fn clean(hupas: &[Hupa]) {
+///
+/// `keep` - Number of generations to keep, everything is deleted if `None`
+///
+/// `rollback` - Clean rollback areas instead of backups
+pub fn clean(hupas: &[Hupa], keep: Option<usize>, rollback: bool) {
     for hupa in hupas {
-        exec_hupa(
-            hupa,
-            |h| h.delete_backup(),
-            &PrintOrder::BackupToNull,
-            "Cleaning",
-        );
+        if rollback {
+            exec_hupa(
+                hupa,
+                |h| h.delete_rollback(),
+                &PrintOrder::RollbackToNull,
+                "Cleaning rollback of",
+            );
+            continue;
+        }
+        match keep {
+            Some(k) => {
+                exec_hupa(
+                    hupa,
+                    |h| h.prune_generations(k),
+                    &PrintOrder::BackupToNull,
+                    "Pruning",
+                )
+            }
+            None => {
+                exec_hupa(
+                    hupa,
+                    |h| h.delete_backup(),
+                    &PrintOrder::BackupToNull,
+                    "Cleaning",
+                )
+            }
+        }
     }
 }
@@ -3,6 +3,7 @@ use colored::*;
 use humansize::FileSize;
 use io::*;
 use std::io::Write;
+use std::path::Path;
 use libhupa::*;
 
 /// Return list of hupa from `hupas_names`
@@ -30,6 +31,8 @@ pub enum PrintOrder {
     BackupToOrigin,
     OriginToBackup,
     BackupToNull,
+    RollbackToOrigin,
+    RollbackToNull,
 }
 
 /// Interface for actions
@@ -46,20 +49,20 @@ where
         .unwrap_or(0)
         .file_size(DEFAULT_FSO)
         .expect("Error while showing file size");
+    let rollback = hupa.get_rollback_size()
+        .unwrap_or(0)
+        .file_size(DEFAULT_FSO)
+        .expect("Error while showing file size");
+    let void = 0.file_size(DEFAULT_FSO).expect(
+        "Error while showing file size",
+    );
 
     let (first, second, first_str, second_str) = match *size_order {
         PrintOrder::BackupToOrigin => (backup, origin, "backup", "origin"),
         PrintOrder::OriginToBackup => (origin, backup, "origin", "backup"),
-        PrintOrder::BackupToNull => {
-            (
-                backup,
-                0.file_size(DEFAULT_FSO).expect(
-                    "Error while showing file size",
-                ),
-                "backup",
-                "void",
-            )
-        }
+        PrintOrder::BackupToNull => (backup, void, "backup", "void"),
+        PrintOrder::RollbackToOrigin => (rollback, origin, "rollback", "origin"),
+        PrintOrder::RollbackToNull => (rollback, void, "rollback", "void"),
     };
     writef!(
         stdout,
@@ -74,7 +77,21 @@ where
     match exec(hupa) {
         Ok(opres) => {
             match opres {
-                OperationResult::Change => writef!(stdout, "{}", "OK!".green()),
+                OperationResult::Change(stats) => {
+                    writef!(stdout, "{}", "OK!".green());
+                    if !stats.is_empty() {
+                        writef!(
+                            stdout,
+                            " ({} added, {} updated, {} removed)",
+                            stats.added,
+                            stats.updated,
+                            stats.removed
+                        );
+                    }
+                    if stats.skipped > 0 {
+                        writef!(stdout, " ({} special files skipped)", stats.skipped);
+                    }
+                }
                 OperationResult::NoChange => writef!(stdout, "{}", "No change...".yellow()),
             }
         }
@@ -86,6 +103,93 @@ where
     writef!(stdout, "\n");
 }
 
+/// Interface for dry-runs
+///
+/// `root` - Path the planned paths are relative to
+pub fn print_plan<F>(hupa: &Hupa, plan: F, root: &Path, print: &str)
+where
+    F: FnOnce(&Hupa) -> Result<Vec<PlannedAction>>,
+{
+    println!("{} {} (dry run)", print, hupa.get_name().yellow());
+    let plan = match plan(hupa) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("    {} {}", "Error:".red(), e);
+            return;
+        }
+    };
+    if plan.is_empty() {
+        println!("    {}", "No change...".yellow());
+        return;
+    }
+    let mut total = 0;
+    for action in &plan {
+        let name = match action.action {
+            Action::Create => "create".green(),
+            Action::Overwrite => "overwrite".yellow(),
+            Action::Delete => "delete".red(),
+            Action::Skip => "skip".blue(),
+        };
+        let path = if action.path.as_os_str().is_empty() {
+            root.to_path_buf()
+        } else {
+            root.join(&action.path)
+        };
+        println!(
+            "    {:<9} {} ({})",
+            name,
+            path.display(),
+            action.size.file_size(DEFAULT_FSO).expect(
+                "Error while showing file size",
+            )
+        );
+        total += action.size;
+    }
+    println!(
+        "    {} files, {}",
+        plan.len(),
+        total.file_size(DEFAULT_FSO).expect(
+            "Error while showing file size",
+        )
+    );
+}
+
+/// Print rules of a hupa on one line
+pub fn print_rules(rules: &Rules) {
+    let mut parts = Vec::new();
+    if !rules.get_include().is_empty() {
+        parts.push(format!("include {}", rules.get_include().join(" ")));
+    }
+    if !rules.get_exclude().is_empty() {
+        parts.push(format!("exclude {}", rules.get_exclude().join(" ")));
+    }
+    if let Some(max) = rules.get_max_file_size() {
+        parts.push(format!(
+            "max file size {}",
+            max.file_size(DEFAULT_FSO).expect("Error while showing file size")
+        ));
+    }
+    if rules.is_one_file_system() {
+        parts.push("one file system".to_string());
+    }
+    if rules.is_following_symlinks() {
+        parts.push("follow symlinks".to_string());
+    }
+    if parts.is_empty() {
+        parts.push("none".to_string());
+    }
+    println!("rules: {}", parts.join(", "));
+}
+
+/// Format requirements on vars, all of them being needed
+pub fn requirements_str(requirements: &[Requirement]) -> String {
+    match requirements.len() {
+        0 => "none".to_string(),
+        1 => requirements[0].to_string(),
+        _ => Requirement::And(requirements.to_vec()).to_string(),
+    }
+}
+
 /// Select hupas
 pub fn select_hupas(hupas: &[Hupa], print: &str) -> Vec<Hupa> {
     for (i, hupa) in hupas.iter().enumerate() {
@@ -1,14 +1,17 @@
 use io::*;
 use libhupa::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Config subcommand
 pub fn config_subcommand(mut config: Config) {
     println!("[1] Set metadata path");
     println!("[2] Set autobackup interval");
     println!("[3] Set vars path");
-    println!("[4] Cancel");
-    let idxs = read_line_usize("Choose what to change [1-4]: ", false, 4);
+    println!("[4] Set rollback max size");
+    println!("[5] Set encryption key");
+    println!("[6] Set metadata format");
+    println!("[7] Cancel");
+    let idxs = read_line_usize("Choose what to change [1-7]: ", false, 7);
     for i in idxs {
         match i {
             1 => {
@@ -30,15 +33,84 @@ pub fn config_subcommand(mut config: Config) {
                 let path = Path::new(&path);
                 config.vars_path = path.to_path_buf();
             }
+            4 => {
+                println!("Current rollback max size: {}", config.rollback_max_size);
+                config.rollback_max_size = read_line_parse("Rollback max size in bytes: ");
+            }
+            5 => {
+                println!("Current encryption key: {}", secret_str(&config.secret));
+                config.secret = read_secret();
+            }
+            6 => {
+                println!("Current metadata format: {}", format_str(&config));
+                config.metadata_format = read_metadata_format();
+            }
             _ => {}
         }
     }
     println!("New config:");
     println!("Metadata path: {}", config.metadata_path.display());
+    println!("Metadata format: {}", format_str(&config));
     println!("Autobackup interval: {}", config.autobackup_interval);
     println!("Vars path: {}", config.vars_path.display());
+    println!("Rollback max size: {}", config.rollback_max_size);
+    println!("Encryption key: {}", secret_str(&config.secret));
     let result = read_line_bool("Do you want to save this config? [y/n] ");
     if result {
         config.save_config().expect("Can't save config");
     }
 }
+
+/// Describe a secret, without its passphrase
+fn secret_str(secret: &Option<Secret>) -> String {
+    match *secret {
+        Some(Secret::Keyfile(ref k)) => format!("keyfile {}", k.display()),
+        Some(Secret::Passphrase(_)) => "passphrase, it will not be saved".to_string(),
+        None => "passphrase".to_string(),
+    }
+}
+
+/// Describe the metadata format
+fn format_str(config: &Config) -> String {
+    match config.metadata_format {
+        Some(ref f) => f.clone(),
+        None => format!(
+            "detected from extension ({})",
+            detect_format(&config.metadata_path).name()
+        ),
+    }
+}
+
+/// Read the metadata format, `None` to detect it from the extension
+fn read_metadata_format() -> Option<String> {
+    loop {
+        let prompt = format!(
+            "Metadata format ({}, empty to detect it from the extension): ",
+            METADATA_FORMATS.join("/")
+        );
+        let format = read_line(&prompt, false);
+        if format.is_empty() {
+            return None;
+        }
+        match get_format(&format) {
+            Ok(f) => return Some(f.name().to_string()),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Read the secret of encrypted hupas
+///
+/// A passphrase is not saved, it is read from `HUPA_PASSPHRASE` or asked
+/// when it is needed.
+fn read_secret() -> Option<Secret> {
+    let keyfile = read_line("Keyfile path (empty to use a passphrase): ", false);
+    if !keyfile.is_empty() {
+        return Some(Secret::Keyfile(PathBuf::from(keyfile)));
+    }
+    println!(
+        "The passphrase is not saved, set {} or type it when it is asked",
+        PASSPHRASE_VAR
+    );
+    None
+}
@@ -0,0 +1,56 @@
+use clap::ArgMatches;
+use colored::*;
+use libhupa::*;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Generate subcommand
+pub fn generate_subcommand(hupas: &[Hupa], vars: &VarsHandler, sub_m: &ArgMatches) {
+    // Without explicit format, guess it from the output extension
+    let output_ext = sub_m
+        .value_of("output")
+        .and_then(|o| Path::new(o).extension())
+        .and_then(|e| e.to_str());
+    let format = match (sub_m.value_of("format"), output_ext) {
+        (Some(f), _) | (None, Some(f @ "zip")) => f,
+        _ => "tar",
+    }.parse::<ArchiveFormat>()
+        .expect("Invalid archive format");
+    let default_name = format!("hupas.{}", format.extension());
+    let output = match sub_m.value_of("output") {
+        Some(o) => {
+            let output = PathBuf::from(o);
+            if output.is_dir() {
+                output.join(default_name)
+            } else {
+                output
+            }
+        }
+        None => PathBuf::from(default_name),
+    };
+    generate(hupas, vars, format, &output);
+}
+
+/// Generate archive with interface
+pub fn generate(hupas: &[Hupa], vars: &VarsHandler, format: ArchiveFormat, output: &Path) {
+    let mut stdout = ::std::io::stdout();
+    writef!(
+        stdout,
+        "Generating {} archive of {} hupa(s) to {}... ",
+        format,
+        hupas.len(),
+        output.display().to_string().yellow()
+    );
+    let result = File::create(output)
+        .map_err(Error::from)
+        .and_then(|f| generate_archive(f, format, hupas, vars));
+    match result {
+        Ok(_) => writef!(stdout, "{}", "OK!".green()),
+        Err(e) => {
+            write!(stdout, "{}", "Error: ".red()).expect("Can't write to stdout");
+            writef!(stdout, "{}", e);
+        }
+    }
+    writef!(stdout, "\n");
+}
@@ -1,8 +1,8 @@
 use colored::*;
 use libhupa;
 use libhupa::*;
-use std::fs::File;
 use std::io::Write;
+use std::path::PathBuf;
 
 /// Read line
 pub fn read_line(print: &str, need_input: bool) -> String {
@@ -22,6 +22,47 @@ pub fn read_line(print: &str, need_input: bool) -> String {
     buf
 }
 
+/// Read a passphrase, without echoing it when stdin is a terminal
+pub fn read_passphrase(print: &str) -> String {
+    let mut stdout = ::std::io::stdout();
+    writef!(stdout, "{}", print);
+    let echo = disable_echo();
+    let mut buf = String::new();
+    let result = ::std::io::stdin().read_line(&mut buf);
+    if let Some(e) = echo {
+        restore_echo(e);
+        println!();
+    }
+    result.expect("Error while reading stdin");
+    buf.trim_end_matches(['\n', '\r']).to_string()
+}
+
+/// Disable the echo of the terminal, return its previous state
+#[cfg(unix)]
+fn disable_echo() -> Option<::libc::termios> {
+    let mut term = unsafe { ::std::mem::zeroed::<::libc::termios>() };
+    if unsafe { ::libc::tcgetattr(::libc::STDIN_FILENO, &mut term) } != 0 {
+        return None;
+    }
+    let mut hidden = term;
+    hidden.c_lflag &= !::libc::ECHO;
+    unsafe { ::libc::tcsetattr(::libc::STDIN_FILENO, ::libc::TCSANOW, &hidden) };
+    Some(term)
+}
+
+#[cfg(unix)]
+fn restore_echo(term: ::libc::termios) {
+    unsafe { ::libc::tcsetattr(::libc::STDIN_FILENO, ::libc::TCSANOW, &term) };
+}
+
+#[cfg(not(unix))]
+fn disable_echo() -> Option<()> {
+    None
+}
+
+#[cfg(not(unix))]
+fn restore_echo(_: ()) {}
+
 /// Read line and parse
 pub fn read_line_parse<T: ::std::str::FromStr>(print: &str) -> T {
     loop {
@@ -111,8 +152,84 @@ fn parse_one(s: &str, or: usize) -> usize {
     s.parse().unwrap_or(0)
 }
 
+/// Read rules of a hupa
+pub fn read_rules() -> Rules {
+    loop {
+        let include = read_line("Include patterns: ", false);
+        let exclude = read_line("Exclude patterns: ", false);
+        let max_file_size = read_line("Max file size in bytes (empty for no limit): ", false);
+        let max_file_size = if max_file_size.is_empty() {
+            None
+        } else {
+            match max_file_size.parse::<u64>() {
+                Ok(m) => Some(m),
+                Err(_) => {
+                    println!("{}", "Max file size is not a number".red());
+                    continue;
+                }
+            }
+        };
+        let one_file_system = read_line_bool("Stay on one filesystem (y/n)? ");
+        let follow_symlinks = read_line_bool("Follow symlinks instead of copying them (y/n)? ");
+        let include = include.split_whitespace().collect::<Vec<&str>>();
+        let exclude = exclude.split_whitespace().collect::<Vec<&str>>();
+        match Rules::new(&include, &exclude, max_file_size, one_file_system) {
+            Ok(mut r) => {
+                r.set_follow_symlinks(follow_symlinks);
+                return r;
+            }
+            Err(e) => println!("{} {}", "Error:".red(), e),
+        }
+    }
+}
+
+/// Read origin paths of a hupa, at least one
+///
+/// Paths are kept as written, `~` and variables being expanded when used.
+pub fn read_origins() -> Vec<PathBuf> {
+    let mut origins = Vec::new();
+    loop {
+        let origin = if origins.is_empty() {
+            read_line("Origin path: ", true)
+        } else {
+            read_line("Other origin path (empty to finish): ", false)
+        };
+        if origin.is_empty() {
+            return origins;
+        }
+        origins.push(PathBuf::from(origin));
+    }
+}
+
+/// Read requirement on vars of a hupa, none if empty
+pub fn read_needed_vars(print: &str) -> Vec<Requirement> {
+    loop {
+        let requirement = read_line(print, false);
+        if requirement.is_empty() {
+            return Vec::new();
+        }
+        match requirement.parse() {
+            Ok(r) => return vec![r],
+            Err(e) => println!("{} {}", "Error:".red(), e),
+        }
+    }
+}
+
+/// Read compression of a hupa
+pub fn read_compression() -> Compression {
+    loop {
+        let compression = read_line("Compression (none, gzip, zstd) [none]: ", false);
+        if compression.is_empty() {
+            return Compression::None;
+        }
+        match compression.parse() {
+            Ok(c) => return c,
+            Err(e) => println!("{} {}", "Error:".red(), e),
+        }
+    }
+}
+
 /// Save hupas
 pub fn save_hupas(config: &Config, hupas: &[Hupa]) {
-    let mut f = File::create(&config.metadata_path).expect("Can't create metadata file");
-    libhupa::write_metadata(&mut f, &hupas.to_vec()).expect("Can't write to metadata file");
+    libhupa::write_metadata_to_config(config, hupas).expect("Can't write to metadata file");
 }
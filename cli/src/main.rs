@@ -21,7 +21,11 @@ mod show;
 mod backup;
 mod restore;
 mod clean;
+mod generate;
+mod metadata;
+mod unpack;
 mod vars;
+mod verify;
 
 use add::*;
 use remove::*;
@@ -31,10 +35,15 @@ use show::*;
 use backup::*;
 use restore::*;
 use clean::*;
+use generate::*;
+use metadata::*;
+use unpack::*;
 use vars::*;
+use verify::*;
 
 use clap::AppSettings;
 use clap::ArgMatches;
+use colored::*;
 use humansize::file_size_opts;
 use humansize::file_size_opts::FileSizeOpts;
 use libhupa::*;
@@ -71,12 +80,20 @@ fn main() {
             (@subcommand backup =>
              (about: "Backup hupa(s)")
              (@arg all: -a --all conflicts_with[hupa] "Backup all hupas")
-             (@arg hupa: +takes_value +multiple "Hupa(s) to backup"))
+             (@arg hupa: +takes_value +multiple "Hupa(s) to backup")
+             (@arg dry_run: -n --("dry-run") "Show what would be done without doing it"))
             (@subcommand restore =>
              (about: "Restore hupa(s)")
              (@arg all: -a --all conflicts_with[hupa] "Restore all hupas")
              (@arg hupa: +takes_value +multiple "Hupa(s) to restore")
-             (@arg ignore_root: -i --("ignore-root") "Ignore hupas that need root access, only for unix"))
+             (@arg ignore_root: -i --("ignore-root") "Ignore hupas that need root access, only for unix")
+             (@arg at: --at +takes_value conflicts_with[generation] "Restore the latest generation made at or before this unix timestamp")
+             (@arg generation: -g --generation +takes_value "Restore the N-th generation before the latest, 0 is the latest")
+             (@arg dry_run: -n --("dry-run") "Show what would be done without doing it"))
+            (@subcommand undo_restore =>
+             (name: "undo-restore")
+             (about: "Put back files as they were before the last restore of hupa(s)")
+             (@arg hupa: +takes_value +multiple "Hupa(s) to undo"))
             (@subcommand generate =>
              (about: "Generate an archive of all hupas")
              (@arg format: -f --format +takes_value possible_value[tar zip] "File format to use for achive")
@@ -96,7 +113,15 @@ fn main() {
             (@subcommand clean =>
              (about: "Clean hupa(s)")
              (@arg all: -a --all "Clean all hupas")
-             (@arg hupa: +takes_value +multiple "Hupa(s) to clean"))
+             (@arg hupa: +takes_value +multiple "Hupa(s) to clean")
+             (@arg keep: -k --keep +takes_value conflicts_with[rollback] "Keep the N most recent generations")
+             (@arg rollback: -r --rollback "Clean rollback areas instead of backups")
+             (@arg dry_run: -n --("dry-run") "Show what would be done without doing it"))
+            (@subcommand verify =>
+             (about: "Verify backups of hupa(s) against checksums recorded when they were made")
+             (@arg hupa: +takes_value +multiple "Hupa(s) to verify, all hupas if none")
+             (@arg generation: -g --generation +takes_value "Verify the N-th generation before the latest, 0 is the latest")
+             (@arg sha256sum: --sha256sum "Print a sha256sum compatible listing of the backups instead, they must not be compressed or encrypted"))
             (@subcommand vars => 
              (about: "Manipulate vars")
              (setting: AppSettings::SubcommandRequiredElseHelp)
@@ -104,6 +129,13 @@ fn main() {
              (@subcommand remove => (about: "Remove var(s)"))
              (@subcommand modify => (about: "Modify var(s)"))
              (@subcommand list => (about: "List var(s)")))
+            (@subcommand metadata =>
+             (about: "Manipulate metadata")
+             (setting: AppSettings::SubcommandRequiredElseHelp)
+             (@subcommand convert =>
+              (about: "Convert metadata to another format")
+              (@arg format: +required +takes_value possible_value[json toml yaml] "Format to convert to")
+              (@arg output: -o --output +takes_value "Output file, next to the metadata with the extension of the format by default")))
     ).get_matches();
 
     if let Some(u) = get_arg_recursive(&matches, "user") {
@@ -116,18 +148,61 @@ fn main() {
         Some(s) => Config::read_config_from_path(s),
         None => Config::read_config(),
     }.unwrap_or(config_default);
-    let vars = if let Ok(mut s) = File::open(&config.vars_path) {
-        VarsHandler::read_from_stream(&mut s).unwrap_or(VarsHandler::new(Vec::new()))
-    } else {
-        VarsHandler::new(Vec::new())
+    let vars = match File::open(&config.vars_path).map(|mut s| VarsHandler::read_from_stream(&mut s)) {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => {
+            eprintln!("{} {}", "Error:".red(), e);
+            // An invalid vars file must not be overwritten
+            if matches.subcommand_name() == Some("vars") {
+                ::std::process::exit(1);
+            }
+            VarsHandler::new(Vec::new())
+        }
+        Err(_) => VarsHandler::new(Vec::new()),
+    }.with_builtins();
+    let metadata = match matches.value_of("metadata") {
+        Some(p) => {
+            let mut f = ::std::fs::File::open(p).expect(&format!("Can't open {}", p));
+            let format = config.get_metadata_format_of(p).expect("Invalid metadata format");
+            read_metadata_lenient(&mut f, &*format).map(|mut m| {
+                for hupa in &mut m.hupas {
+                    hupa.set_secret(config.get_secret());
+                }
+                m
+            })
+        }
+        None => read_metadata_from_config_lenient(&config),
     };
-    let mut hupas = match read_metadata_from_config(&config) {
-        Ok(h) => h,
-        Err(_) => Vec::new(),
+    let (mut hupas, metadata_valid) = match metadata {
+        Ok(m) => {
+            for e in &m.skipped {
+                eprintln!("{} {}", "Skipped:".yellow(), e);
+            }
+            let valid = m.skipped.is_empty();
+            (m.hupas, valid)
+        }
+        Err(e) => {
+            eprintln!("{} {}", "Error:".red(), e);
+            (Vec::new(), false)
+        }
     };
-    if let Some(p) = matches.value_of("metadata") {
-        let mut f = ::std::fs::File::open(p).expect(&format!("Can't open {}", p));
-        hupas = read_metadata(&mut f).unwrap_or(hupas);
+    // Skipped hupas would be lost if metadata was rewritten
+    let writes_metadata = ["add", "remove", "modify", "unpack", "metadata"];
+    if !metadata_valid && writes_metadata.contains(&matches.subcommand_name().unwrap_or("")) {
+        eprintln!("Metadata must be fixed first");
+        ::std::process::exit(1);
+    }
+    for hupa in &mut hupas {
+        hupa.set_template_vars(vars.clone());
+    }
+    // The passphrase is never saved, ask it when encrypted hupas need it
+    if config.get_secret().is_none() && needs_key(&matches) &&
+        hupas.iter().any(|h| h.is_encrypted())
+    {
+        let secret = Secret::Passphrase(io::read_passphrase("Passphrase of encrypted hupas: "));
+        for hupa in &mut hupas {
+            hupa.set_secret(Some(secret.clone()));
+        }
     }
 
 
@@ -154,18 +229,42 @@ fn main() {
             backup_subcommand(&hupas, &vars, sub_m);
         }
         ("restore", Some(sub_m)) => {
-            restore_subcommand(hupas, &vars, sub_m);
+            restore_subcommand(hupas, &vars, &config, sub_m);
+        }
+        ("undo-restore", Some(sub_m)) => {
+            undo_restore_subcommand(&hupas, sub_m);
         }
         ("clean", Some(sub_m)) => {
             clean_subcommand(&hupas, sub_m);
         }
+        ("generate", Some(sub_m)) => {
+            generate_subcommand(&hupas, &vars, sub_m);
+        }
+        ("unpack", Some(sub_m)) => {
+            unpack_subcommand(hupas, &config, sub_m);
+        }
+        ("verify", Some(sub_m)) => {
+            verify_subcommand(&hupas, sub_m);
+        }
         ("vars", Some(sub_m)) => {
             vars_subcommand(vars, &config, sub_m);
         }
+        ("metadata", Some(sub_m)) => {
+            metadata_subcommand(&hupas, &config, sub_m);
+        }
         (s, _) => println!("`{}` is not supported yet", s),
     }
 }
 
+/// Check if the subcommand reads or writes content of backups
+fn needs_key(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        ("backup", _) | ("restore", _) | ("verify", _) => true,
+        ("list", Some(sub_m)) | ("show", Some(sub_m)) => sub_m.is_present("size"),
+        _ => false,
+    }
+}
+
 fn get_arg_recursive(matches: &ArgMatches, arg: &str) -> Option<String> {
     if let Some(val) = matches.value_of(arg) {
         return Some(val.to_string());
@@ -0,0 +1,52 @@
+//! Metadata subcommand
+
+use clap::ArgMatches;
+use colored::*;
+use io::read_line_bool;
+use libhupa::*;
+use std::fs::File;
+use std::path::PathBuf;
+
+/// Metadata subcommand
+pub fn metadata_subcommand(hupas: &[Hupa], config: &Config, sub_m: &ArgMatches) {
+    match sub_m.subcommand() {
+        ("convert", Some(sub_m)) => metadata_convert_subcommand(hupas, config, sub_m),
+        (c, _) => println!("Command {} is not supported", c),
+    }
+}
+
+/// Metadata convert subcommand
+///
+/// The converted metadata is written next to the current one by default, the
+/// current one is kept as it is.
+pub fn metadata_convert_subcommand(hupas: &[Hupa], config: &Config, sub_m: &ArgMatches) {
+    let format = get_format(sub_m.value_of("format").unwrap()).expect("Invalid metadata format");
+    let output = match sub_m.value_of("output") {
+        Some(o) => PathBuf::from(o),
+        None => config.metadata_path.with_extension(format.extensions()[0]),
+    };
+    if output == config.metadata_path {
+        println!("Metadata is already at {}", output.display());
+        return;
+    }
+    if output.exists() &&
+        !read_line_bool(&format!("{} exists, overwrite it? [y/n] ", output.display()))
+    {
+        return;
+    }
+    let result = File::create(&output)
+        .map_err(Error::from)
+        .and_then(|mut f| write_metadata_with_format(&mut f, hupas, &*format));
+    match result {
+        Ok(_) => {
+            println!(
+                "Metadata of {} hupa(s) written in {} to {}",
+                hupas.len(),
+                format.name(),
+                output.display().to_string().yellow()
+            );
+            println!("Set it as metadata path with `hupa config` to use it");
+        }
+        Err(e) => println!("{} {}", "Error:".red(), e),
+    }
+}
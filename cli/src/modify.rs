@@ -21,11 +21,14 @@ pub fn modify_subcommand(mut hupas: Vec<Hupa>, config: &Config, sub_m: &ArgMatch
         println!("[2] Set description");
         println!("[3] Set category");
         println!("[4] Set backup parent");
-        println!("[5] Set origin path");
+        println!("[5] Set origin paths");
         println!("[6] Set autobackup");
         println!("[7] Set needed vars");
-        println!("[8] Cancel");
-        let idxs = read_line_usize("Select action [1-8]: ", false, 8);
+        println!("[8] Set rules");
+        println!("[9] Set compression");
+        println!("[10] Set encryption");
+        println!("[11] Cancel");
+        let idxs = read_line_usize("Select action [1-11]: ", false, 11);
         for i in idxs {
             match i {
                 1 => {
@@ -53,14 +56,16 @@ pub fn modify_subcommand(mut hupas: Vec<Hupa>, config: &Config, sub_m: &ArgMatch
                 4 => {
                     println!(
                         "Current backup parent: {}",
-                        hupa.get_backup_parent().display()
+                        hupa.get_backup_parent_template().display()
                     );
                     hupa.set_backup_parent(read_line("New backup parent: ", true))
                         .expect("Cannot reset backup parent");
                 }
                 5 => {
-                    println!("Current origin path: {}", hupa.get_origin().display());
-                    hupa.set_origin_path(read_line("New origin path: ", true));
+                    for origin in hupa.get_origin_templates() {
+                        println!("Current origin path: {}", origin.display());
+                    }
+                    hupa.set_origins(read_origins());
                 }
                 6 => {
                     let print = if hupa.is_autobackup_enabled() {
@@ -74,16 +79,26 @@ pub fn modify_subcommand(mut hupas: Vec<Hupa>, config: &Config, sub_m: &ArgMatch
                 7 => {
                     println!(
                         "Current needed vars: {}",
-                        hupa.get_needed_vars()
-                            .iter()
-                            .map(|s| format!("{} ", s))
-                            .collect::<String>()
+                        requirements_str(hupa.get_needed_vars())
                     );
-                    let needed_vars = read_line("New needed vars: ", false)
-                        .split_whitespace()
-                        .map(|s| s.to_string())
-                        .collect();
-                    hupa.set_needed_vars(needed_vars);
+                    hupa.set_needed_vars(read_needed_vars("New needed vars: "));
+                }
+                8 => {
+                    print_rules(hupa.get_rules());
+                    hupa.set_rules(read_rules());
+                }
+                9 => {
+                    println!("Current compression: {}", hupa.get_compression());
+                    hupa.set_compression(read_compression());
+                }
+                10 => {
+                    let print = if hupa.is_encrypted() {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    };
+                    println!("Current encryption state: {}", print);
+                    hupa.set_encrypted(read_line_bool("Enable encryption? [y/n]: "));
                 }
                 _ => {}
             }
@@ -6,22 +6,79 @@ use libhupa::*;
 use std::process::Command;
 
 /// Restore subcommand
-pub fn restore_subcommand(hupas: Vec<Hupa>, vars: &VarsHandler, sub_m: &ArgMatches) {
+pub fn restore_subcommand(
+    all_hupas: Vec<Hupa>,
+    vars: &VarsHandler,
+    config: &Config,
+    sub_m: &ArgMatches,
+) {
     let hupas = if sub_m.is_present("all") {
-        hupas
+        all_hupas.clone()
     } else if let Some(hupas_names) = sub_m.values_of("hupa") {
         let hupas_names: Vec<String> = hupas_names.map(|s| s.to_string()).collect();
-        resolve_names(&hupas_names, &hupas)
+        resolve_names(&hupas_names, &all_hupas)
     } else {
-        select_hupas(&hupas, "Select hupas to restore")
+        select_hupas(&all_hupas, "Select hupas to restore")
     };
-    #[cfg(not(unix))] restore(&hupas, vars);
-    #[cfg(unix)] restore(&hupas, vars, sub_m.is_present("ignore_root"));
+    let target = if let Some(at) = sub_m.value_of("at") {
+        GenerationTarget::At(at.parse().expect("Invalid timestamp"))
+    } else if let Some(n) = sub_m.value_of("generation") {
+        GenerationTarget::Nth(n.parse().expect("Invalid generation"))
+    } else {
+        GenerationTarget::Latest
+    };
+    if sub_m.is_present("dry_run") {
+        for hupa in &hupas {
+            print_plan(
+                hupa,
+                |h| h.plan_restore_at(vars, target),
+                &hupa.get_origin(),
+                "Restoring",
+            );
+        }
+        return;
+    }
+    #[cfg(not(unix))] restore(&hupas, vars, target);
+    #[cfg(unix)] restore(&hupas, vars, target, sub_m.is_present("ignore_root"));
+    match prune_rollbacks(&all_hupas, config.rollback_max_size) {
+        Ok(pruned) => {
+            for hupa in pruned {
+                println!(
+                    "Rollback of {} deleted to fit in rollback max size",
+                    hupa.get_name().yellow()
+                );
+            }
+        }
+        Err(e) => println!("{} {}", "Can't prune rollbacks:".red(), e),
+    }
+}
+
+/// Undo restore subcommand
+pub fn undo_restore_subcommand(hupas: &[Hupa], sub_m: &ArgMatches) {
+    let hupas = if let Some(hupas_names) = sub_m.values_of("hupa") {
+        let hupas_names: Vec<String> = hupas_names.map(|s| s.to_string()).collect();
+        resolve_names(&hupas_names, hupas)
+    } else {
+        let hupas = hupas
+            .iter()
+            .filter(|h| h.has_rollback())
+            .cloned()
+            .collect::<Vec<Hupa>>();
+        select_hupas(&hupas, "Select hupas to undo")
+    };
+    for hupa in &hupas {
+        exec_hupa(
+            hupa,
+            |h| h.undo_restore(),
+            &PrintOrder::RollbackToOrigin,
+            "Undoing restore of",
+        );
+    }
 }
 
 /// Restore hupas with interface
 #[cfg(unix)]
-pub fn restore(hupas: &[Hupa], vars: &VarsHandler, ignore_root: bool) {
+pub fn restore(hupas: &[Hupa], vars: &VarsHandler, target: GenerationTarget, ignore_root: bool) {
     // Needs root check
     for hupa in hupas {
         if hupa.needs_root() && !ignore_root {
@@ -40,6 +97,17 @@ pub fn restore(hupas: &[Hupa], vars: &VarsHandler, ignore_root: bool) {
                         .display()
                         .to_string(),
                 );
+                match target {
+                    GenerationTarget::Latest => {}
+                    GenerationTarget::At(t) => {
+                        args.push("--at".to_string());
+                        args.push(t.to_string());
+                    }
+                    GenerationTarget::Nth(n) => {
+                        args.push("--generation".to_string());
+                        args.push(n.to_string());
+                    }
+                }
                 for hupa in hupas {
                     args.push(format!("{}/{}", hupa.get_category_str(), hupa.get_name()));
                 }
@@ -64,7 +132,7 @@ pub fn restore(hupas: &[Hupa], vars: &VarsHandler, ignore_root: bool) {
         }
         exec_hupa(
             hupa,
-            |h| h.restore(vars),
+            |h| h.restore_at(vars, target),
             &PrintOrder::BackupToOrigin,
             "Restoring",
         );
@@ -73,11 +141,11 @@ pub fn restore(hupas: &[Hupa], vars: &VarsHandler, ignore_root: bool) {
 
 /// Restore hupas with interface
 #[cfg(not(unix))]
-pub fn restore(hupas: &[Hupa], vars: &VarsHandler) {
+pub fn restore(hupas: &[Hupa], vars: &VarsHandler, target: GenerationTarget) {
     for hupa in hupas {
         exec_hupa(
             hupa,
-            |h| h.restore(vars),
+            |h| h.restore_at(vars, target),
             &PrintOrder::BackupToOrigin,
             "Restoring",
         );
@@ -1,7 +1,7 @@
 use DEFAULT_FSO;
 use clap::ArgMatches;
 use colored::*;
-use common::resolve_names;
+use common::{print_rules, requirements_str, resolve_names};
 use humansize::*;
 use libhupa::*;
 
@@ -62,9 +62,31 @@ fn print_category(category: &Category, size_enabled: bool) {
 /// Print hupa
 fn print_hupa(hupa: &Hupa, size: &str, size_enabled: bool, base: &str) {
     println!("{} {}:", base, hupa.get_name().yellow().bold());
-    println!("  {} origin: {}", base, hupa.get_origin().display());
+    for (template, origin) in hupa.get_origin_templates().iter().zip(hupa.get_origins()) {
+        if is_template(template) {
+            println!("  {} origin: {} ({})", base, template.display(), origin.display());
+        } else {
+            println!("  {} origin: {}", base, origin.display());
+        }
+    }
     if size_enabled {
-        println!("  {} backup size: {}", base, size);
+        if hupa.get_compression().is_compressed() {
+            let logical = hupa.get_backup_logical_size().unwrap_or(0);
+            println!(
+                "  {} backup size: {} ({} uncompressed)",
+                base,
+                size,
+                logical.file_size(DEFAULT_FSO).unwrap_or_default()
+            );
+        } else {
+            println!("  {} backup size: {}", base, size);
+        }
+    }
+    if hupa.get_compression().is_compressed() {
+        println!("  {} compression: {}", base, hupa.get_compression());
+    }
+    if hupa.is_encrypted() {
+        println!("  {} encryption is {}", base, "enabled".green());
     }
     let autobackup = if hupa.is_autobackup_enabled() {
         format!("{}", "enabled".green())
@@ -72,16 +94,29 @@ fn print_hupa(hupa: &Hupa, size: &str, size_enabled: bool, base: &str) {
         format!("{}", "disabled".red())
     };
     println!("  {} autobackup is {}", base, autobackup);
+    let generations = hupa.get_generations().unwrap_or_default();
+    match generations.last() {
+        Some(last) => {
+            println!(
+                "  {} generations: {} (newest: {})",
+                base,
+                generations.len(),
+                last
+            )
+        }
+        None => println!("  {} generations: 0", base),
+    }
     println!("  {} description: {}", base, hupa.get_desc());
+    if !hupa.get_rules().is_empty() {
+        print!("  {} ", base);
+        print_rules(hupa.get_rules());
+    }
     let needed_vars = hupa.get_needed_vars();
     if needed_vars.len() > 0 {
         println!(
             "  {} needed vars: {}",
             base,
-            needed_vars
-                .iter()
-                .map(|s| format!("{} ", s))
-                .collect::<String>()
+            requirements_str(needed_vars)
         );
     }
 }
@@ -0,0 +1,33 @@
+use clap::ArgMatches;
+use colored::*;
+use io::*;
+use libhupa::*;
+use std::fs::File;
+
+/// Unpack subcommand
+pub fn unpack_subcommand(mut hupas: Vec<Hupa>, config: &Config, sub_m: &ArgMatches) {
+    let archive = sub_m.value_of("archive").unwrap();
+    let f = File::open(archive).unwrap_or_else(|_| panic!("Can't open {}", archive));
+    let backup_parent =
+        Hupa::get_default_backup_parent().expect("Can't get default backup parent");
+    let result = match unpack_archive(f, backup_parent, &hupas) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("{} {}", "Error:".red(), e);
+            return;
+        }
+    };
+    for hupa in &result.conflicts {
+        println!(
+            "{} {}/{} already exists, its backup was not unpacked",
+            "Conflict:".red(),
+            hupa.get_category_str(),
+            hupa.get_name().yellow()
+        );
+    }
+    for hupa in result.added {
+        println!("{} is now added.", hupa.get_name().yellow());
+        hupas.push(hupa);
+    }
+    save_hupas(config, &hupas);
+}
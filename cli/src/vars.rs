@@ -28,25 +28,26 @@ pub fn vars_subcommand(mut vars: VarsHandler, config: &Config, sub_m: &ArgMatche
 /// Vars add subcommand
 pub fn vars_add_subcommand(vars: &mut VarsHandler) {
     let name = ::io::read_line("Var name: ", true);
-    let value = ::io::read_line_bool("Value (true/false): ");
-    vars.add_var((name, value));
+    let value = ::io::read_line("Value (true/false, a number or a string): ", true);
+    let description = ::io::read_line("Description: ", false);
+    let host = ::io::read_line("Only on host (empty for every host): ", false);
+    let host = if host.is_empty() { None } else { Some(host) };
+    let index = vars.add_host_var((name, Value::parse(value)), host);
+    vars.set_description(index, description);
 }
 
 /// Vars remove subcommand
 pub fn vars_remove_subcommand(vars: &mut VarsHandler) {
-    let idxs = select_vars(vars);
-    let mut vars_to_remove = Vec::new();
+    let mut idxs = select_vars(vars);
     if idxs.contains(&(vars.len() + 1)) {
         println!("Action cancelled");
         return;
     }
-    for i in idxs {
-        let &(ref name, _) = &vars[i - 1];
-        vars_to_remove.push(name.clone());
-    }
-    for name in vars_to_remove {
+    idxs.sort();
+    idxs.dedup();
+    for i in idxs.into_iter().rev() {
+        let (name, _) = vars.remove_at(i - 1);
         println!("Var {} removed", name);
-        vars.remove_var(name);
     }
 }
 
@@ -60,23 +61,45 @@ pub fn vars_modify_subcommand(vars: &mut VarsHandler) {
     for i in idxs {
         let var = &mut vars[i - 1];
         println!("{} = {}", var.0, var.1);
-        let new_val = ::io::read_line_bool("New value: ");
-        var.1 = new_val;
+        let new_val = ::io::read_line("New value: ", true);
+        var.1 = Value::parse(new_val);
     }
 }
 
 /// Vars list subcommand
 pub fn vars_list_subcommand(vars: &VarsHandler) {
-    for var in vars {
-        println!("{} = {}", var.0, var.1);
+    for i in 0..vars.len() {
+        print!("{}", var_str(vars, i));
+        if let Some(d) = vars.get_description(i) {
+            print!(" ({})", d);
+        }
+        println!();
     }
+    println!("Built-in:");
+    for var in vars.get_builtins() {
+        if vars.is_present(&var.0) {
+            println!("{} = {} (overridden)", var.0, var.1);
+        } else {
+            println!("{} = {}", var.0, var.1);
+        }
+    }
+    println!("bin.<name> = true if <name> is in PATH");
 }
 
 /// List vars and ask for choice
 fn select_vars(vars: &VarsHandler) -> Vec<usize> {
     for i in 0..vars.len() {
-        println!("[{}] {}", i + 1, vars[i].0);
+        println!("[{}] {}", i + 1, var_str(vars, i));
     }
     println!("[{}] Cancel", vars.len() + 1);
     ::io::read_line_usize("Var(s) to remove: ", false, vars.len())
 }
+
+/// Format var at `index` with its host
+fn var_str(vars: &VarsHandler, index: usize) -> String {
+    let (ref name, ref value) = vars[index];
+    match vars.get_host(index) {
+        Some(h) => format!("{} = {} [host:{}]", name, value, h),
+        None => format!("{} = {}", name, value),
+    }
+}
@@ -0,0 +1,78 @@
+use clap::ArgMatches;
+use colored::*;
+use common::*;
+use libhupa::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Verify subcommand
+///
+/// Exit with a nonzero code if a backup is damaged or can't be verified.
+pub fn verify_subcommand(hupas: &[Hupa], sub_m: &ArgMatches) {
+    let hupas = if let Some(hupas_names) = sub_m.values_of("hupa") {
+        let hupas_names: Vec<String> = hupas_names.map(|s| s.to_string()).collect();
+        resolve_names(&hupas_names, hupas)
+    } else {
+        hupas.to_vec()
+    };
+    let target = match sub_m.value_of("generation") {
+        Some(n) => GenerationTarget::Nth(n.parse().expect("Invalid generation")),
+        None => GenerationTarget::Latest,
+    };
+    let ok = if sub_m.is_present("sha256sum") {
+        sha256sum(&hupas, target)
+    } else {
+        verify(&hupas, target)
+    };
+    if !ok {
+        process::exit(1);
+    }
+}
+
+/// Verify hupas with interface, return false if a backup is damaged
+pub fn verify(hupas: &[Hupa], target: GenerationTarget) -> bool {
+    let mut ok = true;
+    let mut stdout = ::std::io::stdout();
+    for hupa in hupas {
+        writef!(stdout, "Verifying {}... ", hupa.get_name().yellow());
+        let report = match hupa.verify_at(target) {
+            Ok(r) => r,
+            Err(e) => {
+                println!("{} {}", "Error:".red(), e);
+                ok = false;
+                continue;
+            }
+        };
+        if report.is_ok() {
+            println!("{}", "OK!".green());
+            continue;
+        }
+        ok = false;
+        println!("{}", "Damaged!".red());
+        let generation_dir = hupa.generation_dir(report.generation);
+        print_paths(&generation_dir, &report.missing, "missing".red());
+        print_paths(&generation_dir, &report.corrupted, "corrupted".red());
+        print_paths(&generation_dir, &report.extra, "extra".yellow());
+    }
+    ok
+}
+
+/// Print a `sha256sum` compatible listing, return false if one can't be made
+pub fn sha256sum(hupas: &[Hupa], target: GenerationTarget) -> bool {
+    let mut ok = true;
+    let stdout = ::std::io::stdout();
+    for hupa in hupas {
+        if let Err(e) = hupa.write_sha256sum(&mut stdout.lock(), target) {
+            eprintln!("{}: {} {}", hupa.get_name(), "Error:".red(), e);
+            ok = false;
+        }
+    }
+    ok
+}
+
+fn print_paths(root: &Path, paths: &[PathBuf], name: ColoredString) {
+    for path in paths {
+        println!("    {:<9} {}", name, root.join(path).display());
+    }
+}
@@ -30,10 +30,13 @@ fn main() {
         None => Config::read_config(),
     }.unwrap_or(config_default);
     let mut vars = if let Ok(mut s) = File::open(&config.vars_path) {
-        VarsHandler::read_from_stream(&mut s).unwrap_or(VarsHandler::new(Vec::new()))
+        VarsHandler::read_from_stream(&mut s).unwrap_or_else(|e| {
+            eprintln!("Error in vars: {}", e);
+            VarsHandler::new(Vec::new())
+        })
     } else {
         VarsHandler::new(Vec::new())
-    };
+    }.with_builtins();
     if let Some(b) = matches.value_of("interval") {
         if let Ok(i) = b.parse() {
             config.autobackup_interval = i;
@@ -43,10 +46,21 @@ fn main() {
         config.metadata_path = PathBuf::from(p);
     }
     let config = config;
-    let mut hupas = match read_metadata_from_config(&config) {
-        Ok(h) => h,
-        Err(_) => Vec::new(),
+    let mut hupas = match read_metadata_from_config_lenient(&config) {
+        Ok(m) => {
+            for e in &m.skipped {
+                eprintln!("Skipped: {}", e);
+            }
+            m.hupas
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Vec::new()
+        }
     };
+    for hupa in &mut hupas {
+        hupa.set_template_vars(vars.clone());
+    }
 
     let daemonize = Daemonize::new();
     let path = app_dirs::app_root(app_dirs::AppDataType::UserCache, &APP_INFO)
@@ -64,9 +78,22 @@ fn main() {
                 // Check change metadata
                 if last_change_met != change_met {
                     let _ = write!(file, "[{}] Found new change in metadata...", get_time_str());
-                    hupas = match read_metadata_from_config(&config) {
-                        Ok(h) => h,
-                        Err(_) => hupas,
+                    hupas = match read_metadata_from_config_lenient(&config) {
+                        Ok(m) => {
+                            for e in &m.skipped {
+                                let _ = writeln!(
+                                    file,
+                                    "[{}] Skipped: {}",
+                                    get_time_str(),
+                                    e
+                                );
+                            }
+                            m.hupas
+                        }
+                        Err(e) => {
+                            let _ = writeln!(file, "[{}] Error in metadata: {}", get_time_str(), e);
+                            hupas
+                        }
                     };
                     last_change_met = change_met;
                 }
@@ -75,16 +102,18 @@ fn main() {
                 if last_change_vars != change_vars {
                     let _ = write!(file, "[{}] Found new change in vars...", get_time_str());
                     vars = if let Ok(mut s) = File::open(&config.vars_path) {
-                        VarsHandler::read_from_stream(&mut s).unwrap_or(
-                            VarsHandler::new(
-                                Vec::new(),
-                            ),
-                        )
+                        VarsHandler::read_from_stream(&mut s).unwrap_or_else(|e| {
+                            let _ = writeln!(file, "[{}] Error in vars: {}", get_time_str(), e);
+                            VarsHandler::new(Vec::new())
+                        })
                     } else {
                         VarsHandler::new(Vec::new())
-                    };
+                    }.with_builtins();
                     last_change_vars = change_vars;
                 }
+                for hupa in &mut hupas {
+                    hupa.set_template_vars(vars.clone());
+                }
                 for hupa in &hupas {
                     if !hupa.is_autobackup_enabled() {
                         continue;
@@ -92,12 +121,15 @@ fn main() {
                     match hupa.backup(&vars) {
                         Ok(opres) => {
                             let _ = match opres {
-                                OperationResult::Change => {
+                                OperationResult::Change(stats) => {
                                     write!(
                                         file,
-                                        "[{}] {} is backed up\n",
+                                        "[{}] {} is backed up ({} added, {} updated, {} removed)\n",
                                         get_time_str(),
-                                        hupa.get_name()
+                                        hupa.get_name(),
+                                        stats.added,
+                                        stats.updated,
+                                        stats.removed
                                     )
                                 }
                                 OperationResult::NoChange => {
@@ -0,0 +1,423 @@
+//! Archive module is used to pack all hupas in one file.
+//!
+//! An archive contains the metadata, the vars and the backup of every hupa,
+//! laid out by category.
+
+mod tar;
+mod zip;
+
+use error::*;
+use hupa::Hupa;
+use metadata::{read_metadata, write_metadata};
+use std::fmt;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use vars::VarsHandler;
+
+/// Name of the metadata file inside an archive
+pub const ARCHIVE_METADATA: &str = "metadata.json";
+/// Name of the vars file inside an archive
+pub const ARCHIVE_VARS: &str = "vars";
+/// Directory containing hupas' backups inside an archive
+pub const ARCHIVE_HUPAS: &str = "hupas";
+
+/// Offset of the `ustar` magic in a tar header
+const TAR_MAGIC_OFFSET: u64 = 257;
+
+/// Format of an archive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+    /// Tar archive
+    Tar,
+    /// Zip archive
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Get the file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+
+    /// Detect the format of an archive from its magic bytes
+    ///
+    /// The stream is rewound to its start after detection.
+    pub fn detect<R: Read + Seek>(stream: &mut R) -> Result<ArchiveFormat> {
+        let mut magic = Vec::new();
+        stream.seek(SeekFrom::Start(0))?;
+        stream.by_ref().take(TAR_MAGIC_OFFSET + 5).read_to_end(&mut magic)?;
+        stream.seek(SeekFrom::Start(0))?;
+        if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+            Ok(ArchiveFormat::Zip)
+        } else if magic.len() as u64 == TAR_MAGIC_OFFSET + 5 &&
+                   &magic[TAR_MAGIC_OFFSET as usize..] == b"ustar"
+        {
+            Ok(ArchiveFormat::Tar)
+        } else {
+            bail!(ErrorKind::InvalidArchive)
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ArchiveFormat> {
+        match s {
+            "tar" => Ok(ArchiveFormat::Tar),
+            "zip" => Ok(ArchiveFormat::Zip),
+            _ => bail!(ErrorKind::InvalidArchiveFormat(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// Generate an archive of all hupas
+///
+/// `stream` - Stream to write the archive
+///
+/// `format` - Format of the archive
+///
+/// `hupas` - Hupas to put in the archive
+///
+/// `vars_handler` - Vars to embed in the archive
+pub fn generate_archive<W: Write + Seek>(
+    stream: W,
+    format: ArchiveFormat,
+    hupas: &[Hupa],
+    vars_handler: &VarsHandler,
+) -> Result<()> {
+    let mut metadata = Vec::new();
+    write_metadata(&mut metadata, hupas)?;
+    let mut vars = Vec::new();
+    vars_handler.write_to_stream(&mut vars)?;
+    match format {
+        ArchiveFormat::Tar => tar::generate(stream, hupas, &metadata, &vars),
+        ArchiveFormat::Zip => zip::generate(stream, hupas, &metadata, &vars),
+    }
+}
+
+/// Unpack an archive generated by `generate_archive`
+///
+/// The format of the archive is detected from its magic bytes.
+///
+/// `stream` - Stream to read the archive
+///
+/// `backup_parent` - Local backup parent where hupas' files are placed
+///
+/// `hupas` - Already known hupas, hupas colliding with them are not unpacked
+pub fn unpack_archive<R: Read + Seek, P: AsRef<Path>>(
+    mut stream: R,
+    backup_parent: P,
+    hupas: &[Hupa],
+) -> Result<UnpackResult> {
+    let format = ArchiveFormat::detect(&mut stream)?;
+    let mut unpacker = Unpacker::new(backup_parent.as_ref(), hupas);
+    match format {
+        ArchiveFormat::Tar => tar::unpack(stream, &mut unpacker)?,
+        ArchiveFormat::Zip => zip::unpack(stream, &mut unpacker)?,
+    }
+    unpacker.finish()
+}
+
+/// Result of an unpacking
+#[derive(Debug)]
+pub struct UnpackResult {
+    /// Hupas unpacked from the archive, relocated to the local backup parent
+    pub added: Vec<Hupa>,
+    /// Hupas of the archive with the same name and category as a known hupa
+    pub conflicts: Vec<Hupa>,
+}
+
+/// Shared state used by archive readers to place entries
+struct Unpacker<'a> {
+    backup_parent: &'a Path,
+    known: &'a [Hupa],
+    result: Option<UnpackResult>,
+}
+
+impl<'a> Unpacker<'a> {
+    fn new(backup_parent: &'a Path, known: &'a [Hupa]) -> Unpacker<'a> {
+        Unpacker {
+            backup_parent,
+            known,
+            result: None,
+        }
+    }
+
+    /// Read the embedded metadata and sort hupas between added and conflicts
+    fn read_metadata<R: Read>(&mut self, stream: &mut R) -> Result<()> {
+        let mut result = UnpackResult {
+            added: Vec::new(),
+            conflicts: Vec::new(),
+        };
+        for mut hupa in read_metadata(stream)? {
+            if self.known.contains(&hupa) {
+                result.conflicts.push(hupa);
+            } else {
+                hupa.relocate(self.backup_parent);
+                result.added.push(hupa);
+            }
+        }
+        self.result = Some(result);
+        Ok(())
+    }
+
+    /// Get the local destination of an archive entry
+    ///
+    /// Return `None` when the entry must be skipped
+    fn destination<P: AsRef<Path>>(&self, path: P) -> Result<Option<PathBuf>> {
+        let path = path.as_ref();
+        let result = match self.result {
+            Some(ref r) => r,
+            None => bail!(ErrorKind::InvalidArchive),
+        };
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            bail!(ErrorKind::InvalidArchive);
+        }
+        for hupa in &result.added {
+            if path.starts_with(archive_path(hupa)) {
+                let relative = path.strip_prefix(ARCHIVE_HUPAS).unwrap();
+                return self.check_links(relative).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the local destination of an entry, without writing through symlinks
+    ///
+    /// A symlink unpacked earlier may point out of the backup parent, so an
+    /// entry under a symlink fails with `InvalidArchive`, and a symlink at the
+    /// place of the entry is removed.
+    fn check_links(&self, relative: &Path) -> Result<PathBuf> {
+        let dest = self.backup_parent.join(relative);
+        let mut path = self.backup_parent.to_path_buf();
+        for component in relative.components() {
+            path.push(component);
+            match path.symlink_metadata() {
+                Ok(ref m) if m.file_type().is_symlink() => {
+                    if path != dest {
+                        bail!(ErrorKind::InvalidArchive);
+                    }
+                    fs::remove_file(&path)?;
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        Ok(dest)
+    }
+
+    fn finish(self) -> Result<UnpackResult> {
+        match self.result {
+            Some(r) => Ok(r),
+            None => bail!(ErrorKind::InvalidArchive),
+        }
+    }
+}
+
+/// Path of the hupa's backup inside an archive
+pub fn archive_path(hupa: &Hupa) -> PathBuf {
+    let mut path = PathBuf::from(ARCHIVE_HUPAS);
+    for sub_category in hupa.get_category() {
+        path.push(sub_category);
+    }
+    path.push(hupa.get_name());
+    path
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::Path;
+    use test_utils::test_dir;
+
+    #[test]
+    fn archive_format_from_str() {
+        assert_eq!("tar".parse::<ArchiveFormat>().unwrap(), ArchiveFormat::Tar);
+        assert_eq!("zip".parse::<ArchiveFormat>().unwrap(), ArchiveFormat::Zip);
+        assert!("rar".parse::<ArchiveFormat>().is_err());
+    }
+
+    #[test]
+    fn archive_path_test() {
+        let hupa = Hupa::new(
+            "portage",
+            "",
+            vec!["os".to_string(), "gentoo".to_string()],
+            "/",
+            "/",
+            false,
+            Vec::new(),
+        );
+        assert_eq!(archive_path(&hupa), Path::new("hupas/os/gentoo/portage"));
+    }
+
+    #[test]
+    fn generate_tar_archive() {
+        let tmp = test_dir("generate-tar");
+        let parent = tmp.path();
+        let hupa = Hupa::new(
+            "vim",
+            "",
+            vec!["dotfiles".to_string()],
+            parent,
+            "/",
+            false,
+            Vec::new(),
+        );
+        ::std::fs::create_dir_all(hupa.backup_dir()).unwrap();
+        ::std::fs::write(hupa.backup_dir().join("vimrc"), "set nu").unwrap();
+        let mut buf = Cursor::new(Vec::new());
+        generate_archive(
+            &mut buf,
+            ArchiveFormat::Tar,
+            &[hupa],
+            &VarsHandler::new(Vec::new()),
+        ).unwrap();
+        let mut archive = ::tar::Archive::new(&buf.get_ref()[..]);
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<String>>();
+        assert!(paths.contains(&ARCHIVE_METADATA.to_string()));
+        assert!(paths.contains(&ARCHIVE_VARS.to_string()));
+        assert!(paths.contains(&"hupas/dotfiles/vim/vimrc".to_string()));
+    }
+
+    fn unpack_roundtrip(format: ArchiveFormat) {
+        let tmp = test_dir(&format!("unpack-{}", format));
+        let parent = tmp.path();
+        let hupas = vec!["vim", "zsh"]
+            .into_iter()
+            .map(|n| {
+                Hupa::new(
+                    n,
+                    "",
+                    vec!["dotfiles".to_string()],
+                    parent.join("old"),
+                    "/",
+                    false,
+                    Vec::new(),
+                )
+            })
+            .collect::<Vec<Hupa>>();
+        for hupa in &hupas {
+            ::std::fs::create_dir_all(hupa.backup_dir().join("sub")).unwrap();
+            ::std::fs::write(hupa.backup_dir().join("sub/rc"), hupa.get_name()).unwrap();
+        }
+        let mut buf = Cursor::new(Vec::new());
+        generate_archive(&mut buf, format, &hupas, &VarsHandler::new(Vec::new())).unwrap();
+        assert_eq!(ArchiveFormat::detect(&mut buf).unwrap(), format);
+        let new_parent = parent.join("new");
+        let result = unpack_archive(buf, &new_parent, &hupas[1..]).unwrap();
+        assert_eq!(result.added, vec![hupas[0].clone()]);
+        assert_eq!(result.conflicts, vec![hupas[1].clone()]);
+        assert_eq!(result.added[0].get_backup_parent(), new_parent);
+        assert_eq!(
+            ::std::fs::read_to_string(new_parent.join("dotfiles/vim/sub/rc")).unwrap(),
+            "vim"
+        );
+        assert!(!new_parent.join("dotfiles/zsh").exists());
+    }
+
+    #[test]
+    fn unpack_tar_archive() {
+        unpack_roundtrip(ArchiveFormat::Tar);
+    }
+
+    #[test]
+    fn unpack_zip_archive() {
+        unpack_roundtrip(ArchiveFormat::Zip);
+    }
+
+    /// Tar archive of the hupa `vim` with `entries`, as (path, link target, content)
+    fn malicious_tar(entries: &[(&str, Option<&str>, &str)], hard_link: bool) -> Vec<u8> {
+        use ::tar::{Builder, EntryType, Header};
+        let hupa = Hupa::new("vim", "", vec!["dotfiles".to_string()], "/", "/", false, Vec::new());
+        let mut metadata = Vec::new();
+        write_metadata(&mut metadata, &[hupa]).unwrap();
+        let mut builder = Builder::new(Vec::new());
+        let mut append = |path: &str, link: Option<&str>, data: &[u8]| {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            if let Some(target) = link {
+                let kind = if hard_link { EntryType::Link } else { EntryType::Symlink };
+                header.set_entry_type(kind);
+                header.set_link_name(target).unwrap();
+            }
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        };
+        append(ARCHIVE_METADATA, None, &metadata);
+        for &(path, link, content) in entries {
+            append(path, link, content.as_bytes());
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unpack_malicious_tar() {
+        let tmp = test_dir("unpack-malicious");
+        let parent = tmp.path();
+        let outside = parent.join("outside");
+        ::std::fs::create_dir_all(&outside).unwrap();
+        ::std::fs::write(outside.join("rc"), "safe").unwrap();
+        let backup_parent = parent.join("backup");
+        let target = outside.display().to_string();
+        // A file under a symlink pointing out of the backup parent
+        let archive = malicious_tar(
+            &[
+                ("hupas/dotfiles/vim/link", Some(&target), ""),
+                ("hupas/dotfiles/vim/link/evil", None, "evil"),
+            ],
+            false,
+        );
+        match unpack_archive(Cursor::new(archive), &backup_parent, &[]) {
+            Err(Error(ErrorKind::InvalidArchive, _)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(!outside.join("evil").exists());
+        // A file with the name of a symlink replaces it
+        let _ = ::std::fs::remove_dir_all(&backup_parent);
+        let target = outside.join("rc").display().to_string();
+        let archive = malicious_tar(
+            &[
+                ("hupas/dotfiles/vim/rc", Some(&target), ""),
+                ("hupas/dotfiles/vim/rc", None, "evil"),
+            ],
+            false,
+        );
+        unpack_archive(Cursor::new(archive), &backup_parent, &[]).unwrap();
+        assert_eq!(::std::fs::read_to_string(outside.join("rc")).unwrap(), "safe");
+        assert_eq!(
+            ::std::fs::read_to_string(backup_parent.join("dotfiles/vim/rc")).unwrap(),
+            "evil"
+        );
+        // Hard links are refused
+        let _ = ::std::fs::remove_dir_all(&backup_parent);
+        let archive = malicious_tar(&[("hupas/dotfiles/vim/rc", Some(&target), "")], true);
+        assert!(unpack_archive(Cursor::new(archive), &backup_parent, &[]).is_err());
+        assert_eq!(::std::fs::read_to_string(outside.join("rc")).unwrap(), "safe");
+    }
+
+    #[test]
+    fn detect_invalid_archive() {
+        let mut cursor = Cursor::new(b"not an archive".to_vec());
+        assert!(ArchiveFormat::detect(&mut cursor).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! Module to write and read hupas in a tar archive
+
+use super::*;
+use ::tar::{Archive, Builder, Header};
+use error::*;
+use hupa::Hupa;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Generate a tar archive
+pub fn generate<W: Write>(stream: W, hupas: &[Hupa], metadata: &[u8], vars: &[u8]) -> Result<()> {
+    let mut builder = Builder::new(stream);
+    builder.follow_symlinks(false);
+    append_file(&mut builder, ARCHIVE_METADATA, metadata)?;
+    append_file(&mut builder, ARCHIVE_VARS, vars)?;
+    for hupa in hupas {
+        let backup_dir = hupa.backup_dir();
+        if !backup_dir.exists() {
+            continue;
+        }
+        let path = archive_path(hupa);
+        if backup_dir.is_dir() {
+            builder.append_dir_all(&path, &backup_dir)?;
+        } else {
+            builder.append_path_with_name(&backup_dir, &path)?;
+        }
+    }
+    builder.into_inner()?.flush()?;
+    Ok(())
+}
+
+/// Append a file from memory
+fn append_file<W: Write>(builder: &mut Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now());
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// Current time in seconds
+fn now() -> u64 {
+    ::std::time::SystemTime::now()
+        .duration_since(::std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Unpack a tar archive
+pub fn unpack<R: Read>(stream: R, unpacker: &mut Unpacker) -> Result<()> {
+    let mut archive = Archive::new(stream);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        if path == Path::new(ARCHIVE_METADATA) {
+            unpacker.read_metadata(&mut entry)?;
+        } else if path.starts_with(ARCHIVE_HUPAS) {
+            // Archives made by hupa have no hard link, their target is unchecked
+            if entry.header().entry_type().is_hard_link() {
+                bail!(ErrorKind::InvalidArchive);
+            }
+            if let Some(dest) = unpacker.destination(&path)? {
+                if let Some(p) = dest.parent() {
+                    fs::create_dir_all(p)?;
+                }
+                entry.unpack(&dest)?;
+            }
+        }
+    }
+    Ok(())
+}
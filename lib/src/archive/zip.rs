@@ -0,0 +1,148 @@
+//! Module to write and read hupas in a zip archive
+
+use super::*;
+use ::zip::{CompressionMethod, ZipArchive, ZipWriter};
+use ::zip::write::FileOptions;
+use error::*;
+use hupa::Hupa;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+/// Unix file type mask
+const S_IFMT: u32 = 0o170000;
+/// Unix symlink file type
+const S_IFLNK: u32 = 0o120000;
+
+/// Generate a zip archive
+pub fn generate<W: Write + Seek>(
+    stream: W,
+    hupas: &[Hupa],
+    metadata: &[u8],
+    vars: &[u8],
+) -> Result<()> {
+    let mut writer = ZipWriter::new(stream);
+    writer.start_file(ARCHIVE_METADATA, options(0o644))?;
+    writer.write_all(metadata)?;
+    writer.start_file(ARCHIVE_VARS, options(0o644))?;
+    writer.write_all(vars)?;
+    for hupa in hupas {
+        let backup_dir = hupa.backup_dir();
+        if backup_dir.symlink_metadata().is_err() {
+            continue;
+        }
+        append_path(&mut writer, &backup_dir, &archive_path(hupa))?;
+    }
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+/// Append a file or a directory recursively
+fn append_path<W: Write + Seek>(writer: &mut ZipWriter<W>, path: &Path, name: &Path) -> Result<()> {
+    let metadata = path.symlink_metadata()?;
+    let name_str = zip_name(name);
+    let mode = file_mode(&metadata);
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+        writer.add_symlink(name_str, target.display().to_string(), options(mode))?;
+    } else if metadata.is_dir() {
+        writer.add_directory(name_str, options(mode))?;
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            append_path(writer, &entry.path(), &name.join(entry.file_name()))?;
+        }
+    } else if metadata.is_file() {
+        writer.start_file(name_str, options(mode))?;
+        io::copy(&mut File::open(path)?, writer)?;
+    }
+    Ok(())
+}
+
+/// Unpack a zip archive
+pub fn unpack<R: Read + Seek>(stream: R, unpacker: &mut Unpacker) -> Result<()> {
+    let mut archive = ZipArchive::new(stream)?;
+    unpacker.read_metadata(&mut archive.by_name(ARCHIVE_METADATA)?)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let path = match file.enclosed_name() {
+            Some(p) => p.to_path_buf(),
+            None => bail!(ErrorKind::InvalidArchive),
+        };
+        if !path.starts_with(ARCHIVE_HUPAS) {
+            continue;
+        }
+        let dest = match unpacker.destination(&path)? {
+            Some(d) => d,
+            None => continue,
+        };
+        if file.is_dir() {
+            fs::create_dir_all(&dest)?;
+            continue;
+        }
+        if let Some(p) = dest.parent() {
+            fs::create_dir_all(p)?;
+        }
+        let mode = file.unix_mode();
+        if mode.map(|m| m & S_IFMT == S_IFLNK).unwrap_or(false) {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            symlink(target, &dest)?;
+        } else {
+            io::copy(&mut file, &mut File::create(&dest)?)?;
+            if let Some(m) = mode {
+                set_mode(&dest, m)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Zip entry options
+fn options(mode: u32) -> FileOptions {
+    FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .unix_permissions(mode & 0o777)
+}
+
+/// Zip entries always use `/` as separator
+fn zip_name(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.is_dir() { 0o755 } else { 0o644 }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: String, dest: &Path) -> Result<()> {
+    ::std::os::unix::fs::symlink(target, dest)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn symlink(target: String, dest: &Path) -> Result<()> {
+    fs::write(dest, target)?;
+    Ok(())
+}
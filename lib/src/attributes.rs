@@ -0,0 +1,237 @@
+//! File attributes kept along backups
+//!
+//! Mode, ownership, modification time and extended attributes are copied with
+//! files. As ownership can only be given by root, mode and ownership of every
+//! backed up file are also recorded in a sidecar file, so root can restore
+//! them even if the backup was made by another user.
+
+use error::*;
+use filetime::{set_symlink_file_times, FileTime};
+use std::fs::{self, File, Metadata};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Mode and ownership of a file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attributes {
+    /// Permission bits
+    pub mode: u32,
+    /// Owner id
+    pub uid: u32,
+    /// Group id
+    pub gid: u32,
+}
+
+impl Attributes {
+    /// Get attributes of a file, `None` where they are not supported
+    #[cfg(unix)]
+    pub fn of(metadata: &Metadata) -> Option<Attributes> {
+        use std::os::unix::fs::MetadataExt;
+        Some(Attributes {
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        })
+    }
+
+    /// Get attributes of a file, `None` where they are not supported
+    #[cfg(not(unix))]
+    pub fn of(_metadata: &Metadata) -> Option<Attributes> {
+        None
+    }
+}
+
+/// Write a sidecar file
+///
+/// `entries` - Attributes of files, by path relative to the backup
+pub fn write_attributes<P: AsRef<Path>>(path: P, entries: &[(PathBuf, Attributes)]) -> Result<()> {
+    let mut f = File::create(path)?;
+    for (relative, attributes) in entries {
+        let relative = relative.display().to_string();
+        // A path with a new line can't be recorded
+        if relative.contains('\n') {
+            continue;
+        }
+        writeln!(
+            f,
+            "{:o} {} {} {}",
+            attributes.mode,
+            attributes.uid,
+            attributes.gid,
+            relative
+        )?;
+    }
+    Ok(())
+}
+
+/// Read a sidecar file written by `write_attributes`
+pub fn read_attributes<P: AsRef<Path>>(path: P) -> Result<Vec<(PathBuf, Attributes)>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let mut split = line.splitn(4, ' ');
+        let mode = split.next().and_then(|m| u32::from_str_radix(m, 8).ok());
+        let uid = split.next().and_then(|u| u.parse().ok());
+        let gid = split.next().and_then(|g| g.parse().ok());
+        let relative = split.next();
+        match (mode, uid, gid, relative) {
+            (Some(mode), Some(uid), Some(gid), Some(relative)) => {
+                entries.push((PathBuf::from(relative), Attributes { mode, uid, gid }))
+            }
+            _ => bail!(ErrorKind::InvalidAttributes(line)),
+        }
+    }
+    Ok(entries)
+}
+
+/// Give recorded mode and ownership back to files under `root`
+///
+/// Missing files are ignored.
+pub fn apply_attributes(root: &Path, entries: &[(PathBuf, Attributes)]) -> Result<()> {
+    for (relative, attributes) in entries {
+        let path = if relative.as_os_str().is_empty() {
+            root.to_path_buf()
+        } else {
+            root.join(relative)
+        };
+        let metadata = match path.symlink_metadata() {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        set_owner(&path, attributes.uid, attributes.gid)?;
+        if !metadata.file_type().is_symlink() {
+            set_mode(&path, attributes.mode)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy mode, ownership, modification time and extended attributes
+///
+/// Ownership is copied when allowed, the sidecar file keeps it otherwise.
+/// Extended attributes are skipped where they are not supported. The mode is
+/// set last, as changing the owner clears setuid and setgid bits.
+pub fn copy_attributes(from: &Path, to: &Path, metadata: &Metadata) -> Result<()> {
+    let atime = FileTime::from_last_access_time(metadata);
+    let mtime = FileTime::from_last_modification_time(metadata);
+    if let Some(attributes) = Attributes::of(metadata) {
+        let _ = set_owner(to, attributes.uid, attributes.gid);
+    }
+    copy_xattrs(from, to)?;
+    // `to` has the type of `from`, and unlike other setters this one doesn't
+    // open the file, which would block on a FIFO
+    set_symlink_file_times(to, atime, mtime)?;
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(to, metadata.permissions())?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_owner(path: &Path, uid: u32, gid: u32) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| ::std::io::Error::new(::std::io::ErrorKind::InvalidInput, e))?;
+    if unsafe { ::libc::lchown(c_path.as_ptr(), uid, gid) } != 0 {
+        return Err(::std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_owner(_path: &Path, _uid: u32, _gid: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Copy extended attributes, ACLs included
+///
+/// Nothing is copied when a file system doesn't support them.
+#[cfg(unix)]
+fn copy_xattrs(from: &Path, to: &Path) -> Result<()> {
+    let is_unsupported = |e: &::std::io::Error| e.raw_os_error() == Some(::libc::ENOTSUP);
+    let names = match ::xattr::list(from) {
+        Ok(n) => n,
+        Err(ref e) if is_unsupported(e) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for name in names {
+        let value = match ::xattr::get(from, &name)? {
+            Some(v) => v,
+            // Removed since it was listed
+            None => continue,
+        };
+        match ::xattr::set(to, &name, &value) {
+            Err(ref e) if is_unsupported(e) => return Ok(()),
+            r => r?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs(_from: &Path, _to: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use test_utils::test_dir;
+
+    #[test]
+    fn attributes_roundtrip() {
+        let tmp = test_dir("attributes");
+        let dir = tmp.path();
+        fs::create_dir_all(dir).unwrap();
+        let entries = vec![
+            (PathBuf::new(), Attributes { mode: 0o755, uid: 0, gid: 0 }),
+            (
+                PathBuf::from("sub/with space"),
+                Attributes { mode: 0o600, uid: 1000, gid: 100 },
+            ),
+        ];
+        write_attributes(dir.join("attributes"), &entries).unwrap();
+        assert_eq!(read_attributes(dir.join("attributes")).unwrap(), entries);
+        fs::write(dir.join("attributes"), "bad line").unwrap();
+        assert!(read_attributes(dir.join("attributes")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_attributes_test() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = test_dir("copy-attributes");
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("from")).unwrap();
+        fs::create_dir_all(dir.join("to")).unwrap();
+        fs::set_permissions(dir.join("from"), fs::Permissions::from_mode(0o700)).unwrap();
+        let time = FileTime::from_unix_time(1000, 0);
+        set_symlink_file_times(dir.join("from"), time, time).unwrap();
+        let metadata = dir.join("from").metadata().unwrap();
+        copy_attributes(&dir.join("from"), &dir.join("to"), &metadata).unwrap();
+        let copied = dir.join("to").metadata().unwrap();
+        assert_eq!(copied.permissions().mode() & 0o7777, 0o700);
+        assert_eq!(FileTime::from_last_modification_time(&copied).unix_seconds(), 1000);
+        // Changing the owner of a file drops its setuid bit
+        fs::write(dir.join("from/setuid"), "").unwrap();
+        fs::write(dir.join("to/setuid"), "").unwrap();
+        fs::set_permissions(dir.join("from/setuid"), fs::Permissions::from_mode(0o4755)).unwrap();
+        let metadata = dir.join("from/setuid").metadata().unwrap();
+        copy_attributes(&dir.join("from/setuid"), &dir.join("to/setuid"), &metadata).unwrap();
+        let copied = dir.join("to/setuid").metadata().unwrap();
+        assert_eq!(copied.permissions().mode() & 0o7777, 0o4755);
+    }
+}
@@ -0,0 +1,116 @@
+//! Compression of backed up files
+//!
+//! Each file of a compressed backup is compressed on its own and keeps its
+//! path, so generations can still share unchanged files.
+
+use error::*;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+/// Zstandard level used to compress files
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression of the files of a backup
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    /// Files are stored as they are
+    #[default]
+    None,
+    /// Files are compressed with gzip
+    Gzip,
+    /// Files are compressed with zstandard
+    Zstd,
+}
+
+impl Compression {
+    /// Get the name of the compression
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Check if files are compressed
+    pub fn is_compressed(&self) -> bool {
+        *self != Compression::None
+    }
+
+    /// Wrap a stream compressed with this compression, reading its original
+    /// content
+    pub fn decoder<'a>(&self, stream: Box<dyn Read + 'a>) -> Result<Box<dyn Read + 'a>> {
+        Ok(match *self {
+            Compression::None => stream,
+            Compression::Gzip => Box::new(GzDecoder::new(stream)),
+            Compression::Zstd => Box::new(::zstd::Decoder::new(stream)?),
+        })
+    }
+
+    /// Compress `stream` into `out`
+    ///
+    /// Return the size of the original content, with `out`
+    pub fn encode<R: Read, W: Write>(&self, stream: &mut R, mut out: W) -> Result<(u64, W)> {
+        Ok(match *self {
+            Compression::None => (io::copy(stream, &mut out)?, out),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(out, ::flate2::Compression::default());
+                let size = io::copy(stream, &mut encoder)?;
+                (size, encoder.finish()?)
+            }
+            Compression::Zstd => {
+                let mut encoder = ::zstd::Encoder::new(out, ZSTD_LEVEL)?;
+                let size = io::copy(stream, &mut encoder)?;
+                (size, encoder.finish()?)
+            }
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Compression> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => bail!(ErrorKind::InvalidCompression(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn compression_roundtrip() {
+        let content = "compressed ".repeat(100);
+        for compression in &[Compression::Gzip, Compression::Zstd] {
+            let (size, stored) = compression
+                .encode(&mut content.as_bytes(), Vec::new())
+                .unwrap();
+            assert_eq!(size, content.len() as u64);
+            assert!(stored.len() < content.len());
+            let mut read = String::new();
+            compression
+                .decoder(Box::new(stored.as_slice()))
+                .unwrap()
+                .read_to_string(&mut read)
+                .unwrap();
+            assert_eq!(read, content);
+        }
+        assert_eq!("zstd".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert!("rar".parse::<Compression>().is_err());
+    }
+}
@@ -1,8 +1,10 @@
 //! Configuration module, read and write config file.
 
 use APP_INFO;
+use encryption::Secret;
 use error::*;
 use json::JsonValue;
+use metadata::{detect_format, extension_format, get_format, MetadataFormat};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
@@ -13,12 +15,23 @@ use vars::VarsHandler;
 pub struct Config {
     /// Path to the metadata
     pub metadata_path: PathBuf,
+    /// Format of the metadata, detected from its extension if `None`
+    pub metadata_format: Option<String>,
     /// Interval between each autobackup
     pub autobackup_interval: u64,
     /// Path to vars
     pub vars_path: PathBuf,
+    /// Maximum size in bytes of all rollback areas
+    pub rollback_max_size: u64,
+    /// Secret encrypted hupas derive their keys from
+    ///
+    /// Only a keyfile is saved, see `get_secret` for passphrases.
+    pub secret: Option<Secret>,
 }
 
+/// Default maximum size of rollback areas, 1 GB
+pub const DEFAULT_ROLLBACK_MAX_SIZE: u64 = 1_000_000_000;
+
 impl Config {
     /// Default constructor
     pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(
@@ -28,8 +41,39 @@ impl Config {
     ) -> Config {
         Config {
             metadata_path: metadata_path.as_ref().to_path_buf(),
+            metadata_format: None,
             autobackup_interval: autobackup_interval,
             vars_path: vars_path.as_ref().to_path_buf(),
+            rollback_max_size: DEFAULT_ROLLBACK_MAX_SIZE,
+            secret: None,
+        }
+    }
+
+    /// Get the secret encrypted hupas derive their keys from
+    ///
+    /// Without a secret in config, it is the passphrase of `HUPA_PASSPHRASE`.
+    pub fn get_secret(&self) -> Option<Secret> {
+        self.secret.clone().or_else(Secret::from_env)
+    }
+
+    /// Get the format of the metadata
+    pub fn get_metadata_format(&self) -> Result<Box<dyn MetadataFormat>> {
+        match self.metadata_format {
+            Some(ref f) => get_format(f),
+            None => Ok(detect_format(&self.metadata_path)),
+        }
+    }
+
+    /// Get the format of another metadata file
+    ///
+    /// Its extension is used when known, the configured format otherwise.
+    pub fn get_metadata_format_of<P: AsRef<Path>>(&self, path: P) -> Result<Box<dyn MetadataFormat>> {
+        match extension_format(path.as_ref()) {
+            Some(f) => Ok(f),
+            None => match self.metadata_format {
+                Some(ref f) => get_format(f),
+                None => Ok(detect_format(path)),
+            },
         }
     }
 
@@ -55,7 +99,21 @@ impl Config {
             Some(s) => s.to_string(),
             None => VarsHandler::get_default_path()?.display().to_string(),
         };
-        Ok(Config::new(metadata_path, autobackup_interval, vars_path))
+        let mut config = Config::new(metadata_path, autobackup_interval, vars_path);
+        if let Some(m) = json["rollback_max_size"].as_u64() {
+            config.rollback_max_size = m;
+        }
+        if let Some(f) = json["metadata_format"].as_str() {
+            get_format(f)?;
+            config.metadata_format = Some(f.to_string());
+        }
+        if let Some(k) = json["keyfile"].as_str() {
+            config.secret = Some(Secret::Keyfile(PathBuf::from(k)));
+        } else if let Some(p) = json["passphrase"].as_str() {
+            // Saved by older versions, it is not saved again
+            config.secret = Some(Secret::Passphrase(p.to_string()));
+        }
+        Ok(config)
     }
 
     /// Read config from user config
@@ -84,11 +142,19 @@ impl Config {
 /// Convert Config into Json
 impl Into<JsonValue> for Config {
     fn into(self) -> JsonValue {
-        object!{
+        let mut json = object!{
             "metadata_path" => self.metadata_path.display().to_string(),
             "autobackup_interval" => self.autobackup_interval,
-            "vars_path" => self.vars_path.display().to_string()
+            "vars_path" => self.vars_path.display().to_string(),
+            "rollback_max_size" => self.rollback_max_size
+        };
+        if let Some(f) = self.metadata_format {
+            json["metadata_format"] = f.into();
+        }
+        if let Some(Secret::Keyfile(ref k)) = self.secret {
+            json["keyfile"] = k.display().to_string().into();
         }
+        json
     }
 }
 
@@ -115,5 +181,45 @@ mod unit_tests {
         let config = Config::from_json_stream(&mut cursor).unwrap();
         assert_eq!(config.metadata_path, Path::new("/"));
         assert_eq!(config.autobackup_interval, 260);
+        assert_eq!(config.rollback_max_size, DEFAULT_ROLLBACK_MAX_SIZE);
+    }
+
+    #[test]
+    fn read_rollback_max_size() {
+        let json_str = "{\"metadata_path\":\"/\", \"rollback_max_size\":42}";
+        let mut cursor = Cursor::new(json_str);
+        let config = Config::from_json_stream(&mut cursor).unwrap();
+        assert_eq!(config.rollback_max_size, 42);
+    }
+
+    #[test]
+    fn read_secret() {
+        let json_str = "{\"metadata_path\":\"/\", \"keyfile\":\"/key\"}";
+        let mut cursor = Cursor::new(json_str);
+        let config = Config::from_json_stream(&mut cursor).unwrap();
+        assert_eq!(config.secret, Some(Secret::Keyfile(PathBuf::from("/key"))));
+        let json: JsonValue = config.into();
+        assert_eq!(json["keyfile"].as_str(), Some("/key"));
+        let json_str = "{\"metadata_path\":\"/\", \"passphrase\":\"secret\"}";
+        let config = Config::from_json_stream(&mut Cursor::new(json_str)).unwrap();
+        assert_eq!(config.get_secret(), Some(Secret::Passphrase("secret".to_string())));
+        let json: JsonValue = config.into();
+        assert!(!json.dump().contains("secret"));
+    }
+
+    #[test]
+    fn read_metadata_format() {
+        let mut cursor = Cursor::new("{\"metadata_path\":\"/metadata.json\"}");
+        let config = Config::from_json_stream(&mut cursor).unwrap();
+        assert_eq!(config.get_metadata_format().unwrap().name(), "json");
+        let mut cursor = Cursor::new("{\"metadata_path\":\"/m.json\", \"metadata_format\":\"toml\"}");
+        let config = Config::from_json_stream(&mut cursor).unwrap();
+        assert_eq!(config.get_metadata_format().unwrap().name(), "toml");
+        assert_eq!(config.get_metadata_format_of("/other").unwrap().name(), "toml");
+        assert_eq!(config.get_metadata_format_of("/other.yml").unwrap().name(), "yaml");
+        let json: JsonValue = config.into();
+        assert_eq!(json["metadata_format"].as_str(), Some("toml"));
+        let mut cursor = Cursor::new("{\"metadata_path\":\"/\", \"metadata_format\":\"xml\"}");
+        assert!(Config::from_json_stream(&mut cursor).is_err());
     }
 }
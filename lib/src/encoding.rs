@@ -0,0 +1,115 @@
+//! How the files of a tree are stored
+//!
+//! Files of a backup can be compressed, then encrypted. Both are applied to
+//! each file on its own, which keeps its path.
+
+use compression::Compression;
+use encryption::{DecryptReader, EncryptWriter, Key};
+use error::*;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Compression and key of the files of a tree
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Encoding {
+    /// Compression of files
+    pub compression: Compression,
+    /// Key encrypting files, `None` if they are in clear
+    pub key: Option<Key>,
+}
+
+impl Encoding {
+    /// Check if files are stored as they are
+    pub fn is_plain(&self) -> bool {
+        !self.compression.is_compressed() && self.key.is_none()
+    }
+
+    /// Get the encoding of small files, like manifests, which are encrypted
+    /// but never compressed
+    pub fn sidecar(&self) -> Encoding {
+        Encoding {
+            key: self.key,
+            ..Encoding::default()
+        }
+    }
+
+    /// Open a file stored with this encoding, reading its original content
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<Box<dyn Read>> {
+        let f: Box<dyn Read> = Box::new(File::open(path)?);
+        let f: Box<dyn Read> = match self.key {
+            Some(ref key) => Box::new(DecryptReader::new(key, f)?),
+            None => f,
+        };
+        self.compression.decoder(f)
+    }
+
+    /// Write `stream` to a new file stored with this encoding
+    ///
+    /// Return the size of the original content
+    pub fn write<P: AsRef<Path>, R: Read>(&self, stream: &mut R, path: P) -> Result<u64> {
+        let f = File::create(path)?;
+        let (size, mut f) = match self.key {
+            Some(ref key) => {
+                let (size, writer) = self.compression
+                    .encode(stream, EncryptWriter::new(key, f)?)?;
+                (size, writer.finish()?)
+            }
+            None => self.compression.encode(stream, f)?,
+        };
+        f.flush()?;
+        Ok(size)
+    }
+}
+
+/// Copy a file between trees stored with different encodings
+///
+/// Content is copied as is when encodings are the same.
+pub fn transcode(from: &Path, from_encoding: Encoding, to: &Path, to_encoding: Encoding) -> Result<()> {
+    if from_encoding == to_encoding {
+        fs::copy(from, to)?;
+    } else {
+        to_encoding.write(&mut from_encoding.open(from)?, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use encryption::{Encryption, Secret};
+    use test_utils::test_dir;
+
+    #[test]
+    fn encoding_roundtrip() {
+        let tmp = test_dir("encoding");
+        let dir = tmp.path();
+        fs::create_dir_all(dir).unwrap();
+        let content = "encoded ".repeat(100);
+        fs::write(dir.join("plain"), &content).unwrap();
+        let secret = Secret::Passphrase("passphrase".to_string());
+        let key = Encryption::create(&secret).unwrap().1;
+        let encodings = [
+            Encoding {
+                compression: Compression::Zstd,
+                key: None,
+            },
+            Encoding {
+                compression: Compression::None,
+                key: Some(key),
+            },
+            Encoding {
+                compression: Compression::Gzip,
+                key: Some(key),
+            },
+        ];
+        for (i, encoding) in encodings.iter().enumerate() {
+            let stored = dir.join(i.to_string());
+            transcode(&dir.join("plain"), Encoding::default(), &stored, *encoding).unwrap();
+            assert!(!fs::read(&stored).unwrap().starts_with(b"encoded"));
+            let mut read = String::new();
+            encoding.open(&stored).unwrap().read_to_string(&mut read).unwrap();
+            assert_eq!(read, content);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Encryption of backed up files
+//!
+//! Files are encrypted with XChaCha20-Poly1305 by chunks, so big files don't
+//! have to fit in memory, and a damaged or truncated file is detected. Keys
+//! are derived with Argon2 from a passphrase or the content of a keyfile, and
+//! a salt recorded next to each generation. A keyfile is set in the config, a
+//! passphrase is never saved and is read from `HUPA_PASSPHRASE` or asked.
+//!
+//! Only the content of files is encrypted, names are kept in clear.
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::XChaCha20Poly1305;
+use error::*;
+use manifest::{hash_bytes, to_hex};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Header of encrypted files
+const MAGIC: &[u8] = b"hupaenc1";
+/// Size of encrypted chunks, before encryption
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Size of the authentication tag of a chunk
+const TAG_SIZE: usize = 16;
+/// Size of the nonce of a file
+const NONCE_SIZE: usize = 19;
+/// Size of the salt of a generation
+const SALT_SIZE: usize = 16;
+
+/// Environment variable holding the passphrase
+pub const PASSPHRASE_VAR: &str = "HUPA_PASSPHRASE";
+
+/// Secret keys are derived from
+#[derive(Clone, PartialEq)]
+pub enum Secret {
+    /// A passphrase
+    Passphrase(String),
+    /// The content of a file
+    Keyfile(PathBuf),
+}
+
+impl Secret {
+    /// Get the passphrase of `HUPA_PASSPHRASE`, if it is set
+    pub fn from_env() -> Option<Secret> {
+        match env::var(PASSPHRASE_VAR) {
+            Ok(ref p) if !p.is_empty() => Some(Secret::Passphrase(p.clone())),
+            _ => None,
+        }
+    }
+
+    fn read(&self) -> Result<Vec<u8>> {
+        match *self {
+            Secret::Passphrase(ref p) => Ok(p.as_bytes().to_vec()),
+            Secret::Keyfile(ref p) => Ok(fs::read(p)?),
+        }
+    }
+}
+
+/// The passphrase is never printed
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Secret::Passphrase(_) => write!(f, "Passphrase(..)"),
+            Secret::Keyfile(ref p) => write!(f, "Keyfile({:?})", p),
+        }
+    }
+}
+
+/// Key encrypting files
+#[derive(Clone, Copy, PartialEq)]
+pub struct Key([u8; 32]);
+
+/// The key is never printed
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+impl Key {
+    /// Derive a key from a secret
+    fn derive(secret: &Secret, salt: &[u8]) -> Result<Key> {
+        let mut key = [0; 32];
+        if Argon2::default()
+            .hash_password_into(&secret.read()?, salt, &mut key)
+            .is_err()
+        {
+            bail!(ErrorKind::InvalidEncryption);
+        }
+        Ok(Key(key))
+    }
+
+    /// Get a value telling if a key is the right one, without revealing it
+    fn check(&self) -> String {
+        let mut bytes = b"hupa key check ".to_vec();
+        bytes.extend_from_slice(&self.0);
+        hash_bytes(&bytes)
+    }
+}
+
+/// Salt of the key of a generation, with a check of the key
+#[derive(Clone, Debug, PartialEq)]
+pub struct Encryption {
+    salt: Vec<u8>,
+    check: String,
+}
+
+impl Encryption {
+    /// Create a new salt and derive its key
+    pub fn create(secret: &Secret) -> Result<(Encryption, Key)> {
+        let mut salt = vec![0; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        let key = Key::derive(secret, &salt)?;
+        let encryption = Encryption {
+            salt,
+            check: key.check(),
+        };
+        Ok((encryption, key))
+    }
+
+    /// Derive the key, fail with `WrongKey` if the secret is not the one used
+    /// to create the salt
+    pub fn unlock(&self, secret: &Secret) -> Result<Key> {
+        let key = Key::derive(secret, &self.salt)?;
+        if key.check() != self.check {
+            bail!(ErrorKind::WrongKey);
+        }
+        Ok(key)
+    }
+
+    /// Read an encryption written by `write`
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Encryption> {
+        let content = fs::read_to_string(path)?;
+        let mut split = content.split_whitespace();
+        let salt = split.next().and_then(from_hex);
+        match (salt, split.next()) {
+            (Some(salt), Some(check)) => Ok(Encryption {
+                salt,
+                check: check.to_string(),
+            }),
+            _ => bail!(ErrorKind::InvalidEncryption),
+        }
+    }
+
+    /// Write the salt and the check of the key
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, format!("{} {}\n", to_hex(&self.salt), self.check))?;
+        Ok(())
+    }
+}
+
+// `is_multiple_of` is too recent for the toolchains hupa supports
+#[allow(unknown_lints, clippy::manual_is_multiple_of)]
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn damaged() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "encrypted file is damaged")
+}
+
+/// Writer encrypting what is written to it
+///
+/// `finish` must be called to write the last chunk.
+pub struct EncryptWriter<W: Write> {
+    out: W,
+    encryptor: EncryptorBE32<XChaCha20Poly1305>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// Write the header of an encrypted file to `out`
+    pub fn new(key: &Key, mut out: W) -> Result<EncryptWriter<W>> {
+        let mut nonce = [0; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        out.write_all(MAGIC)?;
+        out.write_all(&nonce)?;
+        Ok(EncryptWriter {
+            out,
+            encryptor: EncryptorBE32::new((&key.0).into(), (&nonce).into()),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    /// Write the last chunk and get back the writer
+    pub fn finish(self) -> io::Result<W> {
+        let EncryptWriter {
+            mut out,
+            encryptor,
+            buffer,
+        } = self;
+        let chunk = encryptor
+            .encrypt_last(buffer.as_slice())
+            .map_err(|_| damaged())?;
+        out.write_all(&chunk)?;
+        Ok(out)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A full chunk is only written once more data comes, as the last
+        // chunk is encrypted differently
+        if self.buffer.len() == CHUNK_SIZE {
+            let chunk = self
+                .encryptor
+                .encrypt_next(self.buffer.as_slice())
+                .map_err(|_| damaged())?;
+            self.out.write_all(&chunk)?;
+            self.buffer.clear();
+        }
+        let len = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Reader decrypting a file written by `EncryptWriter`
+pub struct DecryptReader<R: Read> {
+    input: R,
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    /// First byte of the next chunk, read to know if a chunk is the last one
+    lookahead: Vec<u8>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Read the header of an encrypted file from `input`
+    pub fn new(key: &Key, mut input: R) -> Result<DecryptReader<R>> {
+        let mut header = [0; MAGIC.len() + NONCE_SIZE];
+        input.read_exact(&mut header).map_err(|_| damaged())?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(damaged().into());
+        }
+        let nonce = &header[MAGIC.len()..];
+        Ok(DecryptReader {
+            input,
+            decryptor: Some(DecryptorBE32::new((&key.0).into(), nonce.into())),
+            lookahead: Vec::new(),
+            chunk: Vec::new(),
+            position: 0,
+        })
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let decryptor = match self.decryptor {
+            Some(ref mut d) => d,
+            None => return Ok(()),
+        };
+        let full = CHUNK_SIZE + TAG_SIZE;
+        let mut data = ::std::mem::take(&mut self.lookahead);
+        let mut len = data.len();
+        data.resize(full + 1, 0);
+        while len < data.len() {
+            match self.input.read(&mut data[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        data.truncate(len);
+        self.chunk = if len > full {
+            self.lookahead = data.split_off(full);
+            decryptor.decrypt_next(data.as_slice()).map_err(|_| damaged())?
+        } else {
+            let decryptor = self.decryptor.take().unwrap();
+            decryptor.decrypt_last(data.as_slice()).map_err(|_| damaged())?
+        };
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if self.decryptor.is_none() {
+                return Ok(0);
+            }
+            self.read_chunk()?;
+        }
+        let len = buf.len().min(self.chunk.len() - self.position);
+        buf[..len].copy_from_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn roundtrip(key: &Key, content: &[u8]) -> Vec<u8> {
+        let mut writer = EncryptWriter::new(key, Vec::new()).unwrap();
+        writer.write_all(content).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn encryption_roundtrip() {
+        let secret = Secret::Passphrase("passphrase".to_string());
+        let (encryption, key) = Encryption::create(&secret).unwrap();
+        assert_eq!(encryption.unlock(&secret).unwrap(), key);
+        let wrong = Secret::Passphrase("wrong".to_string());
+        match encryption.unlock(&wrong) {
+            Err(Error(ErrorKind::WrongKey, _)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        for len in &[0, 10, CHUNK_SIZE, CHUNK_SIZE * 2 + 3] {
+            let content = (0..*len).map(|i| i as u8).collect::<Vec<u8>>();
+            let encrypted = roundtrip(&key, &content);
+            let mut read = Vec::new();
+            DecryptReader::new(&key, encrypted.as_slice())
+                .unwrap()
+                .read_to_end(&mut read)
+                .unwrap();
+            assert_eq!(read, content);
+            // Truncated files are detected
+            if *len > CHUNK_SIZE {
+                let truncated = &encrypted[..encrypted.len() - TAG_SIZE - 3];
+                let mut reader = DecryptReader::new(&key, truncated).unwrap();
+                assert!(reader.read_to_end(&mut Vec::new()).is_err());
+            }
+        }
+    }
+}
@@ -4,8 +4,13 @@ error_chain! {
     foreign_links {
         AppDirs(::app_dirs::AppDirsError) #[doc = "Error from app_dirs crate"];
         Json(::json::Error) #[doc = "Error from json crate"];
+        Toml(::toml::de::Error) #[doc = "Error from toml crate when reading"];
+        TomlWrite(::toml::ser::Error) #[doc = "Error from toml crate when writing"];
+        Yaml(::yaml_rust2::ScanError) #[doc = "Error from yaml-rust2 crate when reading"];
+        YamlWrite(::yaml_rust2::EmitError) #[doc = "Error from yaml-rust2 crate when writing"];
         Io(::std::io::Error) #[doc = "IO error"];
         Var(::std::env::VarError) #[doc = "Var error"];
+        Zip(::zip::result::ZipError) #[doc = "Error from zip crate"];
     }
 
     errors {
@@ -14,16 +19,112 @@ error_chain! {
             description("metadata is invalid")
             display("metadata is invalid")
         }
+        /// A hupa of metadata is invalid
+        InvalidHupa(hupa: String, reason: String) {
+            description("hupa in metadata is invalid")
+            display("hupa {} in metadata is invalid: {}", hupa, reason)
+        }
+        /// A field of a hupa in metadata is invalid
+        InvalidHupaField(hupa: String, field: String, reason: String) {
+            description("field of hupa in metadata is invalid")
+            display("field `{}` of hupa {} in metadata is invalid: {}", field, hupa, reason)
+        }
+        /// Metadata was written by a newer version of hupa
+        UnsupportedMetadataVersion(v: u64) {
+            description("metadata version is not supported")
+            display("metadata version {} is not supported, hupa must be updated", v)
+        }
         /// Invalid metadata format
         InvalidMetadataFormat(f: String) {
             description("specified metadata format is invalid")
             display("specified metadata format is invalid: {}", f)
         }
+        /// Invalid archive format
+        InvalidArchiveFormat(f: String) {
+            description("specified archive format is invalid")
+            display("specified archive format is invalid: {}", f)
+        }
+        /// Invalid compression
+        InvalidCompression(c: String) {
+            description("specified compression is invalid")
+            display("specified compression is invalid: {}", c)
+        }
+        /// Archive is invalid
+        InvalidArchive {
+            description("archive is invalid")
+            display("archive is invalid")
+        }
         /// Error when origin file is missing
         MissingBackup(p: String)  {
             description("backup file is missing")
             display("backup file is misssing, restore can't be made: {}", p)
         }
+        /// Error when there is no restore to undo
+        MissingRollback(p: String) {
+            description("rollback is missing")
+            display("there is no restore to undo: {}", p)
+        }
+        /// Error when the list of paths created by a restore is invalid
+        InvalidRollback(l: String) {
+            description("rollback is invalid")
+            display("list of restored paths is invalid at line: {}", l)
+        }
+        /// Error when no generation matches
+        MissingGeneration(t: String) {
+            description("generation is missing")
+            display("no generation matches {}, restore can't be made", t)
+        }
+        /// Error when the latest generation pointer is invalid
+        InvalidGeneration(g: String) {
+            description("generation is invalid")
+            display("generation {} is invalid", g)
+        }
+        /// Error when a glob pattern of a rule is invalid
+        InvalidPattern(p: String) {
+            description("pattern is invalid")
+            display("pattern {} is invalid", p)
+        }
+        /// Error when a line of an attributes sidecar file is invalid
+        InvalidAttributes(l: String) {
+            description("attributes file is invalid")
+            display("attributes file is invalid at line: {}", l)
+        }
+        /// Error when a generation has no manifest to be verified against
+        MissingManifest(p: String) {
+            description("manifest is missing")
+            display("manifest is missing, backup can't be verified: {}", p)
+        }
+        /// Files of a generation are compressed or encrypted, so they can't be
+        /// checked by `sha256sum`
+        EncodedGeneration(g: u64) {
+            description("generation is compressed or encrypted")
+            display("generation {} is compressed or encrypted, sha256sum can't check it", g)
+        }
+        /// Error when a line of a manifest is invalid
+        InvalidManifest(l: String) {
+            description("manifest is invalid")
+            display("manifest is invalid at line: {}", l)
+        }
+        /// Error when a key is needed but none is set in config
+        MissingKey(h: String) {
+            description("no key is set in config")
+            display("no key is set in config, backup is encrypted: {}", h)
+        }
+        /// Error when a key can't decrypt a backup
+        WrongKey {
+            description("key is wrong")
+            display("key is wrong, backup can't be decrypted")
+        }
+        /// Error when the encryption of a generation is invalid
+        InvalidEncryption {
+            description("encryption is invalid")
+            display("encryption is invalid")
+        }
+        /// Error when a line of an origins file is invalid
+        InvalidOrigins(l: String) {
+            description("origins file is invalid")
+            display("origins file is invalid at line: {}", l)
+        }
         /// Error when backup file is missing
         MissingOrigin(p: String)  {
             description("origin file is missing")
@@ -49,10 +150,30 @@ error_chain! {
             description("value is missing for variable")
             display("variable {} doesn't have value", n)
         }
+        /// Error when a variable of a path template has no value
+        UndefinedVar(v: String) {
+            description("variable of path is not defined")
+            display("variable {} is not defined, path can't be expanded", v)
+        }
+        /// Error when a line of vars file is invalid
+        InvalidVarsLine(l: usize, reason: String) {
+            description("vars file is invalid")
+            display("vars file is invalid at line {}: {}", l, reason)
+        }
         /// Error when variables does not fullfill needs of one hupa
         MissingNeededVar(v: String) {
             description("variable is needed to backup or restore hupa")
             display("variable {} is needed to backup or restore hupa", v)
         }
+        /// Error when a requirement on variables can't be parsed
+        InvalidRequirement(r: String, reason: String) {
+            description("requirement is invalid")
+            display("requirement {} is invalid: {}", r, reason)
+        }
+        /// Error when a sub-expression of a requirement is not met
+        UnmetRequirement(sub: String, r: String) {
+            description("requirement is needed to backup or restore hupa")
+            display("{} is needed to backup or restore hupa, in requirement {}", sub, r)
+        }
     }
 }
@@ -0,0 +1,138 @@
+//! Built-in facts about the machine
+//!
+//! Facts are read-only vars computed when they are used, so hupas can require
+//! them without filling the vars file:
+//!
+//! - `hostname` - Name of the machine
+//! - `os` - Operating system, like `linux` or `macos`
+//! - `arch` - Architecture, like `x86_64`
+//! - `distro` - Distribution ID from `/etc/os-release`, like `gentoo`
+//! - `username` - Name of the user running hupa
+//! - `bin.<name>` - Presence of the binary `name` in `PATH`
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use vars::{Value, Var};
+
+/// Names of facts, without `bin.<name>`
+pub const FACTS: &[&str] = &["hostname", "os", "arch", "distro", "username"];
+
+/// Prefix of facts telling if a binary is present
+const BINARY_PREFIX: &str = "bin.";
+
+/// Get the value of a fact, `None` if it is unknown on this machine
+pub fn get_fact(name: &str) -> Option<Value> {
+    if let Some(binary) = name.strip_prefix(BINARY_PREFIX) {
+        return Some(Value::Bool(has_binary(binary)));
+    }
+    let value = match name {
+        "hostname" => hostname()?,
+        "os" => env::consts::OS.to_string(),
+        "arch" => env::consts::ARCH.to_string(),
+        "distro" => distro(fs::read_to_string("/etc/os-release").ok()?)?,
+        "username" => username()?,
+        _ => return None,
+    };
+    Some(Value::Str(value))
+}
+
+/// Get every known fact, without `bin.<name>`
+pub fn get_facts() -> Vec<Var> {
+    FACTS
+        .iter()
+        .filter_map(|n| get_fact(n).map(|v| (n.to_string(), v)))
+        .collect()
+}
+
+/// Get the name of the machine
+#[cfg(unix)]
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { ::libc::gethostname(buf.as_mut_ptr() as *mut ::libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).to_string())
+}
+
+/// Get the name of the machine
+#[cfg(not(unix))]
+pub fn hostname() -> Option<String> {
+    env::var("COMPUTERNAME").ok()
+}
+
+/// Get the name of the user running hupa
+#[cfg(unix)]
+fn username() -> Option<String> {
+    let passwd = unsafe { ::libc::getpwuid(::libc::getuid()) };
+    if passwd.is_null() {
+        return env::var("USER").ok();
+    }
+    let name = unsafe { ::std::ffi::CStr::from_ptr((*passwd).pw_name) };
+    Some(name.to_string_lossy().to_string())
+}
+
+/// Get the name of the user running hupa
+#[cfg(not(unix))]
+fn username() -> Option<String> {
+    env::var("USERNAME").ok()
+}
+
+/// Get the `ID` of an `os-release` file
+fn distro<S: AsRef<str>>(os_release: S) -> Option<String> {
+    os_release
+        .as_ref()
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("ID="))
+        .map(|id| id.trim_matches(|c| c == '"' || c == '\'').to_string())
+        .next()
+}
+
+/// Check if a binary is in `PATH`
+fn has_binary(name: &str) -> bool {
+    if name.is_empty() || name.contains('/') || name.contains('\\') {
+        return false;
+    }
+    let path = match env::var_os("PATH") {
+        Some(p) => p,
+        None => return false,
+    };
+    env::split_paths(&path).any(|dir| {
+        is_executable(&dir.join(name)) || (cfg!(windows) && is_executable(&dir.join(format!("{}.exe", name))))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    match fs::metadata(path) {
+        Ok(m) => m.is_file() && m.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn read_facts() {
+        assert_eq!(
+            distro("NAME=Gentoo\nID=gentoo\nPRETTY_NAME=\"Gentoo Linux\"\n"),
+            Some("gentoo".to_string())
+        );
+        assert_eq!(distro("ID=\"opensuse-tumbleweed\""), Some("opensuse-tumbleweed".to_string()));
+        assert_eq!(distro("NAME=x"), None);
+        assert_eq!(get_fact("os"), Some(Value::Str(env::consts::OS.to_string())));
+        assert_eq!(get_fact("bin.hupa-missing-binary"), Some(Value::Bool(false)));
+        assert_eq!(get_fact("bin.a/b"), Some(Value::Bool(false)));
+        assert_eq!(get_fact("unknown"), None);
+        assert!(get_facts().iter().any(|f| f.0 == "arch"));
+    }
+}
@@ -1,18 +1,123 @@
 //! Module to play with filesystem
 
+use attributes::{copy_attributes, Attributes};
+use encoding::{transcode, Encoding};
 use error::*;
-use std::fs;
-use std::path::Path;
+use filetime::{set_file_mtime, FileTime};
+use manifest::Manifest;
+use rules::{device, Rules};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+
+/// Count of files changed by a synchronisation
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SyncStats {
+    /// Number of new files
+    pub added: u64,
+    /// Number of modified files
+    pub updated: u64,
+    /// Number of deleted files
+    pub removed: u64,
+    /// Number of special files, like sockets, which can't be copied
+    pub skipped: u64,
+}
+
+impl SyncStats {
+    /// Check if no file has changed, skipped files are not changes
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.updated == 0 && self.removed == 0
+    }
+}
+
+impl AddAssign for SyncStats {
+    fn add_assign(&mut self, other: SyncStats) {
+        self.added += other.added;
+        self.updated += other.updated;
+        self.removed += other.removed;
+        self.skipped += other.skipped;
+    }
+}
+
+/// Change made to a file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// File is created
+    Create,
+    /// Existing file is replaced
+    Overwrite,
+    /// File is deleted
+    Delete,
+    /// Special file which can't be copied
+    Skip,
+}
+
+/// Change planned on a file, computed without touching disk
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedAction {
+    /// Kind of change
+    pub action: Action,
+    /// Path of the file, relative to the destination
+    pub path: PathBuf,
+    /// Size of the written file, or of the deleted one
+    pub size: u64,
+}
+
+/// How files are stored in the trees of a synchronisation
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Storage<'a> {
+    /// Encoding of the source
+    pub from: Encoding,
+    /// Encoding of the compared tree, unused when synchronised in place
+    pub base: Encoding,
+    /// Encoding of the destination
+    pub to: Encoding,
+    /// Manifest of the encoded tree among the source and the compared tree,
+    /// giving original sizes of its files
+    pub manifest: Option<&'a Manifest>,
+    /// Files of the source whose content changed though their size and
+    /// modification time didn't, never taken from the compared tree
+    pub changed: Option<&'a HashSet<PathBuf>>,
+}
 
 /// Copy a directory
-pub fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
+///
+/// Symlinks are copied as symlinks and FIFOs are recreated, unless `rules`
+/// follows symlinks. Other special files are skipped. Hard links between
+/// copied files are kept.
+///
+/// `rules` - Rules selecting copied files, relative to `from`
+pub fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q, rules: &Rules) -> Result<u64> {
     let (from, to) = (from.as_ref(), to.as_ref());
 
     if !from.exists() {
         let err = ::std::io::Error::new(::std::io::ErrorKind::NotFound, "entity not found");
         return Err(err.into());
     }
+    let metadata = from.metadata()?;
+    let mut links = HashMap::new();
+    let result = copy_dir_relative(
+        from,
+        to,
+        Path::new(""),
+        rules,
+        device(&metadata),
+        &mut links,
+    )?;
+    copy_attributes(from, to, &metadata)?;
+    Ok(result)
+}
 
+/// `links` - Copies of files with many links, by inode of the source
+fn copy_dir_relative(
+    from: &Path,
+    to: &Path,
+    relative: &Path,
+    rules: &Rules,
+    root_device: Option<u64>,
+    links: &mut HashMap<Inode, PathBuf>,
+) -> Result<u64> {
     if !to.exists() {
         fs::create_dir_all(&to)?;
     }
@@ -23,11 +128,35 @@ pub fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
         let entry = entry?;
         let path = entry.path();
         let file_name = path.file_name().unwrap();
+        let relative = relative.join(file_name);
+        let metadata = rules.metadata(&path, &relative)?;
+        if rules.is_excluded(&relative, &metadata, root_device) {
+            continue;
+        }
         to.push(file_name);
-        if path.is_dir() {
-            result += copy_dir(path.clone(), to.clone())?;
-        } else if path.is_file() {
-            result += fs::copy(&path, &to)?;
+        match EntryKind::of(&metadata) {
+            EntryKind::Dir => {
+                if !is_link_loop(&path, from) {
+                    result +=
+                        copy_dir_relative(&path, &to, &relative, rules, root_device, links)?;
+                    copy_attributes(&path, &to, &metadata)?;
+                }
+            }
+            EntryKind::Special => {}
+            _ => {
+                match inode(&metadata).map(|i| (i, links.get(&i).cloned())) {
+                    Some((_, Some(first))) => link_or_copy(&first, &to, &metadata)?,
+                    Some((i, None)) => {
+                        copy_entry(&path, &to, &metadata)?;
+                        links.insert(i, to.clone());
+                        result += metadata.len();
+                    }
+                    None => {
+                        copy_entry(&path, &to, &metadata)?;
+                        result += metadata.len();
+                    }
+                }
+            }
         }
         to.pop();
     }
@@ -35,51 +164,993 @@ pub fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
 }
 
 /// Get size of file or directory
-pub fn get_size<P: AsRef<Path>>(path: P) -> Result<u64> {
+///
+/// Files with many links are counted once.
+///
+/// `rules` - Rules selecting counted files, relative to `path`
+pub fn get_size<P: AsRef<Path>>(path: P, rules: &Rules) -> Result<u64> {
     let path = path.as_ref();
     if !path.exists() {
         let err = ::std::io::Error::new(::std::io::ErrorKind::NotFound, "entity not found");
         return Err(err.into());
     }
+    let root_device = device(&path.metadata()?);
+    get_size_relative(path, Path::new(""), rules, root_device, &mut HashSet::new())
+}
+
+/// `counted` - Inodes of already counted files with many links
+fn get_size_relative(
+    path: &Path,
+    relative: &Path,
+    rules: &Rules,
+    root_device: Option<u64>,
+    counted: &mut HashSet<Inode>,
+) -> Result<u64> {
+    let metadata = rules.metadata(path, relative)?;
+    if rules.is_excluded(relative, &metadata, root_device) {
+        return Ok(0);
+    }
     let mut result = 0;
-    if path.is_file() {
-        result += path.metadata()?.len();
-    } else if path.is_dir() {
+    if metadata.is_file() {
+        if inode(&metadata).map(|i| counted.insert(i)).unwrap_or(true) {
+            result += metadata.len();
+        }
+    } else if metadata.is_dir() {
         for entry in fs::read_dir(&path)? {
             let entry = entry?;
-            let path = entry.path();
-            result += get_size(&path)?;
+            let entry_path = entry.path();
+            if rules.is_following_symlinks() && is_link_loop(&entry_path, path) {
+                continue;
+            }
+            result += get_size_relative(
+                &entry_path,
+                &relative.join(entry.file_name()),
+                rules,
+                root_device,
+                counted,
+            )?;
         }
     }
     Ok(result)
 }
 
 /// Check if directory is older than source
-pub fn check_older<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dir: Q) -> Result<bool> {
+///
+/// `rules` - Rules selecting compared files, relative to `src`
+pub fn check_older<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dir: Q, rules: &Rules) -> Result<bool> {
     let (src, dir) = (src.as_ref(), dir.as_ref());
     if !src.exists() || !dir.exists() {
         return Ok(true);
     }
+    let root_device = device(&src.metadata()?);
+    check_older_relative(src, dir, Path::new(""), rules, root_device)
+}
+
+fn check_older_relative(
+    src: &Path,
+    dir: &Path,
+    relative: &Path,
+    rules: &Rules,
+    root_device: Option<u64>,
+) -> Result<bool> {
     if src.metadata()?.modified()? > dir.metadata()?.modified()? {
         return Ok(true);
     }
     for entry in src.read_dir()? {
         let entry = entry?.path();
-        let dir_entry = dir.join(entry.file_name().unwrap());
-        if !dir_entry.exists() {
-            return Ok(true);
+        let file_name = entry.file_name().unwrap();
+        let relative = relative.join(file_name);
+        let entry_met = rules.metadata(&entry, &relative)?;
+        if rules.is_excluded(&relative, &entry_met, root_device) {
+            continue;
         }
-        if entry.is_dir() {
-            if let Ok(true) = check_older(entry, dir_entry) {
-                return Ok(true);
+        let dir_entry = dir.join(file_name);
+        let dir_entry_met = match dir_entry.symlink_metadata() {
+            Ok(m) => m,
+            Err(_) => return Ok(true),
+        };
+        match EntryKind::of(&entry_met) {
+            EntryKind::Dir => {
+                if is_link_loop(&entry, src) {
+                    continue;
+                }
+                if !dir_entry_met.is_dir() {
+                    return Ok(true);
+                }
+                if let Ok(true) = check_older_relative(&entry, &dir_entry, &relative, rules, root_device) {
+                    return Ok(true);
+                }
             }
-        } else {
-            let entry_met = entry.metadata()?;
-            let dir_entry_met = dir_entry.metadata()?;
-            if entry_met.modified()? > dir_entry_met.modified()? {
-                return Ok(true);
+            EntryKind::Symlink => {
+                if !dir_entry_met.file_type().is_symlink() ||
+                    fs::read_link(&entry)? != fs::read_link(&dir_entry)?
+                {
+                    return Ok(true);
+                }
+            }
+            EntryKind::Special => {}
+            _ => {
+                if entry_met.modified()? > dir_entry_met.modified()? {
+                    return Ok(true);
+                }
             }
         }
     }
     Ok(false)
 }
+
+/// Synchronise `to` with `from`, like rsync
+///
+/// Only new or changed files are copied, files missing from `from` are
+/// deleted and unchanged files are left alone. Files are compared by size and
+/// modification time, which is preserved by the copy.
+///
+/// `base` - Tree to compare `from` against instead of `to`. `to` is then
+/// expected to be empty and unchanged files are hard linked from `base`.
+///
+/// `rules` - Rules selecting synchronised files, excluded files are left
+/// alone on both sides
+///
+/// `storage` - How files are stored in each tree
+///
+/// `attributes` - Filled with mode and ownership of every synchronised entry
+/// of `from`, by relative path
+pub fn sync_all<P: AsRef<Path>, Q: AsRef<Path>>(
+    from: P,
+    to: Q,
+    base: Option<&Path>,
+    rules: &Rules,
+    storage: &Storage,
+    attributes: &mut Vec<(PathBuf, Attributes)>,
+) -> Result<SyncStats> {
+    let mut sync = Sync::new(to.as_ref(), base, None, rules, storage);
+    sync.run(from.as_ref())?;
+    attributes.append(&mut sync.attributes);
+    Ok(sync.stats)
+}
+
+/// Synchronise `to` with `from` in place, keeping what is replaced
+///
+/// Files overwritten or deleted in `to` are moved to `rollback` under the same
+/// relative path.
+///
+/// `added` - Filled with paths created in `to`, relative to `to`, even when
+/// the synchronisation fails
+pub fn sync_all_with_rollback<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
+    from: P,
+    to: Q,
+    rollback: R,
+    rules: &Rules,
+    storage: &Storage,
+    added: &mut Vec<PathBuf>,
+) -> Result<SyncStats> {
+    let mut sync = Sync::new(to.as_ref(), None, Some(rollback.as_ref()), rules, storage);
+    let result = sync.run(from.as_ref());
+    added.append(&mut sync.added);
+    result.map(|_| sync.stats)
+}
+
+/// Plan the synchronisation of `to` with `from` in place, without touching disk
+///
+/// A missing `to` is planned as entirely created.
+pub fn plan_sync<P: AsRef<Path>, Q: AsRef<Path>>(
+    from: P,
+    to: Q,
+    rules: &Rules,
+    storage: &Storage,
+) -> Result<Vec<PlannedAction>> {
+    let mut sync = Sync::new(to.as_ref(), None, None, rules, storage);
+    sync.plan = Some(Vec::new());
+    sync.run(from.as_ref())?;
+    Ok(sync.plan.unwrap_or_default())
+}
+
+/// Plan the removal of a file or a directory, file by file
+///
+/// Paths are relative to `path`.
+pub fn plan_remove<P: AsRef<Path>>(path: P) -> Result<Vec<PlannedAction>> {
+    let mut plan = Vec::new();
+    let path = path.as_ref();
+    if path.symlink_metadata().is_ok() {
+        push_removed(&mut plan, path, Path::new(""))?;
+    }
+    Ok(plan)
+}
+
+/// Push a delete action for every file under `path`
+fn push_removed(plan: &mut Vec<PlannedAction>, path: &Path, relative: &Path) -> Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let name = entry?.file_name();
+            push_removed(plan, &path.join(&name), &relative.join(&name))?;
+        }
+    } else {
+        plan.push(PlannedAction {
+            action: Action::Delete,
+            path: relative.to_path_buf(),
+            size: path.symlink_metadata()?.len(),
+        });
+    }
+    Ok(())
+}
+
+/// State of a synchronisation
+struct Sync<'a> {
+    /// Root of the destination
+    root: &'a Path,
+    /// Tree to compare against, `None` for the destination itself
+    base: Option<&'a Path>,
+    /// Where replaced files are moved, they are deleted if `None`
+    rollback: Option<&'a Path>,
+    /// Count of changed files
+    stats: SyncStats,
+    /// Paths created in the destination, relative to `root`
+    added: Vec<PathBuf>,
+    /// Planned changes, nothing is written to disk when set
+    plan: Option<Vec<PlannedAction>>,
+    /// Rules selecting synchronised files
+    rules: &'a Rules,
+    /// How files are stored in each tree
+    storage: Storage<'a>,
+    /// Devices of the source and compared roots
+    devices: (Option<u64>, Option<u64>),
+    /// Attributes of synchronised entries, relative to roots
+    attributes: Vec<(PathBuf, Attributes)>,
+    /// First met link of source files with many links, by inode, and if it
+    /// was written
+    links: HashMap<Inode, (PathBuf, bool)>,
+}
+
+impl<'a> Sync<'a> {
+    fn new(
+        root: &'a Path,
+        base: Option<&'a Path>,
+        rollback: Option<&'a Path>,
+        rules: &'a Rules,
+        storage: &Storage<'a>,
+    ) -> Sync<'a> {
+        Sync {
+            root,
+            base,
+            rollback,
+            stats: SyncStats::default(),
+            added: Vec::new(),
+            plan: None,
+            rules,
+            storage: *storage,
+            devices: (None, None),
+            attributes: Vec::new(),
+            links: HashMap::new(),
+        }
+    }
+
+    fn run(&mut self, from: &Path) -> Result<()> {
+        if !from.exists() {
+            let err = ::std::io::Error::new(::std::io::ErrorKind::NotFound, "entity not found");
+            return Err(err.into());
+        }
+        let base = self.base.unwrap_or(self.root);
+        self.devices = (
+            device(&from.metadata()?),
+            base.metadata().ok().and_then(|m| device(&m)),
+        );
+        if let (Some(p), false) = (self.root.parent(), self.dry_run()) {
+            fs::create_dir_all(p)?;
+        }
+        self.sync_entry(from, Path::new(""), false)
+    }
+
+    fn in_place(&self) -> bool {
+        self.base.is_none()
+    }
+
+    fn dry_run(&self) -> bool {
+        self.plan.is_some()
+    }
+
+    /// Encoding of the compared tree
+    fn base_encoding(&self) -> Encoding {
+        if self.in_place() {
+            self.storage.to
+        } else {
+            self.storage.base
+        }
+    }
+
+    /// Get the original size of a file, stored with `encoding`
+    fn original_len(&self, encoding: Encoding, relative: &Path, metadata: &Metadata) -> u64 {
+        if encoding.is_plain() {
+            return metadata.len();
+        }
+        self.storage
+            .manifest
+            .and_then(|m| m.get_entries().get(relative))
+            .map(|e| e.size)
+            .unwrap_or_else(|| metadata.len())
+    }
+
+    /// Record a planned change
+    fn push_plan(&mut self, action: Action, relative: &Path, size: u64) {
+        if let Some(ref mut plan) = self.plan {
+            plan.push(PlannedAction {
+                action,
+                path: relative.to_path_buf(),
+                size,
+            });
+        }
+    }
+
+    /// Count removed files under `path` and record them in the plan
+    fn count_removed(&mut self, path: &Path, relative: &Path) -> Result<()> {
+        self.stats.removed += count_files(path)?;
+        if let Some(ref mut plan) = self.plan {
+            push_removed(plan, path, relative)?;
+        }
+        Ok(())
+    }
+
+    /// Synchronise one entry
+    ///
+    /// `relative` - Path of the entry relative to roots
+    ///
+    /// `in_new_dir` - The entry is in a directory created by this sync
+    fn sync_entry(&mut self, from: &Path, relative: &Path, in_new_dir: bool) -> Result<()> {
+        let to = join(self.root, relative);
+        let base = join(self.base.unwrap_or(self.root), relative);
+        let metadata = self.rules.metadata(from, relative)?;
+        let base_metadata = self.rules.metadata(&base, relative).ok();
+        let kind = EntryKind::of(&metadata);
+        if kind == EntryKind::Special {
+            self.stats.skipped += 1;
+            self.push_plan(Action::Skip, relative, 0);
+            return Ok(());
+        }
+        if let Some(attributes) = Attributes::of(&metadata) {
+            self.attributes.push((relative.to_path_buf(), attributes));
+        }
+        if kind == EntryKind::Dir {
+            let base_is_dir = base_metadata.as_ref().map(|m| m.is_dir());
+            if base_is_dir == Some(false) {
+                self.count_removed(&base, relative)?;
+                if self.in_place() && !self.dry_run() {
+                    self.displace(&base, relative)?;
+                }
+            }
+            let created = self.rules
+                .metadata(&to, relative)
+                .map(|m| !m.is_dir())
+                .unwrap_or(true);
+            if created && !self.dry_run() {
+                fs::create_dir_all(&to)?;
+                self.mark_added(relative, in_new_dir);
+            }
+            let mut names = HashSet::new();
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let entry_relative = relative.join(&name);
+                let entry_metadata = self.rules.metadata(&entry.path(), &entry_relative)?;
+                if self.rules.is_excluded(&entry_relative, &entry_metadata, self.devices.0) {
+                    continue;
+                }
+                if self.rules.is_following_symlinks() && is_link_loop(&entry.path(), from) {
+                    self.stats.skipped += 1;
+                    self.push_plan(Action::Skip, &entry_relative, 0);
+                    continue;
+                }
+                self.sync_entry(&entry.path(), &entry_relative, in_new_dir || created)?;
+                names.insert(name);
+            }
+            if base_is_dir == Some(true) {
+                for entry in fs::read_dir(&base)? {
+                    let entry = entry?;
+                    if names.contains(&entry.file_name()) {
+                        continue;
+                    }
+                    let entry_relative = relative.join(entry.file_name());
+                    let entry_metadata = self.rules.metadata(&entry.path(), &entry_relative)?;
+                    // Special files like sockets of running programs are left alone
+                    if EntryKind::of(&entry_metadata) == EntryKind::Special ||
+                        self.rules.is_excluded(&entry_relative, &entry_metadata, self.devices.1)
+                    {
+                        continue;
+                    }
+                    self.count_removed(&entry.path(), &entry_relative)?;
+                    if self.in_place() && !self.dry_run() {
+                        self.displace(&entry.path(), &entry_relative)?;
+                    }
+                }
+            }
+            // Applied last, as changing the content of a directory updates its time
+            if !self.dry_run() {
+                copy_attributes(from, &to, &metadata)?;
+            }
+            return Ok(());
+        }
+        let id = if kind == EntryKind::File {
+            inode(&metadata)
+        } else {
+            None
+        };
+        let len = self.original_len(self.storage.from, relative, &metadata);
+        if let Some(id) = id {
+            if let Some(first) = self.links.get(&id).cloned() {
+                let base_metadata = base_metadata.as_ref();
+                return self.sync_link(&first, relative, base_metadata, len, in_new_dir);
+            }
+            self.links.insert(id, (relative.to_path_buf(), false));
+        }
+        let changed = self.storage.changed.is_some_and(|c| c.contains(relative));
+        if let (Some(base_metadata), false) = (base_metadata.as_ref(), changed) {
+            let base_len = self.original_len(self.base_encoding(), relative, base_metadata);
+            if is_same_entry((from, &metadata, len), (&base, base_metadata, base_len))? {
+                if !self.in_place() && !self.dry_run() {
+                    if kind != EntryKind::File {
+                        copy_entry(&base, &to, base_metadata)?;
+                    } else if self.storage.base == self.storage.to {
+                        link_or_copy(&base, &to, &metadata)?;
+                    } else {
+                        self.copy_entry(from, &to, &metadata)?;
+                    }
+                }
+                return Ok(());
+            }
+        }
+        if let Some(link) = id.and_then(|i| self.links.get_mut(&i)) {
+            link.1 = true;
+        }
+        self.count_written(&base, base_metadata.as_ref(), relative, len)?;
+        if self.dry_run() {
+            return Ok(());
+        }
+        self.make_room(&to, relative, in_new_dir)?;
+        self.copy_entry(from, &to, &metadata)
+    }
+
+    /// Copy an entry of the source, encoded as the destination
+    fn copy_entry(&self, from: &Path, to: &Path, metadata: &Metadata) -> Result<()> {
+        transcode_entry(from, self.storage.from, to, self.storage.to, metadata)
+    }
+
+    /// Synchronise another link of a file already synchronised
+    ///
+    /// The link is recreated unless it already exists in the compared tree.
+    ///
+    /// `first` - First met link of the file and if it was written
+    fn sync_link(
+        &mut self,
+        first: &(PathBuf, bool),
+        relative: &Path,
+        base_metadata: Option<&Metadata>,
+        len: u64,
+        in_new_dir: bool,
+    ) -> Result<()> {
+        let to = join(self.root, relative);
+        let base_root = self.base.unwrap_or(self.root);
+        let base = join(base_root, relative);
+        let (ref first, written) = *first;
+        if !written && is_same_inode(&base, &join(base_root, first)) {
+            if !self.in_place() && !self.dry_run() {
+                link(&join(self.root, first), &to)?;
+            }
+            return Ok(());
+        }
+        self.count_written(&base, base_metadata, relative, len)?;
+        if self.dry_run() {
+            return Ok(());
+        }
+        self.make_room(&to, relative, in_new_dir)?;
+        link(&join(self.root, first), &to)
+    }
+
+    /// Count a written file and record it in the plan
+    ///
+    /// `base` - Entry replaced by the file in the compared tree
+    fn count_written(
+        &mut self,
+        base: &Path,
+        base_metadata: Option<&Metadata>,
+        relative: &Path,
+        size: u64,
+    ) -> Result<()> {
+        match base_metadata {
+            Some(m) if m.is_dir() => {
+                self.count_removed(base, relative)?;
+                self.stats.added += 1;
+                self.push_plan(Action::Create, relative, size);
+            }
+            Some(_) => {
+                self.stats.updated += 1;
+                self.push_plan(Action::Overwrite, relative, size);
+            }
+            None => {
+                self.stats.added += 1;
+                self.push_plan(Action::Create, relative, size);
+            }
+        }
+        Ok(())
+    }
+
+    /// Free the destination path of a written entry
+    fn make_room(&mut self, to: &Path, relative: &Path, in_new_dir: bool) -> Result<()> {
+        if self.in_place() && to.symlink_metadata().is_ok() {
+            self.displace(to, relative)?;
+        } else {
+            self.mark_added(relative, in_new_dir);
+        }
+        Ok(())
+    }
+
+    /// Remove an entry of the destination, or move it to the rollback
+    fn displace(&self, path: &Path, relative: &Path) -> Result<()> {
+        match self.rollback {
+            Some(r) => move_entry(path, join(r, relative)),
+            None => remove_entry(path),
+        }
+    }
+
+    /// Record a created path, unless its directory is already recorded
+    fn mark_added(&mut self, relative: &Path, in_new_dir: bool) {
+        if !in_new_dir {
+            self.added.push(relative.to_path_buf());
+        }
+    }
+}
+
+/// Join a relative path, an empty path being the root itself
+pub(crate) fn join(root: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(relative)
+    }
+}
+
+/// Move a file or a directory, copying it across filesystems
+///
+/// Directories are merged with an existing destination.
+pub fn move_entry<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if let Some(p) = to.parent() {
+        fs::create_dir_all(p)?;
+    }
+    let is_dir = |p: &Path| p.symlink_metadata().map(|m| m.is_dir()).unwrap_or(false);
+    if is_dir(from) && is_dir(to) {
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            move_entry(entry.path(), to.join(entry.file_name()))?;
+        }
+        fs::remove_dir(from)?;
+        return Ok(());
+    }
+    if to.symlink_metadata().is_ok() {
+        remove_entry(to)?;
+    }
+    if fs::rename(from, to).is_err() {
+        if is_dir(from) {
+            copy_dir(from, to, &Rules::default())?;
+        } else {
+            copy_entry(from, to, &from.symlink_metadata()?)?;
+        }
+        remove_entry(from)?;
+    }
+    Ok(())
+}
+
+/// Kind of a directory entry
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EntryKind {
+    Dir,
+    File,
+    Symlink,
+    Fifo,
+    /// Sockets and device nodes, they can't be copied
+    Special,
+}
+
+impl EntryKind {
+    pub(crate) fn of(metadata: &Metadata) -> EntryKind {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else if is_fifo(metadata) {
+            EntryKind::Fifo
+        } else {
+            EntryKind::Special
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_fifo(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    metadata.file_type().is_fifo()
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// Device and inode numbers identifying a file
+type Inode = (u64, u64);
+
+/// Get the inode of a file with many links, `None` for other entries
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> Option<Inode> {
+    use std::os::unix::fs::MetadataExt;
+    if metadata.is_file() && metadata.nlink() > 1 {
+        Some((metadata.dev(), metadata.ino()))
+    } else {
+        None
+    }
+}
+
+/// Get the inode of a file with many links, `None` for other entries
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> Option<Inode> {
+    None
+}
+
+/// Check if two paths are links to the same file
+fn is_same_inode(a: &Path, b: &Path) -> bool {
+    match (a.symlink_metadata(), b.symlink_metadata()) {
+        (Ok(a), Ok(b)) => inode(&a).is_some() && inode(&a) == inode(&b),
+        _ => false,
+    }
+}
+
+/// Check if `path` is a symlink to one of the directories containing it
+pub(crate) fn is_link_loop(path: &Path, parent: &Path) -> bool {
+    let is_symlink = path.symlink_metadata()
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    if !is_symlink {
+        return false;
+    }
+    match (fs::canonicalize(path), fs::canonicalize(parent)) {
+        (Ok(target), Ok(parent)) => parent.starts_with(target),
+        _ => false,
+    }
+}
+
+/// Check if two entries look the same
+///
+/// Entries are given with their metadata and the original size of files.
+fn is_same_entry(a: (&Path, &Metadata, u64), b: (&Path, &Metadata, u64)) -> Result<bool> {
+    let ((a, a_metadata, a_len), (b, b_metadata, b_len)) = (a, b);
+    Ok(match (EntryKind::of(a_metadata), EntryKind::of(b_metadata)) {
+        (EntryKind::File, EntryKind::File) => {
+            a_len == b_len && is_same_file(a_metadata, b_metadata)
+        }
+        (EntryKind::Symlink, EntryKind::Symlink) => fs::read_link(a)? == fs::read_link(b)?,
+        (EntryKind::Fifo, EntryKind::Fifo) => true,
+        _ => false,
+    })
+}
+
+/// Copy a file, a symlink or a FIFO with its attributes
+fn copy_entry(from: &Path, to: &Path, metadata: &Metadata) -> Result<()> {
+    transcode_entry(from, Encoding::default(), to, Encoding::default(), metadata)
+}
+
+/// Copy an entry like `copy_entry`, changing the encoding of a file
+fn transcode_entry(
+    from: &Path,
+    from_encoding: Encoding,
+    to: &Path,
+    to_encoding: Encoding,
+    metadata: &Metadata,
+) -> Result<()> {
+    match EntryKind::of(metadata) {
+        EntryKind::Symlink => {
+            if to.symlink_metadata().is_ok() {
+                fs::remove_file(to)?;
+            }
+            symlink(&fs::read_link(from)?, to)?;
+        }
+        EntryKind::Fifo => {
+            if to.symlink_metadata().is_ok() {
+                fs::remove_file(to)?;
+            }
+            make_fifo(to, metadata)?;
+        }
+        _ => {
+            if to.symlink_metadata().is_ok() {
+                fs::remove_file(to)?;
+            }
+            transcode(from, from_encoding, to, to_encoding)?;
+        }
+    }
+    copy_attributes(from, to, metadata)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, to: &Path) -> Result<()> {
+    ::std::os::unix::fs::symlink(target, to)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, to: &Path) -> Result<()> {
+    ::std::os::windows::fs::symlink_file(target, to)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn make_fifo(path: &Path, metadata: &Metadata) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| ::std::io::Error::new(::std::io::ErrorKind::InvalidInput, e))?;
+    let mode = metadata.permissions().mode() & 0o7777;
+    if unsafe { ::libc::mkfifo(c_path.as_ptr(), mode as ::libc::mode_t) } != 0 {
+        return Err(::std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_fifo(_path: &Path, _metadata: &Metadata) -> Result<()> {
+    Ok(())
+}
+
+/// Check if two files look the same, rsync's quick check with permissions
+///
+/// Sizes are compared apart, as encoded files don't have their original one.
+fn is_same_file(a: &Metadata, b: &Metadata) -> bool {
+    a.permissions() == b.permissions() &&
+        FileTime::from_last_modification_time(a) == FileTime::from_last_modification_time(b)
+}
+
+/// Copy a file and keep its modification time
+///
+/// An existing destination is removed first so a hard linked copy is never
+/// written through.
+fn copy_file(from: &Path, to: &Path, metadata: &Metadata) -> Result<()> {
+    if to.symlink_metadata().is_ok() {
+        fs::remove_file(to)?;
+    }
+    fs::copy(from, to)?;
+    set_file_mtime(to, FileTime::from_last_modification_time(metadata))?;
+    Ok(())
+}
+
+/// Hard link `from` to `to`, copy it if links are not supported
+fn link_or_copy(from: &Path, to: &Path, metadata: &Metadata) -> Result<()> {
+    if fs::hard_link(from, to).is_err() {
+        copy_file(from, to, metadata)?;
+    }
+    Ok(())
+}
+
+/// Hard link a file of the destination to `to`, like `link_or_copy`
+fn link(from: &Path, to: &Path) -> Result<()> {
+    let metadata = from.metadata()?;
+    link_or_copy(from, to, &metadata)
+}
+
+/// Remove a file or a directory, symlinks are not followed
+pub fn remove_entry(path: &Path) -> Result<()> {
+    if !path.symlink_metadata()?.is_dir() {
+        fs::remove_file(path)?;
+        return Ok(());
+    }
+    if let Err(e) = fs::remove_dir_all(path) {
+        // Read-only directories are kept as is in backups
+        if e.kind() != ::std::io::ErrorKind::PermissionDenied {
+            return Err(e.into());
+        }
+        make_writable(path)?;
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+/// Allow removing content of directories
+fn make_writable(path: &Path) -> Result<()> {
+    let metadata = path.symlink_metadata()?;
+    if !metadata.is_dir() {
+        return Ok(());
+    }
+    let mut permissions = metadata.permissions();
+    if permissions.readonly() {
+        set_writable(&mut permissions);
+        fs::set_permissions(path, permissions)?;
+    }
+    for entry in fs::read_dir(path)? {
+        make_writable(&entry?.path())?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_writable(permissions: &mut fs::Permissions) {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = permissions.mode();
+    permissions.set_mode(mode | 0o200);
+}
+
+#[cfg(not(unix))]
+fn set_writable(permissions: &mut fs::Permissions) {
+    permissions.set_readonly(false);
+}
+
+/// Count files in a directory, 1 for a file
+pub fn count_files(path: &Path) -> Result<u64> {
+    if !path.symlink_metadata()?.is_dir() {
+        return Ok(1);
+    }
+    let mut result = 0;
+    for entry in fs::read_dir(path)? {
+        result += count_files(&entry?.path())?;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use tempfile::TempDir;
+    use test_utils::test_dir;
+
+    fn sync(from: &Path, to: &Path, base: Option<&Path>, rules: &Rules) -> SyncStats {
+        sync_all(from, to, base, rules, &Storage::default(), &mut Vec::new()).unwrap()
+    }
+
+    /// Test directory with a `from` tree to sync
+    fn sync_dir(name: &str) -> TempDir {
+        let tmp = test_dir(name);
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("from/sub")).unwrap();
+        fs::write(dir.join("from/a"), "a").unwrap();
+        fs::write(dir.join("from/sub/b"), "b").unwrap();
+        tmp
+    }
+
+    #[test]
+    fn sync_in_place() {
+        let tmp = sync_dir("sync-in-place");
+        let dir = tmp.path();
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        let stats = sync(&from, &to, None, &Rules::default());
+        assert_eq!((stats.added, stats.updated, stats.removed), (2, 0, 0));
+        let stats = sync(&from, &to, None, &Rules::default());
+        assert!(stats.is_empty());
+        fs::write(from.join("a"), "aa").unwrap();
+        fs::remove_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("c"), "c").unwrap();
+        let stats = sync(&from, &to, None, &Rules::default());
+        assert_eq!((stats.added, stats.updated, stats.removed), (1, 1, 1));
+        assert_eq!(fs::read_to_string(to.join("a")).unwrap(), "aa");
+        assert!(!to.join("sub").exists());
+    }
+
+    #[test]
+    fn sync_with_base() {
+        let tmp = sync_dir("sync-with-base");
+        let dir = tmp.path();
+        let (from, base, to) = (dir.join("from"), dir.join("base"), dir.join("to"));
+        sync(&from, &base, None, &Rules::default());
+        fs::write(from.join("a"), "aa").unwrap();
+        let stats = sync(&from, &to, Some(&base), &Rules::default());
+        assert_eq!((stats.added, stats.updated, stats.removed), (0, 1, 0));
+        assert_eq!(fs::read_to_string(base.join("a")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(to.join("a")).unwrap(), "aa");
+        assert_eq!(fs::read_to_string(to.join("sub/b")).unwrap(), "b");
+    }
+
+    #[test]
+    fn sync_with_rollback() {
+        let tmp = sync_dir("sync-with-rollback");
+        let dir = tmp.path();
+        let (from, to, rollback) = (dir.join("from"), dir.join("to"), dir.join("rollback"));
+        fs::create_dir_all(to.join("old")).unwrap();
+        fs::write(to.join("old/c"), "c").unwrap();
+        fs::write(to.join("a"), "old a").unwrap();
+        let mut added = Vec::new();
+        let stats = sync_all_with_rollback(
+            &from,
+            &to,
+            &rollback,
+            &Rules::default(),
+            &Storage::default(),
+            &mut added,
+        ).unwrap();
+        assert_eq!((stats.added, stats.updated, stats.removed), (1, 1, 1));
+        assert_eq!(added, vec![PathBuf::from("sub")]);
+        assert_eq!(fs::read_to_string(rollback.join("a")).unwrap(), "old a");
+        assert_eq!(fs::read_to_string(rollback.join("old/c")).unwrap(), "c");
+        assert!(!to.join("old").exists());
+    }
+
+    #[test]
+    fn plan_sync_test() {
+        let tmp = sync_dir("plan-sync");
+        let dir = tmp.path();
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::create_dir_all(to.join("old")).unwrap();
+        fs::write(to.join("old/c"), "c").unwrap();
+        fs::write(to.join("a"), "old a").unwrap();
+        let mut plan = plan_sync(&from, &to, &Rules::default(), &Storage::default()).unwrap();
+        plan.sort_by(|a, b| a.path.cmp(&b.path));
+        let expected = vec![
+            (Action::Overwrite, "a", 1),
+            (Action::Delete, "old/c", 1),
+            (Action::Create, "sub/b", 1),
+        ];
+        let plan = plan.into_iter()
+            .map(|p| (p.action, p.path.display().to_string(), p.size))
+            .collect::<Vec<(Action, String, u64)>>();
+        let expected = expected
+            .into_iter()
+            .map(|(a, p, s)| (a, p.to_string(), s))
+            .collect::<Vec<(Action, String, u64)>>();
+        assert_eq!(plan, expected);
+        assert_eq!(fs::read_to_string(to.join("a")).unwrap(), "old a");
+        assert!(!to.join("sub").exists());
+        assert_eq!(plan_remove(&to).unwrap().len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sync_hard_links() {
+        use std::os::unix::fs::MetadataExt;
+        let tmp = sync_dir("sync-hard-links");
+        let dir = tmp.path();
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        let ino = |p: PathBuf| p.metadata().unwrap().ino();
+        fs::hard_link(from.join("a"), from.join("sub/link")).unwrap();
+        assert_eq!(get_size(&from, &Rules::default()).unwrap(), 2);
+        let stats = sync(&from, &to, None, &Rules::default());
+        assert_eq!(stats.added, 3);
+        assert_eq!(ino(to.join("a")), ino(to.join("sub/link")));
+        let stats = sync(&from, &to, None, &Rules::default());
+        assert!(stats.is_empty());
+        let base = dir.join("base");
+        let stats = sync(&from, &base, Some(&to), &Rules::default());
+        assert!(stats.is_empty());
+        assert_eq!(ino(base.join("a")), ino(base.join("sub/link")));
+        // Broken links are recreated
+        fs::remove_file(to.join("sub/link")).unwrap();
+        let metadata = to.join("a").metadata().unwrap();
+        copy_entry(&to.join("a"), &to.join("sub/link"), &metadata).unwrap();
+        let stats = sync(&from, &to, None, &Rules::default());
+        assert_eq!(stats.updated, 1);
+        assert_eq!(ino(to.join("a")), ino(to.join("sub/link")));
+        let copy = dir.join("copy");
+        copy_dir(&from, &copy, &Rules::default()).unwrap();
+        assert_eq!(ino(copy.join("a")), ino(copy.join("sub/link")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sync_symlinks_and_fifos() {
+        use std::os::unix::fs::{symlink, FileTypeExt};
+        let tmp = sync_dir("sync-symlinks");
+        let dir = tmp.path();
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        symlink("a", from.join("link")).unwrap();
+        symlink(".", from.join("sub/loop")).unwrap();
+        make_fifo(&from.join("fifo"), &from.join("a").metadata().unwrap()).unwrap();
+        let stats = sync(&from, &to, None, &Rules::default());
+        assert_eq!((stats.added, stats.skipped), (5, 0));
+        assert_eq!(fs::read_link(to.join("link")).unwrap(), PathBuf::from("a"));
+        assert_eq!(fs::read_link(to.join("sub/loop")).unwrap(), PathBuf::from("."));
+        assert!(to.join("fifo").symlink_metadata().unwrap().file_type().is_fifo());
+        let stats = sync(&from, &to, None, &Rules::default());
+        assert!(stats.is_empty());
+        let mut rules = Rules::default();
+        rules.set_follow_symlinks(true);
+        let followed = dir.join("followed");
+        let stats = sync(&from, &followed, None, &rules);
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(fs::read_to_string(followed.join("link")).unwrap(), "a");
+        assert!(!followed.join("sub/loop").exists());
+    }
+}
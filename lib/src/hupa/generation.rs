@@ -0,0 +1,544 @@
+//! Generations of a hupa
+//!
+//! Each backup is stored in its own directory under `backup_dir()`, named by
+//! the unix timestamp of the backup. A `latest` file points to the most recent
+//! complete generation.
+//!
+//! A backup is first written to a staging directory next to `backup_dir()` and
+//! renamed to its generation only once complete, so a failed backup never
+//! leaves a broken generation behind.
+//!
+//! Mode and ownership of the files of a generation are recorded in a
+//! `<generation>.attributes` file next to it, and its manifest in a
+//! `<generation>.manifest` file. Generations whose files are compressed have
+//! a `<generation>.compression` file naming the compression, and encrypted
+//! ones a `<generation>.encryption` file with the salt of their key. The
+//! manifest of an encrypted generation is encrypted too. Generations of hupas
+//! with many origins have a `<generation>.origins` file, see `origins`. The
+//! stats of the origin files when the generation was made are kept in a
+//! `<generation>.stats` file, encrypted like the manifest.
+
+use encoding::Encoding;
+use encryption::Encryption;
+use error::*;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::*;
+
+/// Name of the file pointing to the latest generation
+pub const LATEST_POINTER: &str = "latest";
+/// Extension of attributes files
+const ATTRIBUTES_EXTENSION: &str = "attributes";
+/// Extension of manifest files
+const MANIFEST_EXTENSION: &str = "manifest";
+/// Extension of compression files
+const COMPRESSION_EXTENSION: &str = "compression";
+/// Extension of encryption files
+const ENCRYPTION_EXTENSION: &str = "encryption";
+/// Extension of origins files
+const ORIGINS_EXTENSION: &str = "origins";
+/// Extension of stats files
+const STATS_EXTENSION: &str = "stats";
+/// Extensions of files kept next to a generation
+const SIDECAR_EXTENSIONS: &[&str] = &[
+    ATTRIBUTES_EXTENSION,
+    MANIFEST_EXTENSION,
+    COMPRESSION_EXTENSION,
+    ENCRYPTION_EXTENSION,
+    ORIGINS_EXTENSION,
+    STATS_EXTENSION,
+];
+
+/// Generation to target during a restore
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GenerationTarget {
+    /// Most recent generation
+    Latest,
+    /// Most recent generation made at or before the timestamp
+    At(u64),
+    /// N-th generation before the latest, 0 is the latest
+    Nth(usize),
+}
+
+impl Hupa {
+    /// Get generations of this hupa, from oldest to newest
+    pub fn get_generations(&self) -> Result<Vec<u64>> {
+        let backup_dir = self.backup_dir();
+        let mut generations = Vec::new();
+        if !backup_dir.is_dir() {
+            return Ok(generations);
+        }
+        for entry in fs::read_dir(&backup_dir)? {
+            let entry = entry?;
+            if let Some(gen) = entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+                generations.push(gen);
+            }
+        }
+        generations.sort();
+        Ok(generations)
+    }
+
+    /// Read the latest pointer, `None` if it is missing or doesn't name a
+    /// generation
+    fn read_latest_pointer(&self) -> Option<u64> {
+        let pointer = self.backup_dir().join(LATEST_POINTER);
+        fs::read_to_string(pointer).ok()?.trim().parse().ok()
+    }
+
+    /// Get the latest generation
+    pub fn get_latest_generation(&self) -> Result<Option<u64>> {
+        let pointer = self.backup_dir().join(LATEST_POINTER);
+        if !pointer.is_file() {
+            return Ok(None);
+        }
+        let mut buf = String::new();
+        File::open(&pointer)?.read_to_string(&mut buf)?;
+        match buf.trim().parse() {
+            Ok(gen) => Ok(Some(gen)),
+            Err(_) => bail!(ErrorKind::InvalidGeneration(buf.trim().to_string())),
+        }
+    }
+
+    /// Resolve a generation target into a generation
+    pub fn resolve_generation(&self, target: GenerationTarget) -> Result<u64> {
+        let generations = self.get_generations()?;
+        let latest = self.get_latest_generation()?;
+        // Generations newer than the latest pointer are incomplete
+        let mut complete = generations
+            .into_iter()
+            .filter(|g| latest.map(|l| *g <= l).unwrap_or(false))
+            .collect::<Vec<u64>>();
+        complete.reverse();
+        let found = match target {
+            GenerationTarget::Latest => complete.first().cloned(),
+            GenerationTarget::At(t) => complete.iter().find(|g| **g <= t).cloned(),
+            GenerationTarget::Nth(n) => complete.get(n).cloned(),
+        };
+        match found {
+            Some(gen) => Ok(gen),
+            None => bail!(ErrorKind::MissingGeneration(format!("{:?}", target))),
+        }
+    }
+
+    /// Get directory of a generation
+    pub fn generation_dir(&self, generation: u64) -> PathBuf {
+        self.backup_dir().join(generation.to_string())
+    }
+
+    /// Get the attributes file of a generation
+    pub fn generation_attributes(&self, generation: u64) -> PathBuf {
+        self.generation_sidecar(generation, ATTRIBUTES_EXTENSION)
+    }
+
+    /// Get the manifest of a generation
+    pub fn generation_manifest(&self, generation: u64) -> PathBuf {
+        self.generation_sidecar(generation, MANIFEST_EXTENSION)
+    }
+
+    /// Get the file listing the origin entries of a generation
+    pub fn generation_origins(&self, generation: u64) -> PathBuf {
+        self.generation_sidecar(generation, ORIGINS_EXTENSION)
+    }
+
+    /// Get the compression of the files of a generation
+    pub fn generation_compression(&self, generation: u64) -> Result<Compression> {
+        let path = self.generation_sidecar(generation, COMPRESSION_EXTENSION);
+        if !path.is_file() {
+            return Ok(Compression::None);
+        }
+        fs::read_to_string(path)?.trim().parse()
+    }
+
+    /// Check if the files of a generation are encrypted
+    pub fn is_generation_encrypted(&self, generation: u64) -> bool {
+        self.generation_sidecar(generation, ENCRYPTION_EXTENSION)
+            .is_file()
+    }
+
+    /// Get the salt of the key of a generation, `None` if it isn't encrypted
+    pub(crate) fn generation_encryption(&self, generation: u64) -> Result<Option<Encryption>> {
+        if !self.is_generation_encrypted(generation) {
+            return Ok(None);
+        }
+        let path = self.generation_sidecar(generation, ENCRYPTION_EXTENSION);
+        Ok(Some(Encryption::read(path)?))
+    }
+
+    /// Get how the files of a generation are stored
+    ///
+    /// Fail with `WrongKey` if the key set in config isn't the one of the
+    /// generation.
+    pub(crate) fn generation_encoding(&self, generation: u64) -> Result<Encoding> {
+        let key = match self.generation_encryption(generation)? {
+            Some(encryption) => Some(encryption.unlock(self.get_secret()?)?),
+            None => None,
+        };
+        Ok(Encoding {
+            compression: self.generation_compression(generation)?,
+            key,
+        })
+    }
+
+    /// Read the manifest of a generation
+    pub(crate) fn read_generation_manifest(&self, generation: u64) -> Result<Manifest> {
+        let encoding = self.generation_encoding(generation)?;
+        Manifest::read_stored(self.generation_manifest(generation), encoding.sidecar())
+    }
+
+    /// Read the stats of the origin files of a generation, empty for
+    /// generations made before stats were kept
+    pub(crate) fn read_generation_stats(&self, generation: u64) -> Result<StatCache> {
+        let path = self.generation_sidecar(generation, STATS_EXTENSION);
+        if !path.is_file() {
+            return Ok(StatCache::default());
+        }
+        let encoding = self.generation_encoding(generation)?;
+        StatCache::read_stored(path, encoding.sidecar())
+    }
+
+    /// Get the encoding of a generation, with its manifest if encoded
+    pub(crate) fn read_storage(&self, generation: u64) -> Result<(Encoding, Option<Manifest>)> {
+        let encoding = self.generation_encoding(generation)?;
+        if encoding.is_plain() {
+            return Ok((encoding, None));
+        }
+        let path = self.generation_manifest(generation);
+        let manifest = Manifest::read_stored(path, encoding.sidecar())?;
+        Ok((encoding, Some(manifest)))
+    }
+
+    fn generation_sidecar(&self, generation: u64, extension: &str) -> PathBuf {
+        self.backup_dir().join(format!("{}.{}", generation, extension))
+    }
+
+    /// Get directory of the latest generation
+    pub fn latest_backup_dir(&self) -> Option<PathBuf> {
+        match self.get_latest_generation() {
+            Ok(Some(gen)) => Some(self.generation_dir(gen)),
+            _ => None,
+        }
+    }
+
+    /// Get the staging directory where a backup is written before being complete
+    pub fn staging_dir(&self) -> PathBuf {
+        self.backup_dir()
+            .with_file_name(format!(".{}.staging", self.get_name()))
+    }
+
+    /// Get the attributes file of the staging directory
+    pub(crate) fn staging_attributes(&self) -> PathBuf {
+        self.staging_sidecar(ATTRIBUTES_EXTENSION)
+    }
+
+    /// Get the manifest of the staging directory
+    pub(crate) fn staging_manifest(&self) -> PathBuf {
+        self.staging_sidecar(MANIFEST_EXTENSION)
+    }
+
+    /// Get the compression file of the staging directory
+    pub(crate) fn staging_compression(&self) -> PathBuf {
+        self.staging_sidecar(COMPRESSION_EXTENSION)
+    }
+
+    /// Get the encryption file of the staging directory
+    pub(crate) fn staging_encryption(&self) -> PathBuf {
+        self.staging_sidecar(ENCRYPTION_EXTENSION)
+    }
+
+    /// Get the origins file of the staging directory
+    pub(crate) fn staging_origins(&self) -> PathBuf {
+        self.staging_sidecar(ORIGINS_EXTENSION)
+    }
+
+    /// Get the stats file of the staging directory
+    pub(crate) fn staging_stats(&self) -> PathBuf {
+        self.staging_sidecar(STATS_EXTENSION)
+    }
+
+    fn staging_sidecar(&self, extension: &str) -> PathBuf {
+        self.backup_dir()
+            .with_file_name(format!(".{}.staging.{}", self.get_name(), extension))
+    }
+
+    /// Remove what is left by interrupted backups
+    ///
+    /// Delete the staging directory and generations newer than the latest one.
+    pub fn clean_staging(&self) -> Result<()> {
+        let staging_dir = self.staging_dir();
+        if staging_dir.symlink_metadata().is_ok() {
+            remove_all(&staging_dir)?;
+        }
+        for extension in SIDECAR_EXTENSIONS {
+            if self.staging_sidecar(extension).exists() {
+                remove_all(self.staging_sidecar(extension))?;
+            }
+        }
+        if self.is_legacy_backup()? {
+            return Ok(());
+        }
+        match self.get_latest_generation()? {
+            Some(latest) => {
+                for gen in self.get_generations()? {
+                    if gen > latest {
+                        self.remove_generation(gen)?;
+                    }
+                }
+            }
+            // The first backup was interrupted before pointing latest to it
+            None if self.backup_dir().is_dir() => remove_all(self.backup_dir())?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Move the staging directory to a new generation and mark it as latest
+    pub(crate) fn commit_staging(&self, generation: u64) -> Result<()> {
+        fs::create_dir_all(self.backup_dir())?;
+        for extension in SIDECAR_EXTENSIONS {
+            if self.staging_sidecar(extension).exists() {
+                fs::rename(
+                    self.staging_sidecar(extension),
+                    self.generation_sidecar(generation, extension),
+                )?;
+            }
+        }
+        fs::rename(self.staging_dir(), self.generation_dir(generation))?;
+        self.set_latest_generation(generation)
+    }
+
+    /// Delete oldest generations, keeping only the `keep` most recent ones
+    ///
+    /// The latest generation is always kept.
+    pub fn prune_generations(&self, keep: usize) -> Result<OperationResult> {
+        let pruned = self.prunable_generations(keep)?;
+        if pruned.is_empty() {
+            return Ok(OperationResult::NoChange);
+        }
+        for gen in pruned {
+            self.remove_generation(gen)?;
+        }
+        Ok(OperationResult::Change(SyncStats::default()))
+    }
+
+    /// Get the generations `prune_generations` deletes
+    ///
+    /// Generations newer than the latest are incomplete, they are neither
+    /// counted nor deleted.
+    pub(crate) fn prunable_generations(&self, keep: usize) -> Result<Vec<u64>> {
+        let latest = match self.get_latest_generation()? {
+            Some(latest) => latest,
+            None => return Ok(Vec::new()),
+        };
+        let mut complete = self.get_generations()?;
+        complete.retain(|g| *g <= latest);
+        let kept = keep.max(1).min(complete.len());
+        complete.truncate(complete.len() - kept);
+        Ok(complete)
+    }
+
+    /// Remove a generation with its sidecar files
+    fn remove_generation(&self, generation: u64) -> Result<()> {
+        for extension in SIDECAR_EXTENSIONS {
+            let sidecar = self.generation_sidecar(generation, extension);
+            if sidecar.exists() {
+                remove_all(sidecar)?;
+            }
+        }
+        remove_all(self.generation_dir(generation))
+    }
+
+    /// Get a new generation, always more recent than existing ones
+    pub(crate) fn new_generation(&self) -> Result<u64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let last = self.get_generations()?.last().cloned().unwrap_or(0);
+        Ok(if now > last { now } else { last + 1 })
+    }
+
+    /// Point latest to `generation`
+    pub(crate) fn set_latest_generation(&self, generation: u64) -> Result<()> {
+        let backup_dir = self.backup_dir();
+        let tmp = backup_dir.join(format!(".{}.tmp", LATEST_POINTER));
+        {
+            let mut f = File::create(&tmp)?;
+            f.write_all(generation.to_string().as_bytes())?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp, backup_dir.join(LATEST_POINTER))?;
+        Ok(())
+    }
+
+    /// Check if the backup was made before generations existed
+    ///
+    /// Such a backup has no latest pointer naming a generation, but any of its
+    /// files may have the name of a generation or of the pointer. Unlike an
+    /// interrupted first backup, it has no sidecar file of a generation, the
+    /// attributes file being moved first.
+    pub(crate) fn is_legacy_backup(&self) -> Result<bool> {
+        let backup_dir = self.backup_dir();
+        if backup_dir.symlink_metadata().is_err() || self.read_latest_pointer().is_some() {
+            return Ok(false);
+        }
+        if !backup_dir.is_dir() {
+            return Ok(true);
+        }
+        for entry in fs::read_dir(&backup_dir)? {
+            let path = entry?.path();
+            let is_sidecar = match (path.file_stem().and_then(|s| s.to_str()), path.extension()) {
+                (Some(stem), Some(extension)) => {
+                    stem.parse::<u64>().is_ok() &&
+                        SIDECAR_EXTENSIONS.iter().any(|e| extension == *e)
+                }
+                _ => false,
+            };
+            if is_sidecar {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Move a backup made before generations existed into a generation
+    pub(crate) fn migrate_legacy_backup(&self) -> Result<()> {
+        if !self.is_legacy_backup()? {
+            return Ok(());
+        }
+        let backup_dir = self.backup_dir();
+        let generation = backup_dir
+            .metadata()?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let tmp = backup_dir.with_file_name(format!(".{}.legacy", self.get_name()));
+        fs::rename(&backup_dir, &tmp)?;
+        fs::create_dir_all(&backup_dir)?;
+        fs::rename(&tmp, self.generation_dir(generation))?;
+        self.set_latest_generation(generation)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use tempfile::TempDir;
+    use test_utils::test_dir;
+
+    /// Hupa with empty generations, in a test directory removed when dropped
+    fn hupa_with_generations(name: &str, generations: &[u64], latest: u64) -> (TempDir, Hupa) {
+        let tmp = test_dir(name);
+        let hupa = Hupa::new(name, "", Vec::new(), tmp.path(), "/", false, Vec::new());
+        for gen in generations {
+            fs::create_dir_all(hupa.generation_dir(*gen)).unwrap();
+        }
+        hupa.set_latest_generation(latest).unwrap();
+        (tmp, hupa)
+    }
+
+    #[test]
+    fn resolve_generation_test() {
+        let (_tmp, hupa) = hupa_with_generations("resolve-generation", &[10, 20, 30, 40], 30);
+        assert_eq!(hupa.get_generations().unwrap(), vec![10, 20, 30, 40]);
+        assert_eq!(hupa.resolve_generation(GenerationTarget::Latest).unwrap(), 30);
+        assert_eq!(hupa.resolve_generation(GenerationTarget::At(25)).unwrap(), 20);
+        assert_eq!(hupa.resolve_generation(GenerationTarget::At(30)).unwrap(), 30);
+        assert_eq!(hupa.resolve_generation(GenerationTarget::Nth(2)).unwrap(), 10);
+        assert!(hupa.resolve_generation(GenerationTarget::At(5)).is_err());
+        assert!(hupa.resolve_generation(GenerationTarget::Nth(3)).is_err());
+    }
+
+    #[test]
+    fn backup_and_restore_generations() {
+        let tmp = test_dir("backup-generations");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("rc"), "first").unwrap();
+        let mut hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.set_rollback_parent(root.join("rollback"));
+        let vars = VarsHandler::new(Vec::new());
+        fs::write(origin.join("other"), "other").unwrap();
+        hupa.backup(&vars).unwrap();
+        fs::write(origin.join("rc"), "second").unwrap();
+        let stats = SyncStats {
+            added: 0,
+            updated: 1,
+            removed: 0,
+            skipped: 0,
+        };
+        assert_eq!(hupa.backup(&vars).unwrap(), OperationResult::Change(stats));
+        assert_eq!(hupa.get_generations().unwrap().len(), 2);
+        hupa.restore_at(&vars, GenerationTarget::Nth(1)).unwrap();
+        assert_eq!(fs::read_to_string(origin.join("rc")).unwrap(), "first");
+        hupa.restore(&vars).unwrap();
+        assert_eq!(fs::read_to_string(origin.join("rc")).unwrap(), "second");
+    }
+
+    #[test]
+    fn clean_interrupted_backup() {
+        let (_tmp, hupa) = hupa_with_generations("clean-staging", &[10, 20], 10);
+        fs::create_dir_all(hupa.staging_dir()).unwrap();
+        hupa.clean_staging().unwrap();
+        assert!(!hupa.staging_dir().exists());
+        assert_eq!(hupa.get_generations().unwrap(), vec![10]);
+    }
+
+    #[test]
+    fn prune_generations_test() {
+        let (_tmp, hupa) = hupa_with_generations("prune-generations", &[10, 20, 30, 40], 30);
+        // The incomplete generation 40 is neither counted nor deleted
+        hupa.prune_generations(2).unwrap();
+        assert_eq!(hupa.get_generations().unwrap(), vec![20, 30, 40]);
+        fs::write(hupa.generation_dir(20).join("rc"), "rc").unwrap();
+        assert_eq!(hupa.plan_prune_generations(0).unwrap().len(), 1);
+        // The latest generation is always kept
+        hupa.prune_generations(0).unwrap();
+        assert_eq!(hupa.get_generations().unwrap(), vec![30, 40]);
+        assert_eq!(hupa.prune_generations(1).unwrap(), OperationResult::NoChange);
+        assert!(hupa.new_generation().unwrap() > 40);
+    }
+
+    #[test]
+    fn migrate_legacy_with_numeric_names() {
+        let tmp = test_dir("migrate-legacy");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(origin.join("2024")).unwrap();
+        fs::write(origin.join("2024/notes"), "notes").unwrap();
+        let hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        // A backup made before generations, with files named like one and
+        // like the latest pointer
+        fs::create_dir_all(hupa.backup_dir().join("2024")).unwrap();
+        fs::write(hupa.backup_dir().join("2024/notes"), "old notes").unwrap();
+        fs::write(hupa.backup_dir().join(LATEST_POINTER), "todo").unwrap();
+        assert!(hupa.is_legacy_backup().unwrap());
+        hupa.migrate_legacy_backup().unwrap();
+        let generation = hupa.get_latest_generation().unwrap().unwrap();
+        let migrated = hupa.generation_dir(generation);
+        assert_eq!(fs::read_to_string(migrated.join("2024/notes")).unwrap(), "old notes");
+        assert_eq!(fs::read_to_string(migrated.join(LATEST_POINTER)).unwrap(), "todo");
+        assert!(!hupa.backup_dir().join("2024").exists());
+    }
+
+    #[test]
+    fn interrupted_first_backup() {
+        let tmp = test_dir("interrupted-first-backup");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("rc"), "rc").unwrap();
+        let hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        // Renamed into place, but latest wasn't pointed to it
+        fs::create_dir_all(hupa.generation_dir(10)).unwrap();
+        fs::write(hupa.generation_attributes(10), "").unwrap();
+        assert!(!hupa.is_legacy_backup().unwrap());
+        hupa.backup(&VarsHandler::new(Vec::new())).unwrap();
+        let latest = hupa.get_latest_generation().unwrap().unwrap();
+        assert_eq!(hupa.get_generations().unwrap(), vec![latest]);
+        assert!(!hupa.generation_attributes(10).exists());
+    }
+}
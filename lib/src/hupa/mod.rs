@@ -4,21 +4,42 @@
 //!
 //! They contain a path to their backup and their origin.
 
+mod generation;
+mod origins;
+mod plan;
+mod rollback;
 #[cfg(unix)]
 mod unix;
+mod verify;
+pub use self::generation::*;
+pub use self::origins::*;
+pub use self::rollback::*;
 #[cfg(unix)]
 pub use self::unix::*;
+pub use self::verify::*;
 
 use APP_INFO;
 use error::*;
-use fs_extra::{check_older, copy_dir, get_size};
+use attributes::write_attributes;
+use compression::Compression;
+use encoding::Encoding;
+use encryption::{Encryption, Key, Secret};
+use fs_extra::{check_older, copy_dir, get_size, remove_entry, sync_all,
+               sync_all_with_rollback, Storage, SyncStats};
+use manifest::{Manifest, ManifestDiff, StatCache};
+use requirement::Requirement;
+use rules::Rules;
+use template::{expand_path, expand_path_lossy};
 use std::cmp::{Eq, PartialEq, PartialOrd, Ord, Ordering};
+use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
 use vars::VarsHandler;
 
 /// Hupa is a class to handle a backup
 ///
+/// Every backup is kept as a generation in the backup directory.
+///
 /// # Arguments
 ///
 /// `name` - Name of the hupa, can be whatever the user wants
@@ -29,28 +50,46 @@ use vars::VarsHandler;
 ///
 /// `backup_parent` - Parent directory of the hupa, where it will be stored
 ///
-/// `origin_path` is the directory of the backed up directory
+/// `origins` - Backed up files or directories, only one is given to the
+/// constructor
+///
+/// Paths can be templates, like `~/.vimrc` or `$XDG_CONFIG_HOME/foo`, expanded
+/// each time they are used.
 ///
 /// `autobackup` - Daemon specific variable, enable autobackup.
 ///
-/// `needed_vars` - Vars needed to backup or restore this hupa
+/// `needed_vars` - Requirements on vars, all needed to backup or restore this hupa
+///
+/// `rules` - Rules selecting which files of the origin are backed up
+///
+/// `compression` - Compression of backed up files
+///
+/// `encrypted` - Encrypt backed up files, with the secret set in config
+///
+/// `rollback_parent` - Parent directory of rollback areas, in the app cache
+/// if not set
 #[derive(Clone, Debug)]
 pub struct Hupa {
     name: String,
     desc: String,
     category: Vec<String>,
     backup_parent: PathBuf,
-    origin_path: PathBuf,
+    origins: Vec<PathBuf>,
     autobackup: bool,
-    needed_vars: Vec<String>,
+    needed_vars: Vec<Requirement>,
+    rules: Rules,
+    compression: Compression,
+    encrypted: bool,
+    secret: Option<Secret>,
+    template_vars: VarsHandler,
+    rollback_parent: Option<PathBuf>,
 }
-// TODO replace path by string to allow vars
 
 /// Describe operation result
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum OperationResult {
-    /// A modification has occured
-    Change,
+    /// A modification has occured, with the count of changed files
+    Change(SyncStats),
     /// No change, folder/files is/are up to date
     NoChange,
 }
@@ -64,16 +103,22 @@ impl Hupa {
         backup_parent: P,
         origin_path: Q,
         autobackup: bool,
-        needed_vars: Vec<String>,
+        needed_vars: Vec<Requirement>,
     ) -> Hupa {
         Hupa {
             name: name.as_ref().to_string(),
             desc: desc.as_ref().to_string(),
             category: category,
             backup_parent: backup_parent.as_ref().to_owned(),
-            origin_path: origin_path.as_ref().to_path_buf(),
+            origins: vec![origin_path.as_ref().to_path_buf()],
             autobackup: autobackup,
             needed_vars: needed_vars,
+            rules: Rules::default(),
+            compression: Compression::None,
+            encrypted: false,
+            secret: None,
+            template_vars: VarsHandler::default(),
+            rollback_parent: None,
         }
     }
 
@@ -102,8 +147,8 @@ impl Hupa {
         category
     }
 
-    /// Get needed vars
-    pub fn get_needed_vars(&self) -> &Vec<String> {
+    /// Get requirements on vars
+    pub fn get_needed_vars(&self) -> &Vec<Requirement> {
         &self.needed_vars
     }
 
@@ -112,14 +157,67 @@ impl Hupa {
         ::app_dirs::app_root(::app_dirs::AppDataType::UserData, &APP_INFO).map_err(|e| e.into())
     }
 
-    /// Get backup parent path
-    pub fn get_backup_parent(&self) -> &PathBuf {
+    /// Get backup parent path, expanded
+    ///
+    /// Variables without value are kept as written, `check_templates` tells
+    /// which one is missing.
+    pub fn get_backup_parent(&self) -> PathBuf {
+        expand_path_lossy(&self.backup_parent, &self.template_vars)
+    }
+
+    /// Get backup parent path as written in metadata
+    pub fn get_backup_parent_template(&self) -> &PathBuf {
         &self.backup_parent
     }
 
-    /// Get origin path of this hupa
-    pub fn get_origin(&self) -> &PathBuf {
-        &self.origin_path
+    /// Get origin path of this hupa, expanded, the first one if it has many
+    pub fn get_origin(&self) -> PathBuf {
+        expand_path_lossy(&self.origins[0], &self.template_vars)
+    }
+
+    /// Get all origin paths of this hupa, expanded
+    pub fn get_origins(&self) -> Vec<PathBuf> {
+        self.origins
+            .iter()
+            .map(|o| expand_path_lossy(o, &self.template_vars))
+            .collect()
+    }
+
+    /// Get all origin paths of this hupa as written in metadata
+    pub fn get_origin_templates(&self) -> &[PathBuf] {
+        &self.origins
+    }
+
+    /// Check that every variable of the paths has a value
+    pub fn check_templates(&self) -> Result<()> {
+        expand_path(&self.backup_parent, &self.template_vars)?;
+        for origin in &self.origins {
+            expand_path(origin, &self.template_vars)?;
+        }
+        Ok(())
+    }
+
+    /// Get rules
+    pub fn get_rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    /// Get compression of backed up files
+    pub fn get_compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Get encryption state of backed up files
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Get the secret keys are derived from
+    fn get_secret(&self) -> Result<&Secret> {
+        match self.secret {
+            Some(ref s) => Ok(s),
+            None => bail!(ErrorKind::MissingKey(self.name.clone())),
+        }
     }
 
     /// Get autobackup state
@@ -158,11 +256,46 @@ impl Hupa {
         Ok(())
     }
 
-    /// Set needed vars
-    pub fn set_needed_vars(&mut self, needed_vars: Vec<String>) {
+    /// Set requirements on vars
+    pub fn set_needed_vars(&mut self, needed_vars: Vec<Requirement>) {
         self.needed_vars = needed_vars;
     }
 
+    /// Set rules
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+    }
+
+    /// Set compression of backed up files
+    ///
+    /// Existing generations keep their compression, the new one applies to
+    /// the next backups.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Set encryption state of backed up files
+    ///
+    /// Like compression, it applies to the next backups.
+    pub fn set_encrypted(&mut self, encrypted: bool) {
+        self.encrypted = encrypted;
+    }
+
+    /// Set the secret keys are derived from, usually the one of the config
+    pub fn set_secret(&mut self, secret: Option<Secret>) {
+        self.secret = secret;
+    }
+
+    /// Set vars usable in path templates, usually the ones of the vars file
+    pub fn set_template_vars(&mut self, vars_handler: VarsHandler) {
+        self.template_vars = vars_handler;
+    }
+
+    /// Set the parent directory of rollback areas
+    pub fn set_rollback_parent<P: AsRef<Path>>(&mut self, rollback_parent: P) {
+        self.rollback_parent = Some(rollback_parent.as_ref().to_path_buf());
+    }
+
     /// Set backup parent of the hupa
     ///
     /// May fail when creating and moving new files
@@ -176,9 +309,23 @@ impl Hupa {
         Ok(())
     }
 
-    /// Set origin path of the hupa
+    /// Change backup parent without moving files
+    pub(crate) fn relocate<P: AsRef<Path>>(&mut self, backup_parent: P) {
+        self.backup_parent = backup_parent.as_ref().to_path_buf();
+    }
+
+    /// Set origin path of the hupa, replacing all its origins
     pub fn set_origin_path<P: AsRef<Path>>(&mut self, origin_path: P) {
-        self.origin_path = origin_path.as_ref().to_path_buf();
+        self.origins = vec![origin_path.as_ref().to_path_buf()];
+    }
+
+    /// Set origin paths of the hupa
+    ///
+    /// Nothing is changed if `origins` is empty.
+    pub fn set_origins(&mut self, origins: Vec<PathBuf>) {
+        if !origins.is_empty() {
+            self.origins = origins;
+        }
     }
 
     /// Set autobackup state
@@ -188,7 +335,7 @@ impl Hupa {
 
     /// Return the backup directory of the hupa
     pub fn backup_dir(&self) -> PathBuf {
-        let mut hupas = self.backup_parent.clone();
+        let mut hupas = self.get_backup_parent();
         for sub_category in &self.category {
             hupas = hupas.join(sub_category);
         }
@@ -196,91 +343,317 @@ impl Hupa {
         hupas
     }
 
-    /// Get the backup size
+    /// Get the backup size, as stored on disk
     pub fn get_backup_size(&self) -> Result<u64> {
-        get_size(self.backup_dir()).map_err(|e| e.into())
+        get_size(self.backup_dir(), &Rules::default()).map_err(|e| e.into())
+    }
+
+    /// Get the size of the latest generation once restored
+    ///
+    /// Unlike `get_backup_size`, encoded files count for their original size
+    /// and files shared with older generations are counted.
+    pub fn get_backup_logical_size(&self) -> Result<u64> {
+        let generation = match self.get_latest_generation()? {
+            Some(g) => g,
+            None => return self.get_backup_size(),
+        };
+        if !self.generation_manifest(generation).is_file() {
+            return get_size(self.generation_dir(generation), &Rules::default());
+        }
+        Ok(self.read_generation_manifest(generation)?
+            .get_entries()
+            .values()
+            .filter(|e| e.kind == 'f')
+            .map(|e| e.size)
+            .sum())
     }
 
-    /// Get the origin size
+    /// Get the origin size, of all origins
     pub fn get_origin_size(&self) -> Result<u64> {
-        get_size(&self.origin_path).map_err(|e| e.into())
+        let mut size = 0;
+        for origin in self.get_origins() {
+            size += get_size(origin, &self.rules)?;
+        }
+        Ok(size)
+    }
+
+    /// Build the manifest of the origins, as they would be stored
+    ///
+    /// Only files whose stat changed since `cache` was built are hashed.
+    fn build_origins_manifest(&self, cache: &Manifest, stats: &StatCache) -> Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for entry in self.get_origin_entries() {
+            let cached = cache.subtree(&entry.entry);
+            let cached_stats = stats.subtree(&entry.entry);
+            let origin = Manifest::build_cached(&entry.origin, &self.rules, &cached, &cached_stats)?;
+            manifest.insert_prefixed(&entry.entry, origin);
+        }
+        Ok(manifest)
     }
 
-    /// Check if origin has changed
+    /// Get the stats of the files of the origins
+    fn build_origins_stats(&self) -> Result<StatCache> {
+        let mut stats = StatCache::default();
+        for entry in self.get_origin_entries() {
+            stats.insert_prefixed(&entry.entry, StatCache::build(&entry.origin, &self.rules)?);
+        }
+        Ok(stats)
+    }
+
+    /// Compare the origin with the manifest of a generation
+    ///
+    /// `None` if the generation has no manifest or other origin entries.
+    fn origin_diff(&self, generation: u64) -> Result<Option<ManifestDiff>> {
+        if !self.has_same_origin_entries(generation)? ||
+            !self.generation_manifest(generation).is_file()
+        {
+            return Ok(None);
+        }
+        let manifest = self.read_generation_manifest(generation)?;
+        let stats = self.read_generation_stats(generation)?;
+        let origin = self.build_origins_manifest(&manifest, &stats)?;
+        Ok(Some(manifest.diff(&origin)))
+    }
+
+    /// Check if origin has changed since the latest generation
+    ///
+    /// The origin is compared with the manifest of the generation, or with
+    /// its files for generations made before manifests.
     pub fn has_origin_changed(&self) -> Result<bool> {
-        let backup = self.backup_dir();
-        if get_size(&backup)? != get_size(&self.origin_path)? {
+        let generation = match self.get_latest_generation()? {
+            Some(g) => g,
+            None => return Ok(true),
+        };
+        if let Some(diff) = self.origin_diff(generation)? {
+            return Ok(!diff.is_empty());
+        }
+        if !self.has_same_origin_entries(generation)? {
+            return Ok(true);
+        }
+        let backup = self.generation_dir(generation);
+        if get_size(&backup, &Rules::default())? != get_size(self.get_origin(), &self.rules)? {
             return Ok(true);
         }
-        check_older(&self.origin_path, &backup)
+        check_older(self.get_origin(), &backup, &self.rules)
     }
 
-    /// Check if needed vars are activated
-    fn vars_check(&self, vars_handler: &VarsHandler) -> Result<()> {
-        for var in &self.needed_vars {
-            match vars_handler.get_var(var) {
-                Some(true) => {}
-                _ => bail!(ErrorKind::MissingNeededVar(var.clone())),
+    /// Check if the latest generation is stored with another compression or
+    /// encryption state
+    fn is_storage_changed(&self) -> Result<bool> {
+        Ok(match self.get_latest_generation()? {
+            Some(g) => {
+                self.generation_compression(g)? != self.compression ||
+                    self.is_generation_encrypted(g) != self.encrypted
             }
+            None => false,
+        })
+    }
+
+    /// Get the encryption of a new generation, with its key
+    ///
+    /// The salt of the previous generation is kept, so unchanged files are
+    /// still shared with it.
+    ///
+    /// `base` - Encoding of the previous generation
+    fn new_encryption(&self, previous: Option<u64>, base: Encoding) -> Result<Option<(Encryption, Key)>> {
+        if !self.encrypted {
+            return Ok(None);
+        }
+        if let (Some(gen), Some(key)) = (previous, base.key) {
+            if let Some(encryption) = self.generation_encryption(gen)? {
+                return Ok(Some((encryption, key)));
+            }
+        }
+        Ok(Some(Encryption::create(self.get_secret()?)?))
+    }
+
+    /// Check if requirements on vars are met
+    fn vars_check(&self, vars_handler: &VarsHandler) -> Result<()> {
+        for requirement in &self.needed_vars {
+            requirement.check(vars_handler)?;
         }
         Ok(())
     }
 
-    /// Backup hupa
+    /// Backup hupa in a new generation
     pub fn backup(&self, vars_handler: &VarsHandler) -> Result<OperationResult> {
         self.vars_check(vars_handler)?;
-        let backup_dir = self.backup_dir();
-        if !self.origin_path.exists() {
-            bail!(ErrorKind::MissingOrigin(
-                self.origin_path.display().to_string(),
-            ));
+        self.check_origins()?;
+        #[cfg(unix)] self.set_eid_backup()?;
+        self.migrate_legacy_backup()?;
+        self.clean_staging()?;
+        // A generation stored differently is rewritten even if the origin
+        // didn't change
+        let storage_changed = self.is_storage_changed()?;
+        let previous = self.get_latest_generation()?;
+        // Taken before copying, so an edit made meanwhile is seen next time
+        let origin_stats = self.build_origins_stats()?;
+        let diff = match previous {
+            Some(gen) => self.origin_diff(gen)?,
+            None => None,
+        };
+        let unchanged = match diff {
+            Some(ref diff) => diff.is_empty(),
+            None => self.has_origin_changed().ok() == Some(false),
+        };
+        if unchanged && !storage_changed {
+            return Ok(OperationResult::NoChange);
         }
-        if let Ok(false) = self.has_origin_changed() {
+        // Edits keeping size and modification time are only seen by hashes
+        let changed: HashSet<PathBuf> = diff.map(|d| d.changed)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let (base, manifest) = match previous {
+            Some(gen) => self.read_storage(gen)?,
+            None => (Encoding::default(), None),
+        };
+        let encryption = self.new_encryption(previous, base)?;
+        let to = Encoding {
+            compression: self.compression,
+            key: encryption.as_ref().map(|e| e.1),
+        };
+        let entries = self.get_origin_entries();
+        let previous_entries = match previous {
+            Some(gen) => self.generation_origin_entries(gen)?,
+            None => Vec::new(),
+        };
+        let generation = self.new_generation()?;
+        let mut stats = SyncStats::default();
+        let mut attributes = Vec::new();
+        for entry in &entries {
+            // Origins are compared with their own entry in the previous generation
+            let compared = previous.filter(|_| previous_entries.iter().any(|e| e.entry == entry.entry));
+            let entry_manifest = manifest.as_ref().map(|m| m.subtree(&entry.entry));
+            let entry_changed: HashSet<PathBuf> = changed
+                .iter()
+                .filter_map(|p| p.strip_prefix(&entry.entry).ok())
+                .map(Path::to_path_buf)
+                .collect();
+            let storage = Storage {
+                base,
+                to,
+                manifest: entry_manifest.as_ref(),
+                changed: Some(&entry_changed),
+                ..Storage::default()
+            };
+            let mut entry_attributes = Vec::new();
+            match sync_all(
+                &entry.origin,
+                entry.path_in(&self.staging_dir()),
+                compared.map(|g| entry.path_in(&self.generation_dir(g))).as_deref(),
+                &self.rules,
+                &storage,
+                &mut entry_attributes,
+            ) {
+                Ok(s) => stats += s,
+                Err(e) => {
+                    let _ = self.clean_staging();
+                    return Err(e);
+                }
+            }
+            attributes.extend(entry_attributes.into_iter().map(|(p, a)| (entry.relative(&p), a)));
+        }
+        // Only excluded files have changed
+        if stats.is_empty() && previous.is_some() && !storage_changed {
+            self.clean_staging()?;
             return Ok(OperationResult::NoChange);
         }
-        #[cfg(unix)] self.set_eid_backup()?;
-        // TODO add file sync
-        self.delete_backup()?;
-        if let Some(p) = backup_dir.parent() {
-            fs::create_dir_all(p)?;
+        write_attributes(self.staging_attributes(), &attributes)?;
+        let mut stored = Manifest::default();
+        for entry in &entries {
+            let path = entry.path_in(&self.staging_dir());
+            stored.insert_prefixed(&entry.entry, Manifest::build_stored(path, &Rules::default(), to)?);
+        }
+        stored.write_stored(self.staging_manifest(), to.sidecar())?;
+        origin_stats.write_stored(self.staging_stats(), to.sidecar())?;
+        self.write_staging_origins(&entries)?;
+        if self.compression.is_compressed() {
+            fs::write(self.staging_compression(), self.compression.name())?;
         }
-        copy_all(&self.origin_path, &backup_dir)?;
-        Ok(OperationResult::Change)
+        if let Some((ref encryption, _)) = encryption {
+            encryption.write(self.staging_encryption())?;
+        }
+        self.commit_staging(generation)?;
+        Ok(OperationResult::Change(stats))
     }
 
-    /// Restore hupa
+    /// Restore the latest generation of the hupa
     pub fn restore(&self, vars_handler: &VarsHandler) -> Result<OperationResult> {
+        self.restore_at(vars_handler, GenerationTarget::Latest)
+    }
+
+    /// Restore a specific generation of the hupa
+    ///
+    /// Files replaced or deleted in the origin are kept in the rollback area
+    /// until the next restore, see `undo_restore`.
+    pub fn restore_at(
+        &self,
+        vars_handler: &VarsHandler,
+        target: GenerationTarget,
+    ) -> Result<OperationResult> {
         self.vars_check(vars_handler)?;
+        self.check_templates()?;
         let backup_dir = self.backup_dir();
         if !backup_dir.exists() {
             bail!(ErrorKind::MissingBackup(backup_dir.display().to_string()));
         }
+        self.migrate_legacy_backup()?;
+        let generation = self.resolve_generation(target)?;
+        let generation_dir = self.generation_dir(generation);
+        let (encoding, manifest) = self.read_storage(generation)?;
+        let entries = self.generation_origin_entries(generation)?;
+        let rollback = self.start_rollback()?;
         #[cfg(unix)] self.set_eid_restore()?;
-        // TODO add file sync
-        self.delete_origin()?;
-        if let Some(p) = self.origin_path.parent() {
-            fs::create_dir_all(p)?;
+        let mut added = Vec::new();
+        let mut stats = SyncStats::default();
+        let mut result = Ok(());
+        for entry in &entries {
+            let entry_manifest = manifest.as_ref().map(|m| m.subtree(&entry.entry));
+            let storage = Storage {
+                from: encoding,
+                manifest: entry_manifest.as_ref(),
+                ..Storage::default()
+            };
+            let mut entry_added = Vec::new();
+            let entry_result = sync_all_with_rollback(
+                entry.path_in(&generation_dir),
+                &entry.origin,
+                entry.path_in(&rollback),
+                &self.rules,
+                &storage,
+                &mut entry_added,
+            );
+            added.extend(entry_added.iter().map(|p| entry.relative(p)));
+            match entry_result {
+                Ok(s) => stats += s,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        // Even a failed restore may have changed the origin, keep its rollback
+        let changed = result.is_err() || !stats.is_empty();
+        self.finish_rollback(changed, &entries, &added)?;
+        result?;
+        #[cfg(unix)] self.restore_owners(generation)?;
+        if stats.is_empty() {
+            return Ok(OperationResult::NoChange);
         }
-        copy_all(&backup_dir, &self.origin_path)?;
-        Ok(OperationResult::Change)
+        Ok(OperationResult::Change(stats))
     }
 
-    /// Delete backup
+    /// Delete backup with all its generations
     pub fn delete_backup(&self) -> Result<OperationResult> {
+        self.clean_staging()?;
         let backup_dir = self.backup_dir();
         if backup_dir.exists() {
             remove_all(&backup_dir)?;
         }
-        Ok(OperationResult::Change)
+        Ok(OperationResult::Change(SyncStats::default()))
     }
 
-    /// Delete origin
-    pub fn delete_origin(&self) -> Result<()> {
-        if self.origin_path.exists() {
-            remove_all(&self.origin_path)?;
-        }
-        Ok(())
-    }
 }
 
 impl PartialEq<Hupa> for Hupa {
@@ -319,7 +692,7 @@ fn copy_all<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
         fs::copy(from, to)?;
     } else if from.is_dir() {
         fs::create_dir_all(&to)?;
-        copy_dir(from, to)?;
+        copy_dir(from, to, &Rules::default())?;
     }
     Ok(())
 }
@@ -340,18 +713,13 @@ fn move_all<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
 ///
 /// `path` - path to the file or directory to remove
 fn remove_all<P: AsRef<Path>>(path: P) -> Result<()> {
-    let path = path.as_ref();
-    if path.is_file() {
-        fs::remove_file(&path)?;
-    } else {
-        fs::remove_dir_all(&path)?;
-    }
-    Ok(())
+    remove_entry(path.as_ref())
 }
 
 #[cfg(test)]
 mod unit_tests {
     use super::*;
+    use test_utils::test_dir;
 
     fn set_of_hupas() -> Vec<Hupa> {
         vec![
@@ -397,4 +765,182 @@ mod unit_tests {
         assert!(hupas[0] > hupas[3]);
         assert!(hupas[0] > hupas[4]);
     }
+
+    #[test]
+    fn backup_with_rules() {
+        let tmp = test_dir("backup-rules");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(origin.join("cache")).unwrap();
+        fs::write(origin.join("rc"), "rc").unwrap();
+        fs::write(origin.join("cache/data"), "data").unwrap();
+        fs::write(origin.join("big"), "0123456789").unwrap();
+        let mut hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.set_rollback_parent(root.join("rollback"));
+        hupa.set_rules(Rules::new(&[], &["cache"], Some(5), false).unwrap());
+        let vars = VarsHandler::new(Vec::new());
+        hupa.backup(&vars).unwrap();
+        let backup = hupa.latest_backup_dir().unwrap();
+        assert!(backup.join("rc").exists());
+        assert!(!backup.join("cache").exists());
+        assert!(!backup.join("big").exists());
+        assert_eq!(hupa.get_origin_size().unwrap(), 2);
+        fs::write(origin.join("cache/data"), "changed").unwrap();
+        assert_eq!(hupa.backup(&vars).unwrap(), OperationResult::NoChange);
+        hupa.restore(&vars).unwrap();
+        assert!(origin.join("cache/data").exists());
+        assert!(origin.join("big").exists());
+    }
+
+    #[test]
+    fn template_paths() {
+        let tmp = test_dir("template-paths");
+        let root = tmp.path();
+        fs::create_dir_all(root.join("origin")).unwrap();
+        fs::write(root.join("origin/rc"), "rc").unwrap();
+        let mut hupa = Hupa::new(
+            "rc",
+            "",
+            Vec::new(),
+            "$hupa_test_root/backup",
+            "${hupa_test_root}/origin",
+            false,
+            Vec::new(),
+        );
+        hupa.set_rollback_parent(root.join("rollback"));
+        let vars = VarsHandler::new(Vec::new());
+        match hupa.backup(&vars) {
+            Err(Error(ErrorKind::UndefinedVar(ref v), _)) if v == "hupa_test_root" => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let mut template_vars = VarsHandler::new(Vec::new());
+        template_vars.add_var(("hupa_test_root".to_string(), root.display().to_string().into()));
+        hupa.set_template_vars(template_vars);
+        assert_eq!(hupa.get_origin(), root.join("origin"));
+        hupa.backup(&vars).unwrap();
+        assert!(root.join("backup/rc").is_dir());
+        fs::write(root.join("origin/rc"), "edited").unwrap();
+        hupa.restore(&vars).unwrap();
+        assert_eq!(fs::read_to_string(root.join("origin/rc")).unwrap(), "rc");
+        assert_eq!(
+            hupa.get_origin_templates()[0],
+            PathBuf::from("${hupa_test_root}/origin")
+        );
+    }
+
+    #[test]
+    fn compressed_backup() {
+        let tmp = test_dir("compressed-backup");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(origin.join("sub")).unwrap();
+        let content = "compressed ".repeat(100);
+        fs::write(origin.join("a"), &content).unwrap();
+        fs::write(origin.join("sub/b"), "b").unwrap();
+        let mut hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.set_rollback_parent(root.join("rollback"));
+        hupa.set_compression(Compression::Gzip);
+        let vars = VarsHandler::new(Vec::new());
+        hupa.backup(&vars).unwrap();
+        let gen = hupa.get_latest_generation().unwrap().unwrap();
+        assert_eq!(hupa.generation_compression(gen).unwrap(), Compression::Gzip);
+        assert!(fs::metadata(hupa.generation_dir(gen).join("a")).unwrap().len() < 1100);
+        assert_eq!(hupa.get_backup_logical_size().unwrap(), 1101);
+        assert!(!hupa.has_origin_changed().unwrap());
+        assert!(hupa.verify().unwrap().is_ok());
+        // A new compression applies to the next generation
+        hupa.set_compression(Compression::Zstd);
+        fs::write(origin.join("sub/b"), "c").unwrap();
+        let stats = SyncStats {
+            added: 0,
+            updated: 1,
+            removed: 0,
+            skipped: 0,
+        };
+        assert_eq!(hupa.backup(&vars).unwrap(), OperationResult::Change(stats));
+        assert!(hupa.verify().unwrap().is_ok());
+        fs::write(origin.join("a"), "edited").unwrap();
+        fs::remove_dir_all(origin.join("sub")).unwrap();
+        hupa.restore(&vars).unwrap();
+        assert_eq!(fs::read_to_string(origin.join("a")).unwrap(), content);
+        assert_eq!(fs::read_to_string(origin.join("sub/b")).unwrap(), "c");
+        assert!(hupa.plan_restore(&vars).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn encrypted_backup() {
+        use std::os::unix::fs::MetadataExt;
+        let tmp = test_dir("encrypted-backup");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("a"), "secret").unwrap();
+        fs::write(origin.join("b"), "b").unwrap();
+        let mut hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.set_rollback_parent(root.join("rollback"));
+        hupa.set_encrypted(true);
+        let vars = VarsHandler::new(Vec::new());
+        match hupa.backup(&vars) {
+            Err(Error(ErrorKind::MissingKey(_), _)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        hupa.set_secret(Some(Secret::Passphrase("passphrase".to_string())));
+        hupa.backup(&vars).unwrap();
+        let gen = hupa.get_latest_generation().unwrap().unwrap();
+        assert!(hupa.is_generation_encrypted(gen));
+        let stored = fs::read(hupa.generation_dir(gen).join("a")).unwrap();
+        assert!(!stored.windows(6).any(|w| w == b"secret"));
+        assert!(Manifest::read(hupa.generation_manifest(gen)).is_err());
+        assert!(!hupa.has_origin_changed().unwrap());
+        assert!(hupa.verify().unwrap().is_ok());
+        // Unchanged files are shared with the previous generation
+        fs::write(origin.join("b"), "c").unwrap();
+        hupa.backup(&vars).unwrap();
+        let latest = hupa.get_latest_generation().unwrap().unwrap();
+        assert_eq!(hupa.get_backup_logical_size().unwrap(), 7);
+        let first = fs::metadata(hupa.generation_dir(gen).join("a")).unwrap();
+        let second = fs::metadata(hupa.generation_dir(latest).join("a")).unwrap();
+        assert_eq!(first.ino(), second.ino());
+        fs::write(origin.join("a"), "edited").unwrap();
+        hupa.restore(&vars).unwrap();
+        assert_eq!(fs::read_to_string(origin.join("a")).unwrap(), "secret");
+        hupa.set_secret(Some(Secret::Passphrase("wrong".to_string())));
+        match hupa.restore(&vars) {
+            Err(Error(ErrorKind::WrongKey, _)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match hupa.verify() {
+            Err(Error(ErrorKind::WrongKey, _)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn origin_changes_from_manifest() {
+        use filetime::{set_file_mtime, FileTime};
+        let tmp = test_dir("origin-changes");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("a"), "a").unwrap();
+        fs::write(origin.join("b"), "b").unwrap();
+        let mtime = FileTime::from_unix_time(1000, 0);
+        set_file_mtime(origin.join("a"), mtime).unwrap();
+        let hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.backup(&VarsHandler::new(Vec::new())).unwrap();
+        assert!(!hupa.has_origin_changed().unwrap());
+        // An edit keeping size and modification time is seen and backed up
+        fs::write(origin.join("a"), "c").unwrap();
+        set_file_mtime(origin.join("a"), mtime).unwrap();
+        assert!(hupa.has_origin_changed().unwrap());
+        hupa.backup(&VarsHandler::new(Vec::new())).unwrap();
+        let backup = hupa.latest_backup_dir().unwrap();
+        assert_eq!(fs::read_to_string(backup.join("a")).unwrap(), "c");
+        assert!(!hupa.has_origin_changed().unwrap());
+        set_file_mtime(origin.join("a"), FileTime::from_unix_time(2000, 0)).unwrap();
+        assert!(hupa.has_origin_changed().unwrap());
+        fs::remove_file(origin.join("b")).unwrap();
+        assert!(hupa.has_origin_changed().unwrap());
+    }
 }
@@ -0,0 +1,275 @@
+//! Origins of a hupa
+//!
+//! A hupa with a single origin stores it as the whole generation. A hupa with
+//! many origins stores each of them in an entry of the generation named after
+//! the origin, and lists entries in a `<generation>.origins` file so they are
+//! restored to their respective places. Paths of the list are escaped like in
+//! manifests.
+
+use error::*;
+use fs_extra::join;
+use manifest::{parse_path, path_field, split_escaped};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use super::*;
+
+/// Origin with its entry in generations
+#[derive(Clone, Debug, PartialEq)]
+pub struct OriginEntry {
+    /// Path of the entry relative to a generation, empty when the hupa has a
+    /// single origin
+    pub entry: PathBuf,
+    /// Backed up path
+    pub origin: PathBuf,
+}
+
+impl OriginEntry {
+    /// Get the path of the entry in a generation
+    pub fn path_in(&self, generation_dir: &Path) -> PathBuf {
+        join(generation_dir, &self.entry)
+    }
+
+    /// Get the path of a file of the entry, relative to the generation
+    pub fn relative(&self, relative: &Path) -> PathBuf {
+        join(&self.entry, relative)
+    }
+}
+
+impl Hupa {
+    /// Get the entries of the origins in new generations
+    ///
+    /// Entries are named after the file name of their origin as written, so
+    /// they are the same on every machine, a suffix being added to the same
+    /// names.
+    pub fn get_origin_entries(&self) -> Vec<OriginEntry> {
+        if self.origins.len() == 1 {
+            return self.single_origin_entry();
+        }
+        let mut entries: Vec<OriginEntry> = Vec::new();
+        for (template, origin) in self.origins.iter().zip(self.get_origins()) {
+            let name = match template.file_name() {
+                Some(n) => n.to_string_lossy().to_string(),
+                None => "root".to_string(),
+            };
+            let mut entry = PathBuf::from(&name);
+            let mut i = 1;
+            while entries.iter().any(|e| e.entry == entry) {
+                i += 1;
+                entry = PathBuf::from(format!("{}-{}", name, i));
+            }
+            entries.push(OriginEntry {
+                entry,
+                origin,
+            });
+        }
+        entries
+    }
+
+    /// Get the entries of the origins in a generation
+    ///
+    /// Entries are restored to the origin of the same entry in the hupa, or
+    /// to the one they were backed up from if the hupa doesn't have it
+    /// anymore.
+    pub fn generation_origin_entries(&self, generation: u64) -> Result<Vec<OriginEntry>> {
+        let path = self.generation_origins(generation);
+        if !path.is_file() {
+            return Ok(self.single_origin_entry());
+        }
+        let current = self.get_origin_entries();
+        let mut entries = read_origin_entries(path)?;
+        for entry in &mut entries {
+            if let Some(c) = current.iter().find(|c| c.entry == entry.entry) {
+                entry.origin = c.origin.clone();
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Get the entry of the first origin, stored as the whole generation
+    pub(crate) fn single_origin_entry(&self) -> Vec<OriginEntry> {
+        vec![
+            OriginEntry {
+                entry: PathBuf::new(),
+                origin: self.get_origin(),
+            },
+        ]
+    }
+
+    /// Write the entries of the origins next to the staging directory, only
+    /// when the hupa has many origins
+    pub(crate) fn write_staging_origins(&self, entries: &[OriginEntry]) -> Result<()> {
+        if entries.len() == 1 && entries[0].entry.as_os_str().is_empty() {
+            return Ok(());
+        }
+        write_origin_entries(self.staging_origins(), entries)
+    }
+
+    /// Check if the origins are stored in a generation as they would be now
+    pub(crate) fn has_same_origin_entries(&self, generation: u64) -> Result<bool> {
+        let entries = self.generation_origin_entries(generation)?;
+        let current = self.get_origin_entries();
+        Ok(entries.len() == current.len() &&
+            entries.iter().zip(&current).all(|(a, b)| a.entry == b.entry))
+    }
+
+    /// Find the entry a file of a generation belongs to, with the file path
+    /// relative to the entry
+    pub(crate) fn split_entry<'a>(
+        entries: &'a [OriginEntry],
+        relative: &Path,
+    ) -> Option<(&'a OriginEntry, PathBuf)> {
+        entries.iter().find_map(|e| {
+            relative
+                .strip_prefix(&e.entry)
+                .ok()
+                .map(|r| (e, r.to_path_buf()))
+        })
+    }
+
+    /// Delete every origin
+    pub fn delete_origin(&self) -> Result<()> {
+        for origin in self.get_origins() {
+            if origin.exists() {
+                remove_all(&origin)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every origin can be expanded and exists
+    pub(crate) fn check_origins(&self) -> Result<()> {
+        self.check_templates()?;
+        for origin in self.get_origins() {
+            if !origin.exists() {
+                bail!(ErrorKind::MissingOrigin(origin.display().to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read entries written by `write_origin_entries`
+pub(crate) fn read_origin_entries<P: AsRef<Path>>(path: P) -> Result<Vec<OriginEntry>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let (escaped, fields) = split_escaped(&line);
+        let mut split = fields.splitn(2, '\t');
+        let entry = split.next().and_then(|e| parse_path(e, escaped));
+        let origin = split.next().and_then(|o| parse_path(o, escaped));
+        match (entry, origin) {
+            (Some(entry), Some(origin)) => entries.push(OriginEntry { entry, origin }),
+            _ => bail!(ErrorKind::InvalidOrigins(line.clone())),
+        }
+    }
+    Ok(entries)
+}
+
+/// Write entries, one by line with their origin
+pub(crate) fn write_origin_entries<P: AsRef<Path>>(path: P, entries: &[OriginEntry]) -> Result<()> {
+    let mut f = File::create(path)?;
+    for entry in entries {
+        let (entry_prefix, entry_path) = path_field(&entry.entry);
+        let (origin_prefix, origin_path) = path_field(&entry.origin);
+        // A path which needs no escaping is the same escaped
+        let prefix = if entry_prefix.is_empty() { origin_prefix } else { entry_prefix };
+        writeln!(f, "{}{}\t{}", prefix, entry_path, origin_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use test_utils::test_dir;
+
+    #[test]
+    fn origin_entries() {
+        let mut hupa = Hupa::new("vim", "", Vec::new(), "/backup", "/home/.vimrc", false, Vec::new());
+        assert_eq!(hupa.get_origin_entries()[0].entry, PathBuf::new());
+        hupa.set_origins(vec![
+            PathBuf::from("/home/.vimrc"),
+            PathBuf::from("/home/.vim"),
+            PathBuf::from("/other/.vim"),
+        ]);
+        let entries = hupa.get_origin_entries()
+            .into_iter()
+            .map(|e| e.entry)
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(
+            entries,
+            vec![
+                PathBuf::from(".vimrc"),
+                PathBuf::from(".vim"),
+                PathBuf::from(".vim-2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn many_origins_backup() {
+        use std::fs;
+        let tmp = test_dir("many-origins");
+        let root = tmp.path();
+        fs::create_dir_all(root.join("vim")).unwrap();
+        fs::write(root.join(".vimrc"), "set nu").unwrap();
+        fs::write(root.join("vim/plugin"), "plugin").unwrap();
+        let mut hupa = Hupa::new("vim", "", Vec::new(), root.join("backup"), root.join(".vimrc"), false, Vec::new());
+        hupa.set_rollback_parent(root.join("rollback"));
+        let vars = VarsHandler::new(Vec::new());
+        hupa.backup(&vars).unwrap();
+        hupa.set_origins(vec![root.join(".vimrc"), root.join("vim")]);
+        assert!(hupa.has_origin_changed().unwrap());
+        hupa.backup(&vars).unwrap();
+        let gen = hupa.get_latest_generation().unwrap().unwrap();
+        let generation_dir = hupa.generation_dir(gen);
+        assert_eq!(fs::read_to_string(generation_dir.join(".vimrc")).unwrap(), "set nu");
+        assert_eq!(fs::read_to_string(generation_dir.join("vim/plugin")).unwrap(), "plugin");
+        assert!(!hupa.has_origin_changed().unwrap());
+        assert!(hupa.verify().unwrap().is_ok());
+        fs::write(root.join(".vimrc"), "edited").unwrap();
+        fs::write(root.join("vim/new"), "new").unwrap();
+        assert_eq!(hupa.plan_restore(&vars).unwrap().len(), 2);
+        hupa.restore(&vars).unwrap();
+        assert_eq!(fs::read_to_string(root.join(".vimrc")).unwrap(), "set nu");
+        assert!(!root.join("vim/new").exists());
+        hupa.undo_restore().unwrap();
+        assert_eq!(fs::read_to_string(root.join(".vimrc")).unwrap(), "edited");
+        assert!(root.join("vim/new").exists());
+    }
+
+    #[test]
+    fn origin_entries_escaped() {
+        let tmp = test_dir("origins-escaped");
+        let mut entries = vec![
+            OriginEntry {
+                entry: PathBuf::from("tab\tname"),
+                origin: PathBuf::from("/home/tab\tname"),
+            },
+            OriginEntry {
+                entry: PathBuf::from("new\nline"),
+                origin: PathBuf::from("C:\\new\nline"),
+            },
+            OriginEntry {
+                entry: PathBuf::from("plain"),
+                origin: PathBuf::from("/home/back\\slash"),
+            },
+        ];
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+            entries.push(OriginEntry {
+                entry: PathBuf::from(OsStr::from_bytes(b"latin\xe9")),
+                origin: PathBuf::from(OsStr::from_bytes(b"/home/latin\xe9")),
+            });
+        }
+        let path = tmp.path().join("origins");
+        write_origin_entries(&path, &entries).unwrap();
+        assert_eq!(read_origin_entries(&path).unwrap(), entries);
+        // Lists written before escaping are read as they are
+        ::std::fs::write(&path, "a\\b\t/home/a\\b\n").unwrap();
+        assert_eq!(read_origin_entries(&path).unwrap()[0].origin, PathBuf::from("/home/a\\b"));
+    }
+}
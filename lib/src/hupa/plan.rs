@@ -0,0 +1,196 @@
+//! Dry-run of hupa operations
+//!
+//! Plans list every file an operation would create, overwrite or delete,
+//! without touching disk.
+
+use error::*;
+use encoding::Encoding;
+use fs_extra::{join, plan_remove, plan_sync, PlannedAction, Storage};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use super::*;
+
+impl Hupa {
+    /// Plan a backup against the latest generation
+    ///
+    /// Paths are relative to the generation. Every file is written when the
+    /// latest generation is stored with another compression or encryption.
+    pub fn plan_backup(&self, vars_handler: &VarsHandler) -> Result<Vec<PlannedAction>> {
+        self.vars_check(vars_handler)?;
+        self.check_origins()?;
+        let storage_changed = self.is_storage_changed()?;
+        if let Ok(false) = self.has_origin_changed() {
+            if !storage_changed {
+                return Ok(Vec::new());
+            }
+        }
+        let new_dir = self.generation_dir(self.new_generation()?);
+        let (previous, encoding, manifest, previous_entries) = match self.get_latest_generation()? {
+            Some(gen) => {
+                let (encoding, manifest) = self.read_storage(gen)?;
+                let entries = self.generation_origin_entries(gen)?;
+                (self.generation_dir(gen), encoding, manifest, entries)
+            }
+            None => (new_dir.clone(), Encoding::default(), None, Vec::new()),
+        };
+        let changed = match self.get_latest_generation()? {
+            Some(gen) => self.origin_diff(gen)?.map(|d| d.changed).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let mut plan = Vec::new();
+        for entry in self.get_origin_entries() {
+            // Origins missing from the previous generation are entirely created
+            let compared = previous_entries.iter().any(|e| e.entry == entry.entry);
+            let to = if compared && !storage_changed {
+                entry.path_in(&previous)
+            } else {
+                entry.path_in(&new_dir)
+            };
+            let entry_manifest = manifest.as_ref().map(|m| m.subtree(&entry.entry));
+            let entry_changed: HashSet<PathBuf> = changed
+                .iter()
+                .filter_map(|p| p.strip_prefix(&entry.entry).ok())
+                .map(Path::to_path_buf)
+                .collect();
+            let storage = Storage {
+                to: encoding,
+                manifest: entry_manifest.as_ref(),
+                changed: Some(&entry_changed),
+                ..Storage::default()
+            };
+            for mut action in plan_sync(&entry.origin, to, &self.rules, &storage)? {
+                action.path = entry.relative(&action.path);
+                plan.push(action);
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Plan a restore of the latest generation, paths are relative to the origin
+    pub fn plan_restore(&self, vars_handler: &VarsHandler) -> Result<Vec<PlannedAction>> {
+        self.plan_restore_at(vars_handler, GenerationTarget::Latest)
+    }
+
+    /// Plan a restore of a specific generation, paths are relative to the origin
+    ///
+    /// Paths are absolute when the generation has many origins.
+    pub fn plan_restore_at(
+        &self,
+        vars_handler: &VarsHandler,
+        target: GenerationTarget,
+    ) -> Result<Vec<PlannedAction>> {
+        self.vars_check(vars_handler)?;
+        let backup_dir = self.backup_dir();
+        if !backup_dir.exists() {
+            bail!(ErrorKind::MissingBackup(backup_dir.display().to_string()));
+        }
+        // A legacy backup is read in place, without migrating it
+        let (generation_dir, encoding, manifest, entries) =
+            if target == GenerationTarget::Latest && self.is_legacy_backup()? {
+                (backup_dir, Encoding::default(), None, self.single_origin_entry())
+            } else {
+                let gen = self.resolve_generation(target)?;
+                let (encoding, manifest) = self.read_storage(gen)?;
+                let entries = self.generation_origin_entries(gen)?;
+                (self.generation_dir(gen), encoding, manifest, entries)
+            };
+        let mut plan = Vec::new();
+        for entry in &entries {
+            let entry_manifest = manifest.as_ref().map(|m| m.subtree(&entry.entry));
+            let storage = Storage {
+                from: encoding,
+                manifest: entry_manifest.as_ref(),
+                ..Storage::default()
+            };
+            let from = entry.path_in(&generation_dir);
+            for mut action in plan_sync(from, &entry.origin, &self.rules, &storage)? {
+                if entries.len() > 1 {
+                    action.path = join(&entry.origin, &action.path);
+                }
+                plan.push(action);
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Plan the deletion of the backup, paths are relative to `backup_dir()`
+    pub fn plan_delete_backup(&self) -> Result<Vec<PlannedAction>> {
+        plan_remove(self.backup_dir())
+    }
+
+    /// Plan the pruning of generations, paths are relative to `backup_dir()`
+    pub fn plan_prune_generations(&self, keep: usize) -> Result<Vec<PlannedAction>> {
+        let mut plan = Vec::new();
+        for gen in self.prunable_generations(keep)? {
+            for mut action in plan_remove(self.generation_dir(gen))? {
+                action.path = PathBuf::from(gen.to_string()).join(action.path);
+                plan.push(action);
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Plan the deletion of the rollback area, paths are relative to `rollback_dir()`
+    pub fn plan_delete_rollback(&self) -> Result<Vec<PlannedAction>> {
+        plan_remove(self.rollback_dir()?)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use fs_extra::Action;
+    use std::fs;
+    use test_utils::test_dir;
+
+    #[test]
+    fn plan_does_not_touch_disk() {
+        let tmp = test_dir("plan");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("rc"), "first").unwrap();
+        let mut hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.set_rollback_parent(root.join("rollback"));
+        let vars = VarsHandler::new(Vec::new());
+        let plan = hupa.plan_backup(&vars).unwrap();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].action, Action::Create);
+        assert!(!hupa.backup_dir().exists());
+        hupa.backup(&vars).unwrap();
+        fs::write(origin.join("rc"), "second").unwrap();
+        let plan = hupa.plan_restore(&vars).unwrap();
+        assert_eq!(plan[0].action, Action::Overwrite);
+        assert_eq!(plan[0].path, PathBuf::from("rc"));
+        assert_eq!(fs::read_to_string(origin.join("rc")).unwrap(), "second");
+        assert!(!hupa.has_rollback());
+        // Latest pointer, rc, the attributes, manifest and stats files
+        assert_eq!(hupa.plan_delete_backup().unwrap().len(), 5);
+        assert!(hupa.plan_prune_generations(1).unwrap().is_empty());
+        // Errors other than a legacy backup are reported
+        match hupa.plan_restore_at(&vars, GenerationTarget::At(0)) {
+            Err(Error(ErrorKind::MissingGeneration(_), _)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn plan_storage_change() {
+        use compression::Compression;
+        let tmp = test_dir("plan-storage");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("rc"), "rc").unwrap();
+        let mut hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        let vars = VarsHandler::new(Vec::new());
+        hupa.backup(&vars).unwrap();
+        assert!(hupa.plan_backup(&vars).unwrap().is_empty());
+        // The generation is rewritten compressed though the origin didn't change
+        hupa.set_compression(Compression::Gzip);
+        let plan = hupa.plan_backup(&vars).unwrap();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].action, Action::Create);
+        assert_eq!(plan[0].path, PathBuf::from("rc"));
+    }
+}
@@ -0,0 +1,350 @@
+//! Rollback of a restore
+//!
+//! Before a restore overwrites or deletes files of the origin, they are moved
+//! to a rollback area. The paths created by the restore are recorded so
+//! `undo_restore` can put the origin back as it was.
+//!
+//! Replaced files are stored as they are in the origin, so rollback areas of
+//! all hupas are kept out of backup parents, in a directory only readable by
+//! its owner, see `get_rollback_parent`.
+
+use error::*;
+use fs_extra::{count_files, get_size, join, move_entry, remove_entry, SyncStats};
+use manifest::{parse_path, path_field, split_escaped};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use rules::Rules;
+use std::path::PathBuf;
+use super::origins::{read_origin_entries, write_origin_entries};
+use super::*;
+
+/// Directory of the rollback area holding replaced files
+const ROLLBACK_FILES: &str = "files";
+/// File of the rollback area listing created paths
+const ROLLBACK_ADDED: &str = "added";
+/// File of the rollback area listing restored origins, when there are many
+const ROLLBACK_ORIGINS: &str = "origins";
+/// Directory of the app cache holding rollback areas
+const ROLLBACKS: &str = "rollback";
+
+impl Hupa {
+    /// Get the default parent of rollback areas, in the app cache
+    ///
+    /// It is out of the default backup parent, which must not hold files of
+    /// encrypted hupas in plaintext.
+    pub fn get_default_rollback_parent() -> Result<PathBuf> {
+        let cache = ::app_dirs::app_root(::app_dirs::AppDataType::UserCache, &APP_INFO)?;
+        Ok(cache.join(ROLLBACKS))
+    }
+
+    /// Get the parent of rollback areas
+    pub fn get_rollback_parent(&self) -> Result<PathBuf> {
+        match self.rollback_parent {
+            Some(ref parent) => Ok(parent.clone()),
+            None => Hupa::get_default_rollback_parent(),
+        }
+    }
+
+    /// Get the rollback area of the last restore
+    pub fn rollback_dir(&self) -> Result<PathBuf> {
+        self.rollback_path("rollback")
+    }
+
+    /// Get a path of the rollback area, `<category>/<name>.<suffix>` in the
+    /// rollback parent
+    fn rollback_path(&self, suffix: &str) -> Result<PathBuf> {
+        let mut path = self.get_rollback_parent()?;
+        for sub_category in self.get_category() {
+            path.push(sub_category);
+        }
+        Ok(path.join(format!("{}.{}", self.get_name(), suffix)))
+    }
+
+    /// Check if the last restore can be undone
+    pub fn has_rollback(&self) -> bool {
+        self.rollback_dir().map(|d| d.is_dir()).unwrap_or(false)
+    }
+
+    /// Get the size of the rollback area
+    pub fn get_rollback_size(&self) -> Result<u64> {
+        get_size(self.rollback_dir()?, &Rules::default())
+    }
+
+    /// Delete the rollback area
+    pub fn delete_rollback(&self) -> Result<OperationResult> {
+        let rollback_dir = self.rollback_dir()?;
+        if !rollback_dir.exists() {
+            return Ok(OperationResult::NoChange);
+        }
+        remove_all(&rollback_dir)?;
+        Ok(OperationResult::Change(SyncStats::default()))
+    }
+
+    /// Put the origin back as it was before the last restore
+    pub fn undo_restore(&self) -> Result<OperationResult> {
+        let rollback_dir = self.rollback_dir()?;
+        if !rollback_dir.is_dir() {
+            bail!(ErrorKind::MissingRollback(
+                rollback_dir.display().to_string(),
+            ));
+        }
+        let mut stats = SyncStats::default();
+        let entries = if rollback_dir.join(ROLLBACK_ORIGINS).is_file() {
+            read_origin_entries(rollback_dir.join(ROLLBACK_ORIGINS))?
+        } else {
+            self.single_origin_entry()
+        };
+        let mut added = Vec::new();
+        for line in BufReader::new(File::open(rollback_dir.join(ROLLBACK_ADDED))?).lines() {
+            let line = line?;
+            let (escaped, path) = split_escaped(&line);
+            match parse_path(path, escaped) {
+                Some(path) => added.push(path),
+                None => bail!(ErrorKind::InvalidRollback(line.clone())),
+            }
+        }
+        for path in added.iter().rev() {
+            let (entry, relative) = match Hupa::split_entry(&entries, path) {
+                Some(e) => e,
+                None => continue,
+            };
+            let path = join(&entry.origin, &relative);
+            if path.symlink_metadata().is_ok() {
+                remove_entry(&path)?;
+                stats.removed += 1;
+            }
+        }
+        for entry in &entries {
+            let files = entry.path_in(&rollback_dir.join(ROLLBACK_FILES));
+            if files.symlink_metadata().is_ok() {
+                stats.updated += count_files(&files)?;
+                move_entry(&files, &entry.origin)?;
+            }
+        }
+        remove_all(&rollback_dir)?;
+        Ok(OperationResult::Change(stats))
+    }
+
+    /// Rollback area being filled by a running restore
+    fn pending_rollback_dir(&self) -> Result<PathBuf> {
+        self.rollback_path("rollback.tmp")
+    }
+
+    /// Prepare a rollback area for a new restore
+    ///
+    /// Return the directory where replaced files are moved
+    pub(crate) fn start_rollback(&self) -> Result<PathBuf> {
+        self.create_rollback_parent()?;
+        let pending = self.pending_rollback_dir()?;
+        if pending.exists() {
+            remove_all(&pending)?;
+        }
+        fs::create_dir_all(&pending)?;
+        Ok(pending.join(ROLLBACK_FILES))
+    }
+
+    /// Create the parent of rollback areas, only readable by its owner
+    #[cfg(unix)]
+    fn create_rollback_parent(&self) -> Result<()> {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        let dir = self.get_rollback_parent()?;
+        if dir.is_dir() {
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        } else {
+            fs::DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+        }
+        Ok(())
+    }
+
+    /// Create the parent of rollback areas
+    #[cfg(not(unix))]
+    fn create_rollback_parent(&self) -> Result<()> {
+        fs::create_dir_all(self.get_rollback_parent()?)?;
+        Ok(())
+    }
+
+    /// Replace the rollback area by the pending one if the restore changed the origin
+    ///
+    /// `entries` - Restored origins
+    ///
+    /// `added` - Paths created by the restore, relative to the generation
+    pub(crate) fn finish_rollback(
+        &self,
+        changed: bool,
+        entries: &[OriginEntry],
+        added: &[PathBuf],
+    ) -> Result<()> {
+        let pending = self.pending_rollback_dir()?;
+        if !changed {
+            remove_all(&pending)?;
+            return Ok(());
+        }
+        if entries.len() > 1 {
+            write_origin_entries(pending.join(ROLLBACK_ORIGINS), entries)?;
+        }
+        {
+            let mut f = File::create(pending.join(ROLLBACK_ADDED))?;
+            for path in added {
+                let (prefix, path) = path_field(path);
+                writeln!(f, "{}{}", prefix, path)?;
+            }
+        }
+        self.delete_rollback()?;
+        fs::rename(&pending, self.rollback_dir()?)?;
+        Ok(())
+    }
+}
+
+/// Prune rollback areas, oldest first, until they fit in `max_size` bytes
+///
+/// Return hupas whose rollback area was deleted
+pub fn prune_rollbacks(hupas: &[Hupa], max_size: u64) -> Result<Vec<Hupa>> {
+    let mut rollbacks = Vec::new();
+    let mut total = 0;
+    for hupa in hupas {
+        if !hupa.has_rollback() {
+            continue;
+        }
+        let size = hupa.get_rollback_size()?;
+        let modified = hupa.rollback_dir()?.metadata()?.modified()?;
+        total += size;
+        rollbacks.push((modified, size, hupa));
+    }
+    rollbacks.sort_by_key(|r| r.0);
+    let mut pruned = Vec::new();
+    for (_, size, hupa) in rollbacks {
+        if total <= max_size {
+            break;
+        }
+        hupa.delete_rollback()?;
+        total -= size;
+        pruned.push(hupa.clone());
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use test_utils::test_dir;
+
+    #[test]
+    fn undo_restore_test() {
+        let tmp = test_dir("undo-restore");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("rc"), "backed up").unwrap();
+        let mut hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.set_rollback_parent(root.join("rollback"));
+        let vars = VarsHandler::new(Vec::new());
+        hupa.backup(&vars).unwrap();
+        fs::write(origin.join("rc"), "edited").unwrap();
+        fs::write(origin.join("new"), "new").unwrap();
+        fs::remove_dir_all(hupa.latest_backup_dir().unwrap()).unwrap();
+        fs::create_dir_all(hupa.latest_backup_dir().unwrap().join("dir")).unwrap();
+        fs::write(hupa.latest_backup_dir().unwrap().join("rc"), "backed up").unwrap();
+        fs::write(hupa.latest_backup_dir().unwrap().join("dir/f"), "f").unwrap();
+        hupa.restore(&vars).unwrap();
+        assert_eq!(fs::read_to_string(origin.join("rc")).unwrap(), "backed up");
+        assert!(!origin.join("new").exists());
+        assert!(hupa.has_rollback());
+        hupa.undo_restore().unwrap();
+        assert_eq!(fs::read_to_string(origin.join("rc")).unwrap(), "edited");
+        assert_eq!(fs::read_to_string(origin.join("new")).unwrap(), "new");
+        assert!(!origin.join("dir").exists());
+        assert!(!hupa.has_rollback());
+        assert!(hupa.undo_restore().is_err());
+    }
+
+    #[test]
+    fn undo_restore_new_line() {
+        let tmp = test_dir("undo-restore-new-line");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        for name in &["a", "b", "a\nb"] {
+            fs::write(origin.join(name), "backed up").unwrap();
+        }
+        let mut hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.set_rollback_parent(root.join("rollback"));
+        let vars = VarsHandler::new(Vec::new());
+        hupa.backup(&vars).unwrap();
+        fs::remove_file(origin.join("a\nb")).unwrap();
+        hupa.restore(&vars).unwrap();
+        assert!(origin.join("a\nb").exists());
+        // Only the created path is removed, not the paths it contains
+        hupa.undo_restore().unwrap();
+        assert!(!origin.join("a\nb").exists());
+        assert_eq!(fs::read_to_string(origin.join("a")).unwrap(), "backed up");
+        assert_eq!(fs::read_to_string(origin.join("b")).unwrap(), "backed up");
+    }
+
+    #[test]
+    fn prune_rollbacks_test() {
+        let tmp = test_dir("prune-rollbacks");
+        let root = tmp.path();
+        let hupas = vec!["a", "b"]
+            .into_iter()
+            .map(|n| {
+                let mut hupa = Hupa::new(n, "", Vec::new(), root, "/", false, Vec::new());
+                hupa.set_rollback_parent(root.join("rollback"));
+                hupa
+            })
+            .collect::<Vec<Hupa>>();
+        for hupa in &hupas {
+            let files = hupa.start_rollback().unwrap();
+            fs::write(files, "12345").unwrap();
+            hupa.finish_rollback(true, &hupa.get_origin_entries(), &[]).unwrap();
+        }
+        assert!(prune_rollbacks(&hupas, 10).unwrap().is_empty());
+        assert_eq!(prune_rollbacks(&hupas, 5).unwrap().len(), 1);
+        assert_eq!(hupas.iter().filter(|h| h.has_rollback()).count(), 1);
+    }
+
+    /// Check that no file under `dir` contains `content`
+    fn assert_not_stored(dir: &Path, content: &[u8]) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                assert_not_stored(&path, content);
+            } else {
+                let stored = fs::read(&path).unwrap();
+                assert!(!stored.windows(content.len()).any(|w| w == content), "{}", path.display());
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn encrypted_rollback_test() {
+        use encryption::Secret;
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = test_dir("encrypted-rollback");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("key"), "backed up").unwrap();
+        let mut hupa = Hupa::new("ssh", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.set_rollback_parent(root.join("rollback"));
+        hupa.set_encrypted(true);
+        hupa.set_secret(Some(Secret::Passphrase("passphrase".to_string())));
+        let vars = VarsHandler::new(Vec::new());
+        hupa.backup(&vars).unwrap();
+        fs::write(origin.join("key"), "private key").unwrap();
+        fs::write(origin.join("new"), "private key").unwrap();
+        hupa.restore(&vars).unwrap();
+        assert_eq!(fs::read_to_string(origin.join("key")).unwrap(), "backed up");
+        assert!(hupa.has_rollback());
+        let parent = root.join("rollback");
+        assert!(hupa.rollback_dir().unwrap().starts_with(&parent));
+        assert_not_stored(&root.join("backup"), b"private key");
+        assert_eq!(fs::metadata(&parent).unwrap().permissions().mode() & 0o777, 0o700);
+        // The rollback area doesn't depend on the encryption of next backups
+        hupa.set_encrypted(false);
+        assert!(hupa.has_rollback());
+        hupa.undo_restore().unwrap();
+        assert_eq!(fs::read_to_string(origin.join("key")).unwrap(), "private key");
+        assert_eq!(fs::read_to_string(origin.join("new")).unwrap(), "private key");
+    }
+}
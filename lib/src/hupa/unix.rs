@@ -1,5 +1,6 @@
 //! Unix only function for hupa
 
+use attributes::{apply_attributes, read_attributes};
 use error::*;
 use libc::*;
 use std::fs::Metadata;
@@ -38,9 +39,9 @@ impl Hupa {
 
     /// Set eid to restore file
     ///
-    /// Use origin path for getting file permissions
+    /// Use the first origin path for getting file permissions
     pub fn set_eid_restore(&self) -> Result<()> {
-        self.set_eid(&self.origin_path)
+        self.set_eid(self.get_origin())
     }
 
     /// Get metadata or get parent one
@@ -55,30 +56,62 @@ impl Hupa {
         }
     }
 
+    /// Give back recorded mode and ownership to the restored origin
+    ///
+    /// Only root can give files to other users, nothing is done otherwise.
+    pub fn restore_owners(&self, generation: u64) -> Result<()> {
+        let attributes = self.generation_attributes(generation);
+        if unsafe { getuid() } != 0 || !attributes.exists() {
+            return Ok(());
+        }
+        // Effective ids were set to the owner of the origin by `set_eid`
+        unsafe { setresuid(0, 0, 0) };
+        unsafe { setresgid(0, 0, 0) };
+        let attributes = read_attributes(attributes)?;
+        for entry in self.generation_origin_entries(generation)? {
+            let entry_attributes = attributes
+                .iter()
+                .filter_map(|(relative, a)| {
+                    relative
+                        .strip_prefix(&entry.entry)
+                        .ok()
+                        .map(|r| (r.to_path_buf(), *a))
+                })
+                .collect::<Vec<_>>();
+            apply_attributes(&entry.origin, &entry_attributes)?;
+        }
+        Ok(())
+    }
+
     /// Check if user needs to be root to restore this hupa
     pub fn needs_root(&self) -> bool {
         let uid = unsafe { getuid() };
         if uid == 0 {
             return false;
         }
-        let metadata = match self.origin_path.metadata() {
-            Ok(m) => m,
-            Err(_) => return true,
-        };
-        let euid = unsafe { geteuid() };
-        let egid = unsafe { getegid() };
-        let file_uid = metadata.uid();
-        let file_gid = metadata.gid();
-        let permissions = metadata.permissions();
-        let mode = permissions.mode();
-        let (owner_w, group_w, other_w) = can_write(mode);
-        if euid == file_uid {
-            !owner_w
-        } else if egid == file_gid {
-            !group_w
-        } else {
-            !other_w
-        }
+        self.get_origins().iter().any(|o| needs_root_for(o))
+    }
+}
+
+/// Check if user needs to be root to write an origin
+fn needs_root_for(origin: &Path) -> bool {
+    let metadata = match origin.metadata() {
+        Ok(m) => m,
+        Err(_) => return true,
+    };
+    let euid = unsafe { geteuid() };
+    let egid = unsafe { getegid() };
+    let file_uid = metadata.uid();
+    let file_gid = metadata.gid();
+    let permissions = metadata.permissions();
+    let mode = permissions.mode();
+    let (owner_w, group_w, other_w) = can_write(mode);
+    if euid == file_uid {
+        !owner_w
+    } else if egid == file_gid {
+        !group_w
+    } else {
+        !other_w
     }
 }
 
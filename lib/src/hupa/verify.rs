@@ -0,0 +1,161 @@
+//! Integrity check of generations
+//!
+//! A generation is checked against the manifest recorded when it was made,
+//! see `generation_manifest`.
+
+use error::*;
+use manifest::Manifest;
+use std::io::Write;
+use std::path::PathBuf;
+use super::*;
+
+/// Result of the verification of a generation
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    /// Generation which was verified
+    pub generation: u64,
+    /// Files listed in the manifest but missing from the generation
+    pub missing: Vec<PathBuf>,
+    /// Files of the generation not listed in the manifest
+    pub extra: Vec<PathBuf>,
+    /// Files whose content, size, mode or modification time differ from the
+    /// manifest
+    pub corrupted: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    /// Check if the generation is intact
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+impl Hupa {
+    /// Verify the latest generation against its manifest
+    pub fn verify(&self) -> Result<VerifyReport> {
+        self.verify_at(GenerationTarget::Latest)
+    }
+
+    /// Verify a specific generation against its manifest
+    pub fn verify_at(&self, target: GenerationTarget) -> Result<VerifyReport> {
+        let generation = self.resolve_generation(target)?;
+        let manifest = self.read_manifest(generation)?;
+        let encoding = self.generation_encoding(generation)?;
+        let generation_dir = self.generation_dir(generation);
+        let mut found = Manifest::default();
+        for entry in self.generation_origin_entries(generation)? {
+            let path = entry.path_in(&generation_dir);
+            if path.symlink_metadata().is_ok() {
+                let stored = Manifest::build_stored(path, &Rules::default(), encoding)?;
+                found.insert_prefixed(&entry.entry, stored);
+            }
+        }
+        let diff = manifest.diff(&found);
+        Ok(VerifyReport {
+            generation,
+            missing: diff.removed,
+            extra: diff.added,
+            corrupted: diff.changed,
+        })
+    }
+
+    /// Write a `sha256sum` compatible listing of the files of a generation
+    ///
+    /// Checksums are the ones recorded in the manifest, paths are absolute.
+    /// Checksums of compressed or encrypted files are the ones of their
+    /// original content, so such generations fail with `EncodedGeneration`.
+    pub fn write_sha256sum<W: Write>(&self, stream: &mut W, target: GenerationTarget) -> Result<()> {
+        let generation = self.resolve_generation(target)?;
+        if self.generation_compression(generation)?.is_compressed() ||
+            self.is_generation_encrypted(generation)
+        {
+            bail!(ErrorKind::EncodedGeneration(generation));
+        }
+        self.read_manifest(generation)?
+            .write_sha256sum(stream, &self.generation_dir(generation))
+    }
+
+    fn read_manifest(&self, generation: u64) -> Result<Manifest> {
+        let manifest = self.generation_manifest(generation);
+        if !manifest.is_file() {
+            bail!(ErrorKind::MissingManifest(manifest.display().to_string()));
+        }
+        self.read_generation_manifest(generation)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use compression::Compression;
+    use manifest::{hash_file, unescape_path};
+    use std::fs;
+    use test_utils::test_dir;
+
+    /// Check a listing like `sha256sum -c`, return the number of checked files
+    fn check_sha256sum(listing: &str) -> usize {
+        for line in listing.lines() {
+            let (escaped, line) = match line.strip_prefix('\\') {
+                Some(l) => (true, l),
+                None => (false, line),
+            };
+            let (hash, path) = line.split_at(64);
+            let path = path.strip_prefix("  ").unwrap();
+            let path = if escaped { unescape_path(path).unwrap() } else { PathBuf::from(path) };
+            assert_eq!(hash_file(&path).unwrap(), hash, "{}", path.display());
+        }
+        listing.lines().count()
+    }
+
+    #[test]
+    fn verify_test() {
+        let tmp = test_dir("verify");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("a"), "a").unwrap();
+        fs::write(origin.join("b"), "b").unwrap();
+        let hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        hupa.backup(&VarsHandler::new(Vec::new())).unwrap();
+        assert!(hupa.verify().unwrap().is_ok());
+        let mut listing = Vec::new();
+        hupa.write_sha256sum(&mut listing, GenerationTarget::Latest).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        assert!(listing.starts_with(
+            "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb  /"
+        ));
+        assert_eq!(check_sha256sum(&listing), 2);
+        let backup = hupa.latest_backup_dir().unwrap();
+        fs::remove_file(backup.join("a")).unwrap();
+        fs::write(backup.join("b"), "c").unwrap();
+        fs::write(backup.join("d"), "d").unwrap();
+        let report = hupa.verify().unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.missing, vec![PathBuf::from("a")]);
+        assert_eq!(report.corrupted, vec![PathBuf::from("b")]);
+        assert_eq!(report.extra, vec![PathBuf::from("d")]);
+    }
+
+    #[test]
+    fn sha256sum_of_compressed() {
+        let tmp = test_dir("sha256sum-compressed");
+        let root = tmp.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("a"), "a").unwrap();
+        let mut hupa = Hupa::new("rc", "", Vec::new(), root.join("backup"), &origin, false, Vec::new());
+        let vars = VarsHandler::new(Vec::new());
+        hupa.backup(&vars).unwrap();
+        hupa.set_compression(Compression::Gzip);
+        fs::write(origin.join("a"), "b").unwrap();
+        hupa.backup(&vars).unwrap();
+        let mut listing = Vec::new();
+        match hupa.write_sha256sum(&mut listing, GenerationTarget::Latest) {
+            Err(Error(ErrorKind::EncodedGeneration(_), _)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        // The previous generation is still stored as is
+        hupa.write_sha256sum(&mut listing, GenerationTarget::Nth(1)).unwrap();
+        assert_eq!(check_sha256sum(&String::from_utf8(listing).unwrap()), 1);
+    }
+}
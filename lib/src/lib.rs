@@ -2,30 +2,66 @@
 
 #![deny(missing_docs)]
 #![allow(unused_doc_comment)]
-#![recursion_limit="128"]
+#![recursion_limit="256"]
 
 #[cfg(unix)]
 extern crate libc;
+#[cfg(unix)]
+extern crate xattr;
 
 extern crate app_dirs;
+extern crate argon2;
+extern crate chacha20poly1305;
 #[macro_use]
 extern crate error_chain;
+extern crate filetime;
+extern crate flate2;
+extern crate glob;
 #[macro_use]
 extern crate json;
+extern crate sha2;
+extern crate tar;
+#[cfg(test)]
+extern crate tempfile;
+extern crate toml;
+extern crate yaml_rust2;
+extern crate zip;
+extern crate zstd;
 
+mod archive;
+mod attributes;
 mod category;
+mod compression;
 mod config;
+mod encoding;
+mod encryption;
 mod error;
+mod facts;
 mod fs_extra;
 mod hupa;
+mod manifest;
 mod metadata;
+mod requirement;
+mod rules;
+mod template;
+#[cfg(test)]
+mod test_utils;
 mod vars;
 
+pub use archive::*;
 pub use category::*;
+pub use compression::Compression;
 pub use config::*;
+pub use encryption::{Secret, PASSPHRASE_VAR};
 pub use error::*;
+pub use facts::{get_fact, get_facts, FACTS};
+pub use fs_extra::{Action, PlannedAction, SyncStats};
 pub use hupa::*;
+pub use manifest::{hash_file, Manifest, ManifestDiff, ManifestEntry};
 pub use metadata::*;
+pub use requirement::{Comparison, Requirement};
+pub use rules::*;
+pub use template::{expand_path, is_template};
 pub use vars::*;
 
 use app_dirs::AppInfo;
@@ -0,0 +1,793 @@
+//! Manifest of the files of a backup
+//!
+//! A manifest lists every entry of a tree with its size, modification time,
+//! mode and a hash of its content. Comparing the manifest of a backup with the
+//! one of its origin tells exactly what changed, even when a tool preserved
+//! modification times, without walking the backup again.
+//!
+//! A path with a new line, a tab or a backslash is escaped like `sha256sum`
+//! does, its line starting with a backslash. Bytes of a path which aren't
+//! UTF-8 are escaped as `\xHH`.
+//!
+//! Hashing every file of the origins on each check is slow, so the stats of
+//! origin files are kept with a backup in a `StatCache`. A file keeps its
+//! hash only if its change time, inode and device didn't change either, which
+//! no tool can preserve along an edit. As change times are coarse, files
+//! changed in the second the stats were taken are always hashed.
+
+use encoding::Encoding;
+use error::*;
+use filetime::FileTime;
+use fs_extra::{is_link_loop, join, EntryKind};
+use rules::{device, Rules};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Entry of a manifest
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestEntry {
+    /// Kind of the entry, `d` for a directory, `f` for a file, `l` for a
+    /// symlink and `p` for a FIFO
+    pub kind: char,
+    /// Permission bits
+    pub mode: u32,
+    /// Size of a file, 0 for other entries
+    pub size: u64,
+    /// Modification time in seconds, 0 for directories as their content is
+    /// already listed
+    pub mtime: i64,
+    /// SHA-256 of the content of a file or of the target of a symlink, `-` for
+    /// other entries, `invalid` for an encoded file which can't be read
+    pub hash: String,
+}
+
+/// Entries of a tree, by path relative to its root
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Manifest {
+    entries: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+/// Differences between two manifests
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ManifestDiff {
+    /// Paths only in the newer manifest
+    pub added: Vec<PathBuf>,
+    /// Paths only in the older manifest
+    pub removed: Vec<PathBuf>,
+    /// Paths in both manifests with different entries
+    pub changed: Vec<PathBuf>,
+}
+
+impl ManifestDiff {
+    /// Check if manifests are the same
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Manifest {
+    /// Build the manifest of a tree
+    ///
+    /// Special files, like sockets, are not listed.
+    ///
+    /// `rules` - Rules selecting listed files, relative to `root`
+    pub fn build<P: AsRef<Path>>(root: P, rules: &Rules) -> Result<Manifest> {
+        Manifest::build_stored(root, rules, Encoding::default())
+    }
+
+    /// Build the manifest of a tree, hashing only files which changed since
+    /// `cache` was built
+    ///
+    /// A file keeps its hash in `cache` if its stat is the one in `stats`.
+    pub fn build_cached<P: AsRef<Path>>(
+        root: P,
+        rules: &Rules,
+        cache: &Manifest,
+        stats: &StatCache,
+    ) -> Result<Manifest> {
+        let root = root.as_ref();
+        let mut manifest = Manifest::default();
+        let root_device = device(&root.metadata()?);
+        let context = BuildContext {
+            rules,
+            root_device,
+            encoding: Encoding::default(),
+            cache: Some((cache, stats)),
+        };
+        manifest.build_relative(root, Path::new(""), &context)?;
+        Ok(manifest)
+    }
+
+    /// Build the manifest of a tree whose files are encoded
+    ///
+    /// Sizes and hashes are the ones of the original content.
+    pub fn build_stored<P: AsRef<Path>>(
+        root: P,
+        rules: &Rules,
+        encoding: Encoding,
+    ) -> Result<Manifest> {
+        let root = root.as_ref();
+        let mut manifest = Manifest::default();
+        let root_device = device(&root.metadata()?);
+        let context = BuildContext {
+            rules,
+            root_device,
+            encoding,
+            cache: None,
+        };
+        manifest.build_relative(root, Path::new(""), &context)?;
+        Ok(manifest)
+    }
+
+    fn build_relative(
+        &mut self,
+        path: &Path,
+        relative: &Path,
+        context: &BuildContext,
+    ) -> Result<()> {
+        let rules = context.rules;
+        let metadata = rules.metadata(path, relative)?;
+        if rules.is_excluded(relative, &metadata, context.root_device) {
+            return Ok(());
+        }
+        let kind = EntryKind::of(&metadata);
+        let entry = match kind {
+            EntryKind::Dir => ManifestEntry::new('d', &metadata, "-".to_string()),
+            EntryKind::File => {
+                let mut entry = ManifestEntry::new('f', &metadata, String::new());
+                if let Some(hash) = context.cached_hash(relative, &entry, &metadata) {
+                    entry.hash = hash;
+                    self.entries.insert(relative.to_path_buf(), entry);
+                    return Ok(());
+                }
+                let encoding = context.encoding;
+                let mut hasher = Sha256::new();
+                let copied = encoding
+                    .open(path)
+                    .and_then(|mut s| Ok(io::copy(&mut s, &mut hasher)?));
+                match copied {
+                    Ok(size) => {
+                        entry.size = size;
+                        entry.hash = to_hex(&hasher.finalize());
+                    }
+                    Err(_) if !encoding.is_plain() => entry.hash = "invalid".to_string(),
+                    Err(e) => return Err(e),
+                }
+                entry
+            }
+            EntryKind::Symlink => {
+                let target = fs::read_link(path)?;
+                let hash = hash_bytes(target.to_string_lossy().as_bytes());
+                ManifestEntry::new('l', &metadata, hash)
+            }
+            EntryKind::Fifo => ManifestEntry::new('p', &metadata, "-".to_string()),
+            EntryKind::Special => return Ok(()),
+        };
+        self.entries.insert(relative.to_path_buf(), entry);
+        if kind == EntryKind::Dir {
+            for entry in fs::read_dir(path)? {
+                let entry_path = entry?.path();
+                if rules.is_following_symlinks() && is_link_loop(&entry_path, path) {
+                    continue;
+                }
+                let entry_relative = relative.join(entry_path.file_name().unwrap());
+                self.build_relative(&entry_path, &entry_relative, context)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a manifest written by `write`
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Manifest> {
+        Manifest::read_stored(path, Encoding::default())
+    }
+
+    /// Read a manifest written by `write_stored`
+    pub fn read_stored<P: AsRef<Path>>(path: P, encoding: Encoding) -> Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for line in BufReader::new(encoding.open(path)?).lines() {
+            let line = line?;
+            let (escaped, fields) = split_escaped(&line);
+            let mut split = fields.splitn(6, ' ');
+            let kind = split.next().and_then(|k| k.chars().next());
+            let mode = split.next().and_then(|m| u32::from_str_radix(m, 8).ok());
+            let size = split.next().and_then(|s| s.parse().ok());
+            let mtime = split.next().and_then(|m| m.parse().ok());
+            let hash = split.next();
+            let relative = split.next().and_then(|r| parse_path(r, escaped));
+            match (kind, mode, size, mtime, hash, relative) {
+                (Some(kind), Some(mode), Some(size), Some(mtime), Some(hash), Some(relative)) => {
+                    let entry = ManifestEntry {
+                        kind,
+                        mode,
+                        size,
+                        mtime,
+                        hash: hash.to_string(),
+                    };
+                    manifest.entries.insert(relative, entry);
+                }
+                _ => bail!(ErrorKind::InvalidManifest(line.clone())),
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Write the manifest
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_stored(path, Encoding::default())
+    }
+
+    /// Write the manifest, stored with `encoding`
+    pub fn write_stored<P: AsRef<Path>>(&self, path: P, encoding: Encoding) -> Result<()> {
+        let mut f = Vec::new();
+        for (relative, entry) in &self.entries {
+            let (prefix, relative) = path_field(relative);
+            writeln!(
+                f,
+                "{}{} {:o} {} {} {} {}",
+                prefix,
+                entry.kind,
+                entry.mode,
+                entry.size,
+                entry.mtime,
+                entry.hash,
+                relative
+            )?;
+        }
+        encoding.write(&mut f.as_slice(), path)?;
+        Ok(())
+    }
+
+    /// Write a `sha256sum` compatible listing of files
+    ///
+    /// `root` - Path joined to listed paths
+    pub fn write_sha256sum<W: Write>(&self, stream: &mut W, root: &Path) -> Result<()> {
+        for (relative, entry) in &self.entries {
+            if entry.kind != 'f' {
+                continue;
+            }
+            let path = root.join(relative).display().to_string();
+            // Like sha256sum, a leading backslash marks an escaped path
+            match escape(&path) {
+                Some(escaped) => writeln!(stream, "\\{}  {}", entry.hash, escaped)?,
+                None => writeln!(stream, "{}  {}", entry.hash, path)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the entries of another manifest, under `prefix`
+    pub fn insert_prefixed(&mut self, prefix: &Path, other: Manifest) {
+        for (relative, entry) in other.entries {
+            self.entries.insert(join(prefix, &relative), entry);
+        }
+    }
+
+    /// Get the entries under `prefix`, relative to it
+    pub fn subtree(&self, prefix: &Path) -> Manifest {
+        let entries = self.entries
+            .iter()
+            .filter_map(|(relative, entry)| {
+                relative
+                    .strip_prefix(prefix)
+                    .ok()
+                    .map(|r| (r.to_path_buf(), entry.clone()))
+            })
+            .collect();
+        Manifest { entries }
+    }
+
+    /// Get entries, sorted by path
+    pub fn get_entries(&self) -> &BTreeMap<PathBuf, ManifestEntry> {
+        &self.entries
+    }
+
+    /// Get the differences with a newer manifest
+    pub fn diff(&self, newer: &Manifest) -> ManifestDiff {
+        let mut diff = ManifestDiff::default();
+        for (path, entry) in &self.entries {
+            match newer.entries.get(path) {
+                Some(e) if e == entry => {}
+                Some(_) => diff.changed.push(path.clone()),
+                None => diff.removed.push(path.clone()),
+            }
+        }
+        for path in newer.entries.keys() {
+            if !self.entries.contains_key(path) {
+                diff.added.push(path.clone());
+            }
+        }
+        diff
+    }
+}
+
+/// Stat of a file, changing with its content even if its modification time
+/// is set back
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileStat {
+    size: u64,
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+    ino: u64,
+    dev: u64,
+    mode: u32,
+}
+
+impl FileStat {
+    /// Get the stat of a file, `None` where change times are not available
+    #[cfg(unix)]
+    pub fn of(metadata: &Metadata) -> Option<FileStat> {
+        use std::os::unix::fs::MetadataExt;
+        Some(FileStat {
+            size: metadata.size(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+            ino: metadata.ino(),
+            dev: metadata.dev(),
+            mode: metadata.mode(),
+        })
+    }
+
+    /// Get the stat of a file, `None` where change times are not available
+    #[cfg(not(unix))]
+    pub fn of(_metadata: &Metadata) -> Option<FileStat> {
+        None
+    }
+}
+
+/// Stats of the files of a tree, by path relative to its root
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StatCache {
+    /// Unix time when the stats were taken
+    taken: i64,
+    entries: BTreeMap<PathBuf, FileStat>,
+}
+
+impl StatCache {
+    /// Get the stats of the files of a tree
+    ///
+    /// `rules` - Rules selecting files, relative to `root`
+    pub fn build<P: AsRef<Path>>(root: P, rules: &Rules) -> Result<StatCache> {
+        let root = root.as_ref();
+        let taken = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        let mut stats = StatCache {
+            taken,
+            ..StatCache::default()
+        };
+        let root_device = device(&root.metadata()?);
+        stats.build_relative(root, Path::new(""), rules, root_device)?;
+        Ok(stats)
+    }
+
+    fn build_relative(
+        &mut self,
+        path: &Path,
+        relative: &Path,
+        rules: &Rules,
+        root_device: Option<u64>,
+    ) -> Result<()> {
+        let metadata = rules.metadata(path, relative)?;
+        if rules.is_excluded(relative, &metadata, root_device) {
+            return Ok(());
+        }
+        match EntryKind::of(&metadata) {
+            EntryKind::File => {
+                if let Some(stat) = FileStat::of(&metadata) {
+                    self.entries.insert(relative.to_path_buf(), stat);
+                }
+            }
+            EntryKind::Dir => {
+                for entry in fs::read_dir(path)? {
+                    let entry_path = entry?.path();
+                    if rules.is_following_symlinks() && is_link_loop(&entry_path, path) {
+                        continue;
+                    }
+                    let entry_relative = relative.join(entry_path.file_name().unwrap());
+                    self.build_relative(&entry_path, &entry_relative, rules, root_device)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Read stats written by `write_stored`
+    pub fn read_stored<P: AsRef<Path>>(path: P, encoding: Encoding) -> Result<StatCache> {
+        let mut stats = StatCache::default();
+        let mut lines = BufReader::new(encoding.open(path)?).lines();
+        if let Some(line) = lines.next() {
+            let line = line?;
+            stats.taken = match line.parse() {
+                Ok(taken) => taken,
+                Err(_) => bail!(ErrorKind::InvalidManifest(line)),
+            };
+        }
+        for line in lines {
+            let line = line?;
+            let (escaped, fields) = split_escaped(&line);
+            let mut split = fields.splitn(9, ' ');
+            let mut numbers = Vec::new();
+            for _ in 0..8 {
+                match split.next().and_then(|n| n.parse::<i64>().ok()) {
+                    Some(n) => numbers.push(n),
+                    None => bail!(ErrorKind::InvalidManifest(line.clone())),
+                }
+            }
+            let relative = match split.next().and_then(|r| parse_path(r, escaped)) {
+                Some(r) => r,
+                None => bail!(ErrorKind::InvalidManifest(line.clone())),
+            };
+            let stat = FileStat {
+                size: numbers[0] as u64,
+                mtime: (numbers[1], numbers[2]),
+                ctime: (numbers[3], numbers[4]),
+                ino: numbers[5] as u64,
+                dev: numbers[6] as u64,
+                mode: numbers[7] as u32,
+            };
+            stats.entries.insert(relative, stat);
+        }
+        Ok(stats)
+    }
+
+    /// Write stats, stored with `encoding`
+    pub fn write_stored<P: AsRef<Path>>(&self, path: P, encoding: Encoding) -> Result<()> {
+        let mut f = Vec::new();
+        writeln!(f, "{}", self.taken)?;
+        for (relative, stat) in &self.entries {
+            let (prefix, relative) = path_field(relative);
+            writeln!(
+                f,
+                "{}{} {} {} {} {} {} {} {} {}",
+                prefix,
+                stat.size,
+                stat.mtime.0,
+                stat.mtime.1,
+                stat.ctime.0,
+                stat.ctime.1,
+                stat.ino as i64,
+                stat.dev as i64,
+                stat.mode,
+                relative
+            )?;
+        }
+        encoding.write(&mut f.as_slice(), path)?;
+        Ok(())
+    }
+
+    /// Check if a file has the stat it had when the stats were taken, and
+    /// wasn't changed again in the same second
+    fn is_unchanged(&self, relative: &Path, stat: &FileStat) -> bool {
+        stat.ctime.0 < self.taken && self.entries.get(relative) == Some(stat)
+    }
+
+    /// Add the stats of another cache, under `prefix`
+    ///
+    /// The stats are as old as the oldest of both.
+    pub fn insert_prefixed(&mut self, prefix: &Path, other: StatCache) {
+        if self.taken == 0 || other.taken < self.taken {
+            self.taken = other.taken;
+        }
+        for (relative, stat) in other.entries {
+            self.entries.insert(join(prefix, &relative), stat);
+        }
+    }
+
+    /// Get the stats under `prefix`, relative to it
+    pub fn subtree(&self, prefix: &Path) -> StatCache {
+        let entries = self.entries
+            .iter()
+            .filter_map(|(relative, stat)| {
+                relative
+                    .strip_prefix(prefix)
+                    .ok()
+                    .map(|r| (r.to_path_buf(), *stat))
+            })
+            .collect();
+        StatCache {
+            taken: self.taken,
+            entries,
+        }
+    }
+}
+
+/// Settings shared while building a manifest
+struct BuildContext<'a> {
+    rules: &'a Rules,
+    root_device: Option<u64>,
+    encoding: Encoding,
+    /// Manifest whose hashes are reused for files with the same stats
+    cache: Option<(&'a Manifest, &'a StatCache)>,
+}
+
+impl<'a> BuildContext<'a> {
+    /// Get the cached hash of a file if its stat didn't change
+    fn cached_hash(
+        &self,
+        relative: &Path,
+        entry: &ManifestEntry,
+        metadata: &Metadata,
+    ) -> Option<String> {
+        let (cache, stats) = self.cache?;
+        if !stats.is_unchanged(relative, &FileStat::of(metadata)?) {
+            return None;
+        }
+        let cached = cache.entries.get(relative)?;
+        if cached.kind == entry.kind && cached.size == entry.size && cached.hash != "invalid" {
+            Some(cached.hash.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl ManifestEntry {
+    fn new(kind: char, metadata: &Metadata, hash: String) -> ManifestEntry {
+        let (size, mtime) = if kind == 'd' {
+            (0, 0)
+        } else {
+            let mtime = FileTime::from_last_modification_time(metadata).unix_seconds();
+            (if kind == 'f' { metadata.len() } else { 0 }, mtime)
+        };
+        ManifestEntry {
+            kind,
+            mode: mode(metadata),
+            size,
+            mtime,
+            hash,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+/// Get the SHA-256 of a file, in hexadecimal
+pub fn hash_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Escape backslashes and new lines of a path like `sha256sum`, `None` if it
+/// has none
+pub(crate) fn escape(path: &str) -> Option<String> {
+    if !path.contains(['\\', '\n']) {
+        return None;
+    }
+    Some(path.replace('\\', "\\\\").replace('\n', "\\n"))
+}
+
+/// Split a line in whether its path is escaped and its fields
+pub(crate) fn split_escaped(line: &str) -> (bool, &str) {
+    match line.strip_prefix('\\') {
+        Some(fields) => (true, fields),
+        None => (false, line),
+    }
+}
+
+/// Get the line prefix and the written form of a path, escaped if needed
+pub(crate) fn path_field(path: &Path) -> (&'static str, String) {
+    match escape_path(path) {
+        Some(escaped) => ("\\", escaped),
+        None => ("", path.display().to_string()),
+    }
+}
+
+/// Parse a path written by `path_field`
+pub(crate) fn parse_path(s: &str, escaped: bool) -> Option<PathBuf> {
+    if escaped {
+        unescape_path(s)
+    } else {
+        Some(PathBuf::from(s))
+    }
+}
+
+/// Escape backslashes, new lines, tabs and bytes which aren't UTF-8 of a path,
+/// `None` if it has none
+pub(crate) fn escape_path(path: &Path) -> Option<String> {
+    let bytes = path_bytes(path);
+    let mut rest = bytes.as_slice();
+    let mut escaped = String::with_capacity(rest.len());
+    let mut is_escaped = false;
+    while !rest.is_empty() {
+        let (valid, invalid) = match str::from_utf8(rest) {
+            Ok(_) => (rest.len(), 0),
+            Err(e) => (
+                e.valid_up_to(),
+                e.error_len().unwrap_or(rest.len() - e.valid_up_to()),
+            ),
+        };
+        for c in str::from_utf8(&rest[..valid]).unwrap_or_default().chars() {
+            let sequence = match c {
+                '\\' => "\\\\",
+                '\n' => "\\n",
+                '\t' => "\\t",
+                c => {
+                    escaped.push(c);
+                    continue;
+                }
+            };
+            escaped.push_str(sequence);
+            is_escaped = true;
+        }
+        for byte in &rest[valid..valid + invalid] {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+            is_escaped = true;
+        }
+        rest = &rest[valid + invalid..];
+    }
+    if is_escaped {
+        Some(escaped)
+    } else {
+        None
+    }
+}
+
+/// Unescape a path escaped by `escape_path` or `escape`, `None` if it is not
+/// valid
+pub(crate) fn unescape_path(path: &str) -> Option<PathBuf> {
+    let mut unescaped = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            unescaped.push(byte);
+            continue;
+        }
+        match bytes.next()? {
+            b'\\' => unescaped.push(b'\\'),
+            b'n' => unescaped.push(b'\n'),
+            b't' => unescaped.push(b'\t'),
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                let hex = str::from_utf8(&hex).ok()?;
+                unescaped.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            _ => return None,
+        }
+    }
+    Some(path_from_bytes(unescaped))
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(::std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+pub(crate) fn hash_bytes(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use test_utils::test_dir;
+
+    #[test]
+    fn manifest_roundtrip() {
+        let tmp = test_dir("manifest");
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("tree/with space")).unwrap();
+        fs::write(dir.join("tree/a"), "a").unwrap();
+        fs::write(dir.join("tree/with space/b"), "b").unwrap();
+        fs::write(dir.join("tree/new\nline\\"), "c").unwrap();
+        let manifest = Manifest::build(dir.join("tree"), &Rules::default()).unwrap();
+        assert_eq!(manifest.get_entries().len(), 5);
+        assert_eq!(
+            manifest.get_entries()[Path::new("a")].hash,
+            "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb"
+        );
+        manifest.write(dir.join("manifest")).unwrap();
+        assert_eq!(Manifest::read(dir.join("manifest")).unwrap(), manifest);
+        fs::write(dir.join("manifest"), "bad line").unwrap();
+        assert!(Manifest::read(dir.join("manifest")).is_err());
+        fs::write(dir.join("manifest"), "\\f 644 1 0 - a\\z").unwrap();
+        assert!(Manifest::read(dir.join("manifest")).is_err());
+    }
+
+    #[test]
+    fn manifest_diff() {
+        let tmp = test_dir("manifest-diff");
+        let dir = tmp.path();
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("a"), "a").unwrap();
+        fs::write(dir.join("b"), "b").unwrap();
+        let mtime = FileTime::from_unix_time(1000, 0);
+        ::filetime::set_file_mtime(dir.join("a"), mtime).unwrap();
+        let old = Manifest::build(dir, &Rules::default()).unwrap();
+        assert!(old.diff(&old).is_empty());
+        // Same size and modification time, only the content tells the change
+        fs::write(dir.join("a"), "c").unwrap();
+        ::filetime::set_file_mtime(dir.join("a"), mtime).unwrap();
+        fs::remove_file(dir.join("b")).unwrap();
+        fs::write(dir.join("d"), "d").unwrap();
+        let diff = old.diff(&Manifest::build(dir, &Rules::default()).unwrap());
+        assert_eq!(diff.added, vec![PathBuf::from("d")]);
+        assert_eq!(diff.removed, vec![PathBuf::from("b")]);
+        assert_eq!(diff.changed, vec![PathBuf::from("a")]);
+    }
+
+    #[test]
+    fn manifest_cached() {
+        let tmp = test_dir("manifest-cached");
+        let dir = tmp.path();
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("a"), "a").unwrap();
+        fs::write(dir.join("b"), "b").unwrap();
+        fs::write(dir.join("c"), "c").unwrap();
+        let mut cache = Manifest::build(dir, &Rules::default()).unwrap();
+        for entry in cache.entries.values_mut() {
+            entry.hash = "cached".to_string();
+        }
+        fs::write(dir.join("c"), "c").unwrap();
+        let mut stats = StatCache::build(dir, &Rules::default()).unwrap();
+        // Files changed in the second the stats were taken are hashed again
+        let manifest = Manifest::build_cached(dir, &Rules::default(), &cache, &stats).unwrap();
+        assert_eq!(manifest.get_entries()[Path::new("a")].hash, hash_bytes(b"a"));
+        stats.taken = i64::MAX;
+        fs::write(dir.join("b"), "bb").unwrap();
+        // An edit keeping size and modification time changes the change time
+        let mtime = FileTime::from_last_modification_time(&dir.join("c").metadata().unwrap());
+        ::std::thread::sleep(::std::time::Duration::from_millis(50));
+        fs::write(dir.join("c"), "d").unwrap();
+        ::filetime::set_file_mtime(dir.join("c"), mtime).unwrap();
+        let manifest = Manifest::build_cached(dir, &Rules::default(), &cache, &stats).unwrap();
+        assert_eq!(manifest.get_entries()[Path::new("a")].hash, "cached");
+        assert_eq!(manifest.get_entries()[Path::new("b")].hash, hash_bytes(b"bb"));
+        assert_eq!(manifest.get_entries()[Path::new("c")].hash, hash_bytes(b"d"));
+    }
+
+    #[test]
+    fn stats_stored() {
+        let tmp = test_dir("stats-stored");
+        let dir = tmp.path().join("tree");
+        fs::create_dir_all(dir.join("new\nline")).unwrap();
+        fs::write(dir.join("a"), "a").unwrap();
+        fs::write(dir.join("new\nline/b"), "b").unwrap();
+        let stats = StatCache::build(&dir, &Rules::default()).unwrap();
+        let path = tmp.path().join("stats");
+        stats.write_stored(&path, Encoding::default()).unwrap();
+        assert_eq!(StatCache::read_stored(&path, Encoding::default()).unwrap(), stats);
+    }
+}
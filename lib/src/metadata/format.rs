@@ -0,0 +1,276 @@
+//! Formats of the metadata file
+//!
+//! Every format converts from and to the json document of metadata, so
+//! hupas and migrations are handled the same way whatever the format.
+//! Comments of TOML and YAML are read, but they are not kept when hupa
+//! rewrites metadata.
+
+use error::*;
+use json::JsonValue;
+use std::path::Path;
+use toml::Value as TomlValue;
+use yaml_rust2::{Yaml, YamlEmitter, YamlLoader};
+
+/// Names of the supported formats
+pub const METADATA_FORMATS: &[&str] = &["json", "toml", "yaml"];
+
+/// Format of the metadata file
+pub trait MetadataFormat {
+    /// Name of the format, as written in config
+    fn name(&self) -> &'static str;
+
+    /// File extensions of the format, the first one is used for new files
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Parse metadata written in this format
+    fn parse(&self, s: &str) -> Result<JsonValue>;
+
+    /// Write metadata in this format
+    fn stringify(&self, json: &JsonValue) -> Result<String>;
+}
+
+/// Get a format from its name
+pub fn get_format(name: &str) -> Result<Box<dyn MetadataFormat>> {
+    match name {
+        "json" => Ok(Box::new(JsonFormat)),
+        "toml" => Ok(Box::new(TomlFormat)),
+        "yaml" | "yml" => Ok(Box::new(YamlFormat)),
+        _ => bail!(ErrorKind::InvalidMetadataFormat(name.to_string())),
+    }
+}
+
+/// Detect the format of a metadata file from its extension
+///
+/// Files without a known extension are json, like every metadata file
+/// written before other formats were supported.
+pub fn detect_format<P: AsRef<Path>>(path: P) -> Box<dyn MetadataFormat> {
+    extension_format(path).unwrap_or_else(|| Box::new(JsonFormat))
+}
+
+/// Get the format of a metadata file from its extension, `None` if it isn't known
+pub fn extension_format<P: AsRef<Path>>(path: P) -> Option<Box<dyn MetadataFormat>> {
+    let extension = path.as_ref()
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "json" => Some(Box::new(JsonFormat)),
+        "toml" => Some(Box::new(TomlFormat)),
+        "yaml" | "yml" => Some(Box::new(YamlFormat)),
+        _ => None,
+    }
+}
+
+/// JSON, the default format
+pub struct JsonFormat;
+
+impl MetadataFormat for JsonFormat {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["json"]
+    }
+
+    fn parse(&self, s: &str) -> Result<JsonValue> {
+        Ok(::json::parse(s)?)
+    }
+
+    fn stringify(&self, json: &JsonValue) -> Result<String> {
+        Ok(::json::stringify_pretty(json.clone(), 2))
+    }
+}
+
+/// TOML, which has no null, so null values are left out
+pub struct TomlFormat;
+
+impl MetadataFormat for TomlFormat {
+    fn name(&self) -> &'static str {
+        "toml"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["toml"]
+    }
+
+    fn parse(&self, s: &str) -> Result<JsonValue> {
+        let toml = s.parse::<TomlValue>()?;
+        Ok(toml_to_json(&toml))
+    }
+
+    fn stringify(&self, json: &JsonValue) -> Result<String> {
+        match json_to_toml(json) {
+            Some(TomlValue::Table(t)) => Ok(::toml::to_string_pretty(&t)?),
+            _ => bail!(ErrorKind::InvalidMetadata),
+        }
+    }
+}
+
+/// YAML
+pub struct YamlFormat;
+
+impl MetadataFormat for YamlFormat {
+    fn name(&self) -> &'static str {
+        "yaml"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["yaml", "yml"]
+    }
+
+    fn parse(&self, s: &str) -> Result<JsonValue> {
+        match YamlLoader::load_from_str(s)?.first() {
+            Some(yaml) => yaml_to_json(yaml),
+            None => bail!(ErrorKind::InvalidMetadata),
+        }
+    }
+
+    fn stringify(&self, json: &JsonValue) -> Result<String> {
+        let mut s = String::new();
+        YamlEmitter::new(&mut s).dump(&json_to_yaml(json))?;
+        s.push('\n');
+        Ok(s)
+    }
+}
+
+fn toml_to_json(toml: &TomlValue) -> JsonValue {
+    match *toml {
+        TomlValue::String(ref s) => s.as_str().into(),
+        TomlValue::Integer(i) => i.into(),
+        TomlValue::Float(f) => f.into(),
+        TomlValue::Boolean(b) => b.into(),
+        TomlValue::Datetime(ref d) => d.to_string().as_str().into(),
+        TomlValue::Array(ref a) => JsonValue::Array(a.iter().map(toml_to_json).collect()),
+        TomlValue::Table(ref t) => {
+            let mut json = JsonValue::new_object();
+            for (k, v) in t {
+                json[k.as_str()] = toml_to_json(v);
+            }
+            json
+        }
+    }
+}
+
+/// Convert json to toml, `None` for null
+fn json_to_toml(json: &JsonValue) -> Option<TomlValue> {
+    let toml = match *json {
+        JsonValue::Null => return None,
+        JsonValue::Short(ref s) => TomlValue::String(s.to_string()),
+        JsonValue::String(ref s) => TomlValue::String(s.clone()),
+        JsonValue::Number(_) => match json.as_i64() {
+            Some(i) => TomlValue::Integer(i),
+            None => TomlValue::Float(json.as_f64()?),
+        },
+        JsonValue::Boolean(b) => TomlValue::Boolean(b),
+        JsonValue::Array(ref a) => TomlValue::Array(a.iter().filter_map(json_to_toml).collect()),
+        JsonValue::Object(ref o) => TomlValue::Table(
+            o.iter()
+                .filter_map(|(k, v)| json_to_toml(v).map(|v| (k.to_string(), v)))
+                .collect(),
+        ),
+    };
+    Some(toml)
+}
+
+fn yaml_to_json(yaml: &Yaml) -> Result<JsonValue> {
+    let json = match *yaml {
+        Yaml::String(ref s) => s.as_str().into(),
+        Yaml::Integer(i) => i.into(),
+        Yaml::Real(ref r) => match yaml.as_f64() {
+            Some(f) => f.into(),
+            None => bail!(ErrorKind::InvalidValue(r.clone())),
+        },
+        Yaml::Boolean(b) => b.into(),
+        Yaml::Null => JsonValue::Null,
+        Yaml::Array(ref a) => JsonValue::Array(a.iter().map(yaml_to_json).collect::<Result<_>>()?),
+        Yaml::Hash(ref h) => {
+            let mut json = JsonValue::new_object();
+            for (k, v) in h {
+                let key = match *k {
+                    Yaml::String(ref s) => s.clone(),
+                    Yaml::Integer(i) => i.to_string(),
+                    _ => bail!(ErrorKind::InvalidMetadata),
+                };
+                json[key.as_str()] = yaml_to_json(v)?;
+            }
+            json
+        }
+        Yaml::Alias(_) | Yaml::BadValue => bail!(ErrorKind::InvalidMetadata),
+    };
+    Ok(json)
+}
+
+fn json_to_yaml(json: &JsonValue) -> Yaml {
+    match *json {
+        JsonValue::Null => Yaml::Null,
+        JsonValue::Short(ref s) => Yaml::String(s.to_string()),
+        JsonValue::String(ref s) => Yaml::String(s.clone()),
+        JsonValue::Number(_) => match json.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(json.to_string()),
+        },
+        JsonValue::Boolean(b) => Yaml::Boolean(b),
+        JsonValue::Array(ref a) => Yaml::Array(a.iter().map(json_to_yaml).collect()),
+        JsonValue::Object(ref o) => Yaml::Hash(
+            o.iter()
+                .map(|(k, v)| (Yaml::String(k.to_string()), json_to_yaml(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn document() -> JsonValue {
+        object! {
+            "version" => 2,
+            "hupas" => array![
+                object! {
+                    "name" => "vim",
+                    "desc" => "true",
+                    "category" => array!["dotfiles", "123"],
+                    "origin" => "~/.vimrc",
+                    "autobackup" => false,
+                    "rules" => object! {
+                        "include" => array![],
+                        "max_file_size" => 100
+                    }
+                },
+                object! { "name" => "zsh", "autobackup" => true }
+            ]
+        }
+    }
+
+    #[test]
+    fn formats_roundtrip() {
+        for name in METADATA_FORMATS {
+            let format = get_format(name).unwrap();
+            assert_eq!(format.name(), *name);
+            let s = format.stringify(&document()).unwrap();
+            assert_eq!(format.parse(&s).unwrap(), document(), "{}:\n{}", name, s);
+        }
+        assert!(get_format("xml").is_err());
+    }
+
+    #[test]
+    fn parse_with_comments() {
+        let toml = "# Hupas of the team\nversion = 2\n\n[[hupas]]\nname = \"vim\" # editor\n";
+        let yaml = "# Hupas of the team\nversion: 2\nhupas:\n  - name: vim # editor\n";
+        let expected = object! { "version" => 2, "hupas" => array![object! { "name" => "vim" }] };
+        assert_eq!(TomlFormat.parse(toml).unwrap(), expected);
+        assert_eq!(YamlFormat.parse(yaml).unwrap(), expected);
+    }
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(detect_format("/a/metadata.toml").name(), "toml");
+        assert_eq!(detect_format("/a/metadata.YML").name(), "yaml");
+        assert_eq!(detect_format("/a/metadata.json").name(), "json");
+        assert_eq!(detect_format("/a/metadata").name(), "json");
+        assert!(extension_format("/a/metadata").is_none());
+        assert!(extension_format("/a/metadata.txt").is_none());
+    }
+}
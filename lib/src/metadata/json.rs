@@ -3,17 +3,19 @@
 use error::*;
 use json::JsonValue;
 use hupa::Hupa;
+use rules::Rules;
+use std::path::PathBuf;
 
 /// Convert hupa to json
 impl Into<JsonValue> for Hupa {
     fn into(self) -> JsonValue {
-        if self.get_needed_vars().len() == 0 {
+        let mut json = if self.get_needed_vars().len() == 0 {
             object! {
                 "name" => self.get_name(),
                 "desc" => self.get_desc(),
                 "category" => self.get_category().clone(),
-                "backup_parent" => self.get_backup_parent().display().to_string(),
-                "origin" => self.get_origin().display().to_string(),
+                "backup_parent" => self.get_backup_parent_template().display().to_string(),
+                "origin" => self.get_origin_templates()[0].display().to_string(),
                 "autobackup" => self.is_autobackup_enabled()
             }
         } else {
@@ -21,56 +23,255 @@ impl Into<JsonValue> for Hupa {
                 "name" => self.get_name(),
                 "desc" => self.get_desc(),
                 "category" => self.get_category().clone(),
-                "backup_parent" => self.get_backup_parent().display().to_string(),
-                "origin" => self.get_origin().display().to_string(),
+                "backup_parent" => self.get_backup_parent_template().display().to_string(),
+                "origin" => self.get_origin_templates()[0].display().to_string(),
                 "autobackup" => self.is_autobackup_enabled(),
-                "needed_vars" => self.get_needed_vars().clone()
+                "needed_vars" => self.get_needed_vars()
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<String>>()
             }
+        };
+        // The first origin is kept in "origin" for older versions
+        if self.get_origin_templates().len() > 1 {
+            json["origins"] = self.get_origin_templates()
+                .iter()
+                .map(|o| o.display().to_string())
+                .collect::<Vec<String>>()
+                .into();
+        }
+        if !self.get_rules().is_empty() {
+            json["rules"] = self.get_rules().clone().into();
+        }
+        if self.get_compression().is_compressed() {
+            json["compression"] = self.get_compression().name().into();
+        }
+        if self.is_encrypted() {
+            json["encrypted"] = true.into();
+        }
+        json
+    }
+}
+
+/// Convert rules to json
+impl From<Rules> for JsonValue {
+    fn from(rules: Rules) -> JsonValue {
+        let mut json = object! {
+            "include" => rules.get_include(),
+            "exclude" => rules.get_exclude(),
+            "one_file_system" => rules.is_one_file_system(),
+            "follow_symlinks" => rules.is_following_symlinks()
+        };
+        if let Some(max) = rules.get_max_file_size() {
+            json["max_file_size"] = max.into();
+        }
+        json
+    }
+}
+
+/// Fields of a hupa being read, to name the hupa and the field in errors
+struct Fields<'a> {
+    json: &'a JsonValue,
+    hupa: &'a str,
+    prefix: &'static str,
+}
+
+impl<'a> Fields<'a> {
+    /// Error about a field
+    fn error<S: ToString>(&self, field: &str, reason: S) -> Error {
+        let field = format!("{}{}", self.prefix, field);
+        ErrorKind::InvalidHupaField(
+            self.hupa.to_string(),
+            field.trim_end_matches('.').to_string(),
+            reason.to_string(),
+        ).into()
+    }
+
+    /// Error about a field of the wrong type
+    fn invalid(&self, field: &str, value: &JsonValue, expected: &str) -> Error {
+        let reason = if !self.json.has_key(field) {
+            format!("missing, expected {}", expected)
+        } else {
+            format!("expected {}, found {}", expected, describe(value))
+        };
+        self.error(field, reason)
+    }
+
+    /// Check if an optional field is present
+    fn has(&self, field: &str) -> bool {
+        !self.json[field].is_null()
+    }
+
+    fn str(&self, field: &str) -> Result<&'a str> {
+        let value = &self.json[field];
+        value.as_str().ok_or_else(|| self.invalid(field, value, "a string"))
+    }
+
+    fn bool(&self, field: &str) -> Result<bool> {
+        let value = &self.json[field];
+        value.as_bool().ok_or_else(|| self.invalid(field, value, "a boolean"))
+    }
+
+    fn u64(&self, field: &str) -> Result<u64> {
+        let value = &self.json[field];
+        value.as_u64().ok_or_else(|| self.invalid(field, value, "a positive integer"))
+    }
+
+    fn strs(&self, field: &str) -> Result<Vec<&'a str>> {
+        let value = &self.json[field];
+        if !value.is_array() {
+            return Err(self.invalid(field, value, "an array of strings"));
+        }
+        value
+            .members()
+            .enumerate()
+            .map(|(i, m)| {
+                m.as_str().ok_or_else(|| {
+                    self.error(
+                        &format!("{}[{}]", field, i),
+                        format!("expected a string, found {}", describe(m)),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Fields of an object field
+    fn object(&self, field: &str, prefix: &'static str) -> Result<Fields<'a>> {
+        let value = &self.json[field];
+        if !value.is_object() {
+            return Err(self.invalid(field, value, "an object"));
+        }
+        Ok(Fields {
+            json: value,
+            hupa: self.hupa,
+            prefix,
+        })
+    }
+}
+
+/// Describe the type of a json value
+fn describe(json: &JsonValue) -> &'static str {
+    match *json {
+        JsonValue::Null => "null",
+        JsonValue::Short(_) | JsonValue::String(_) => "a string",
+        JsonValue::Number(_) => "a number",
+        JsonValue::Boolean(_) => "a boolean",
+        JsonValue::Object(_) => "an object",
+        JsonValue::Array(_) => "an array",
+    }
+}
+
+/// Convert json to rules
+fn json_to_rules(fields: &Fields) -> Result<Rules> {
+    let include = if fields.has("include") { fields.strs("include")? } else { Vec::new() };
+    let exclude = if fields.has("exclude") { fields.strs("exclude")? } else { Vec::new() };
+    let max_file_size = if fields.has("max_file_size") {
+        Some(fields.u64("max_file_size")?)
+    } else {
+        None
+    };
+    let one_file_system = fields.has("one_file_system") && fields.bool("one_file_system")?;
+    let mut rules = Rules::new(&include, &exclude, max_file_size, one_file_system)
+        .map_err(|e| fields.error("", e))?;
+    rules.set_follow_symlinks(fields.has("follow_symlinks") && fields.bool("follow_symlinks")?);
+    Ok(rules)
+}
+
+/// Convert json to a hupa
+///
+/// `index` - Position of the hupa in metadata, to name it in errors
+fn json_to_hupa(index: usize, json: &JsonValue) -> Result<Hupa> {
+    let hupa = match json["name"].as_str() {
+        Some(name) => format!("{} ({})", index + 1, name),
+        None => (index + 1).to_string(),
+    };
+    let fields = Fields {
+        json,
+        hupa: &hupa,
+        prefix: "",
+    };
+    if !json.is_object() {
+        let reason = format!("expected an object, found {}", describe(json));
+        bail!(ErrorKind::InvalidHupa(hupa, reason));
+    }
+    let category = fields.strs("category")?.into_iter().map(String::from).collect();
+    let mut needed_vars = Vec::new();
+    if fields.has("needed_vars") {
+        for (i, r) in fields.strs("needed_vars")?.into_iter().enumerate() {
+            let requirement = r.parse()
+                .map_err(|e| fields.error(&format!("needed_vars[{}]", i), e))?;
+            needed_vars.push(requirement);
         }
     }
+    let mut hupa = Hupa::new(
+        fields.str("name")?,
+        fields.str("desc")?,
+        category,
+        fields.str("backup_parent")?,
+        fields.str("origin")?,
+        fields.bool("autobackup")?,
+        needed_vars,
+    );
+    if fields.has("origins") {
+        let origins = fields.strs("origins")?.into_iter().map(PathBuf::from).collect();
+        hupa.set_origins(origins);
+    }
+    if fields.has("rules") {
+        hupa.set_rules(json_to_rules(&fields.object("rules", "rules.")?)?);
+    }
+    if fields.has("compression") {
+        let compression = fields.str("compression")?
+            .parse()
+            .map_err(|e| fields.error("compression", e))?;
+        hupa.set_compression(compression);
+    }
+    if fields.has("encrypted") {
+        hupa.set_encrypted(fields.bool("encrypted")?);
+    }
+    Ok(hupa)
 }
 
 /// Convert json to hupas
+///
+/// Fail with `InvalidHupaField` at the first invalid hupa.
 pub fn json_to_hupas(json: &JsonValue) -> Result<Vec<Hupa>> {
-    let mut hupas = Vec::new();
     if !json.is_array() {
         bail!(ErrorKind::InvalidMetadata);
     }
-    for member in json.members() {
-        let name = member["name"].as_str().unwrap();
-        let desc = member["desc"].as_str().unwrap();
-        let category_json = &member["category"];
-        let mut category = Vec::new();
-        for sub_category in category_json.members() {
-            category.push(sub_category.as_str().unwrap().to_owned());
-        }
-        let backup_parent = member["backup_parent"].as_str().unwrap();
-        let origin = member["origin"].as_str().unwrap();
-        let autobackup = member["autobackup"].as_bool().unwrap();
-        let mut needed_vars = Vec::new();
-        if member["needed_vars"].is_array() {
-            for sub_member in member["needed_vars"].members() {
-                needed_vars.push(sub_member.as_str().unwrap().to_string());
-            }
-        }
-        hupas.push(Hupa::new(
-            name,
-            desc,
-            category,
-            backup_parent,
-            origin,
-            autobackup,
-            needed_vars,
-        ));
-    }
-    Ok(hupas)
+    json.members()
+        .enumerate()
+        .map(|(i, m)| json_to_hupa(i, m))
+        .collect()
 }
 
+/// Convert json to hupas, skipping invalid hupas
+///
+/// Return valid hupas and the errors of skipped ones.
+pub fn json_to_hupas_lenient(json: &JsonValue) -> Result<(Vec<Hupa>, Vec<Error>)> {
+    if !json.is_array() {
+        bail!(ErrorKind::InvalidMetadata);
+    }
+    let mut hupas = Vec::new();
+    let mut errors = Vec::new();
+    for (i, member) in json.members().enumerate() {
+        match json_to_hupa(i, member) {
+            Ok(h) => hupas.push(h),
+            Err(e) => errors.push(e),
+        }
+    }
+    Ok((hupas, errors))
+}
 
 #[cfg(test)]
 mod unit_tests {
     use json;
     use hupa::Hupa;
+    use super::{json_to_hupas, json_to_hupas_lenient};
+    use error::*;
+    use compression::Compression;
+    use std::path::PathBuf;
+    use rules::Rules;
 
     fn vec_of_hupas() -> Vec<Hupa> {
         vec![
@@ -123,6 +324,104 @@ mod unit_tests {
         }
     }
 
+    #[test]
+    fn test_rules_roundtrip() {
+        let mut hupa = vec_of_hupas().remove(0);
+        let mut rules = Rules::new(&["*.conf"], &["cache"], Some(1024), true).unwrap();
+        rules.set_follow_symlinks(true);
+        hupa.set_rules(rules.clone());
+        let json = json::from(hupa);
+        assert_eq!(json["rules"]["max_file_size"].as_u64(), Some(1024));
+        let hupas = json_to_hupas(&json::from(vec![json])).unwrap();
+        assert_eq!(hupas[0].get_rules(), &rules);
+    }
+
+    #[test]
+    fn test_origins_roundtrip() {
+        let mut hupa = vec_of_hupas().remove(0);
+        let origins = vec![PathBuf::from("/a"), PathBuf::from("/b")];
+        hupa.set_origins(origins.clone());
+        let json = json::from(hupa);
+        assert_eq!(json["origin"].as_str(), Some("/a"));
+        let hupas = json_to_hupas(&json::from(vec![json])).unwrap();
+        assert_eq!(hupas[0].get_origins(), &origins[..]);
+    }
+
+    #[test]
+    fn test_compression_roundtrip() {
+        let mut hupa = vec_of_hupas().remove(0);
+        hupa.set_compression(Compression::Zstd);
+        hupa.set_encrypted(true);
+        let json = json::from(hupa);
+        assert_eq!(json["compression"].as_str(), Some("zstd"));
+        let hupas = json_to_hupas(&json::from(vec![json])).unwrap();
+        assert_eq!(hupas[0].get_compression(), Compression::Zstd);
+        assert!(hupas[0].is_encrypted());
+    }
+
+    #[test]
+    fn test_needed_vars_roundtrip() {
+        let mut hupa = vec_of_hupas().remove(0);
+        hupa.set_needed_vars(vec![
+            "gentoo".parse().unwrap(),
+            "work || !(home && laptop)".parse().unwrap(),
+        ]);
+        let mut json = json::from(hupa.clone());
+        assert_eq!(json["needed_vars"][1].as_str(), Some("work || !(home && laptop)"));
+        let hupas = json_to_hupas(&json::from(vec![json.clone()])).unwrap();
+        assert_eq!(hupas[0].get_needed_vars(), hupa.get_needed_vars());
+        json["needed_vars"][1] = "work ||".into();
+        assert!(json_to_hupas(&json::from(vec![json])).is_err());
+    }
+
+    fn field_error(json: json::JsonValue) -> (String, String, String) {
+        match json_to_hupas(&json::from(vec![json])) {
+            Err(Error(ErrorKind::InvalidHupaField(h, f, r), _)) => (h, f, r),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        let valid = json::from(vec_of_hupas().remove(0));
+        let mut json = valid.clone();
+        json.remove("desc");
+        assert_eq!(
+            field_error(json),
+            ("1 (test1)".to_string(), "desc".to_string(), "missing, expected a string".to_string())
+        );
+        let mut json = valid.clone();
+        json["autobackup"] = "yes".into();
+        assert_eq!(field_error(json).2, "expected a boolean, found a string");
+        let mut json = valid.clone();
+        json["category"] = array!["a", 1];
+        assert_eq!(field_error(json).1, "category[1]");
+        let mut json = valid.clone();
+        json["rules"] = object! { "max_file_size" => "1M" };
+        assert_eq!(field_error(json).1, "rules.max_file_size");
+        let mut json = valid.clone();
+        json["compression"] = "rar".into();
+        assert_eq!(field_error(json).1, "compression");
+        let mut json = valid.clone();
+        json.remove("name");
+        assert_eq!(field_error(json).0, "1");
+        match json_to_hupas(&array![valid.clone(), 42]) {
+            Err(Error(ErrorKind::InvalidHupa(h, _), _)) => assert_eq!(h, "2"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_lenient_hupas() {
+        let valid = json::from(vec_of_hupas().remove(0));
+        let mut invalid = valid.clone();
+        invalid["autobackup"] = "yes".into();
+        let (hupas, errors) = json_to_hupas_lenient(&array![invalid, valid]).unwrap();
+        assert_eq!(hupas, vec![vec_of_hupas().remove(0)]);
+        assert_eq!(errors.len(), 1);
+        assert!(json_to_hupas_lenient(&object! {}).is_err());
+    }
+
     #[test]
     fn test_hupas_to_json() {
         let json = json::stringify(vec_of_hupas());
@@ -0,0 +1,67 @@
+//! Migration of metadata from older layouts
+//!
+//! - Version 1 is an array of hupas, without version
+//! - Version 2 is an object with the `version` and the array of `hupas`
+
+use error::*;
+use json::JsonValue;
+
+/// Version of metadata written by this version of hupa
+pub const METADATA_VERSION: u64 = 2;
+
+/// Get the version of metadata
+pub fn get_version(json: &JsonValue) -> Result<u64> {
+    if json.is_array() {
+        return Ok(1);
+    }
+    match json["version"].as_u64() {
+        Some(v) => Ok(v),
+        None => bail!(ErrorKind::InvalidMetadata),
+    }
+}
+
+/// Migrate metadata to the current version
+///
+/// Fail with `UnsupportedMetadataVersion` if it was written by a newer
+/// version of hupa.
+pub fn migrate(json: JsonValue) -> Result<JsonValue> {
+    let version = get_version(&json)?;
+    if version > METADATA_VERSION {
+        bail!(ErrorKind::UnsupportedMetadataVersion(version));
+    }
+    let mut json = json;
+    if version < 2 {
+        json = from_v1(json);
+    }
+    Ok(json)
+}
+
+fn from_v1(hupas: JsonValue) -> JsonValue {
+    object! {
+        "version" => 2,
+        "hupas" => hupas
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn migrate_versions() {
+        let v1 = array![object! { "name" => "a" }];
+        assert_eq!(get_version(&v1).unwrap(), 1);
+        let current = migrate(v1.clone()).unwrap();
+        assert_eq!(get_version(&current).unwrap(), METADATA_VERSION);
+        assert_eq!(current["hupas"], v1);
+        assert_eq!(migrate(current.clone()).unwrap(), current);
+        let newer = object! { "version" => METADATA_VERSION + 1, "hupas" => array![] };
+        match migrate(newer) {
+            Err(Error(ErrorKind::UnsupportedMetadataVersion(v), _)) => {
+                assert_eq!(v, METADATA_VERSION + 1)
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(migrate(object! { "hupas" => array![] }).is_err());
+    }
+}
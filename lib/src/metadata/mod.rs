@@ -1,53 +1,178 @@
 //! Metadata module is used to store and read metadata.
 //!
-//! Metadata file contains all info to instantiate hupas.
+//! Metadata file contains all info to instantiate hupas, with the version of
+//! its layout. Older layouts are migrated when read. It is written in json by
+//! default, or in another format of `METADATA_FORMATS`.
 
+mod format;
 mod json;
+mod migration;
+
+pub use self::format::{detect_format, extension_format, get_format, JsonFormat, MetadataFormat,
+                       TomlFormat, YamlFormat, METADATA_FORMATS};
+pub use self::migration::METADATA_VERSION;
 
 use config::*;
 use error::*;
 use hupa::Hupa;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{Read, Write};
 
-/// Read metadata from stream
+/// Read json metadata from stream
 ///
 /// `stream` - Stream to read metadata
 pub fn read_metadata<R: Read>(stream: &mut R) -> Result<Vec<Hupa>> {
+    read_metadata_with_format(stream, &JsonFormat)
+}
+
+/// Read metadata from stream
+///
+/// `stream` - Stream to read metadata
+///
+/// `format` - Format of metadata
+pub fn read_metadata_with_format<R: Read>(
+    stream: &mut R,
+    format: &dyn MetadataFormat,
+) -> Result<Vec<Hupa>> {
+    let json = migration::migrate(parse(stream, format)?)?;
+    Ok(json::json_to_hupas(&json["hupas"])?)
+}
+
+/// Read metadata from stream, skipping invalid hupas
+///
+/// `stream` - Stream to read metadata
+///
+/// `format` - Format of metadata
+pub fn read_metadata_lenient<R: Read>(
+    stream: &mut R,
+    format: &dyn MetadataFormat,
+) -> Result<LenientMetadata> {
+    let json = migration::migrate(parse(stream, format)?)?;
+    let (hupas, skipped) = json::json_to_hupas_lenient(&json["hupas"])?;
+    Ok(LenientMetadata { hupas, skipped })
+}
+
+/// Hupas read from metadata, without the invalid ones
+#[derive(Debug)]
+pub struct LenientMetadata {
+    /// Valid hupas
+    pub hupas: Vec<Hupa>,
+    /// Errors of skipped hupas
+    pub skipped: Vec<Error>,
+}
+
+/// Read the version of metadata from stream
+pub fn read_metadata_version<R: Read>(stream: &mut R, format: &dyn MetadataFormat) -> Result<u64> {
+    migration::get_version(&parse(stream, format)?)
+}
+
+fn parse<R: Read>(stream: &mut R, format: &dyn MetadataFormat) -> Result<::json::JsonValue> {
     let mut buffer = String::new();
     stream.read_to_string(&mut buffer)?;
-    let json = ::json::parse(&buffer)?;
-    Ok(json::json_to_hupas(&json)?)
+    format.parse(&buffer)
 }
 
 /// Read metadata from config
 ///
+/// Hupas get the secret of the config to encrypt their backups, see
+/// `Config::get_secret`.
+///
 /// `config` - A reference to config
 pub fn read_metadata_from_config(config: &Config) -> Result<Vec<Hupa>> {
+    let format = config.get_metadata_format()?;
     let mut f = match File::open(&config.metadata_path) {
         Ok(f) => f,
         Err(_) => return Ok(Vec::new()),
     };
-    read_metadata(&mut f)
+    let mut hupas = read_metadata_with_format(&mut f, &*format)?;
+    for hupa in &mut hupas {
+        hupa.set_secret(config.get_secret());
+    }
+    Ok(hupas)
 }
 
-/// Write metadata to a stream
+/// Read metadata from config, skipping invalid hupas
+///
+/// `config` - A reference to config
+pub fn read_metadata_from_config_lenient(config: &Config) -> Result<LenientMetadata> {
+    let format = config.get_metadata_format()?;
+    let mut f = match File::open(&config.metadata_path) {
+        Ok(f) => f,
+        Err(_) => {
+            return Ok(LenientMetadata {
+                hupas: Vec::new(),
+                skipped: Vec::new(),
+            })
+        }
+    };
+    let mut metadata = read_metadata_lenient(&mut f, &*format)?;
+    for hupa in &mut metadata.hupas {
+        hupa.set_secret(config.get_secret());
+    }
+    Ok(metadata)
+}
+
+/// Write json metadata to a stream
 ///
 /// `stream` - Stream to write metadata
 ///
 /// `hupas` - Hupas to write metadata
 pub fn write_metadata<W: Write>(stream: &mut W, hupas: &[Hupa]) -> Result<()> {
-    let to_write = ::json::stringify_pretty(hupas.to_vec(), 2)
-        .as_bytes()
-        .to_vec();
-    stream.write_all(&to_write)?;
+    write_metadata_with_format(stream, hupas, &JsonFormat)
+}
+
+/// Write metadata to a stream
+///
+/// `stream` - Stream to write metadata
+///
+/// `hupas` - Hupas to write metadata
+///
+/// `format` - Format of metadata
+pub fn write_metadata_with_format<W: Write>(
+    stream: &mut W,
+    hupas: &[Hupa],
+    format: &dyn MetadataFormat,
+) -> Result<()> {
+    let json = object! {
+        "version" => METADATA_VERSION,
+        "hupas" => hupas.to_vec()
+    };
+    stream.write_all(format.stringify(&json)?.as_bytes())?;
     Ok(())
 }
 
+/// Write metadata to the path of config
+///
+/// A metadata file of another version is first copied next to it, as
+/// `<name>.v<version>.bak`, so it is not lost when rewritten. An existing
+/// copy is never replaced.
+pub fn write_metadata_to_config(config: &Config, hupas: &[Hupa]) -> Result<()> {
+    let path = &config.metadata_path;
+    let format = config.get_metadata_format()?;
+    if let Ok(mut f) = File::open(path) {
+        let suffix = match read_metadata_version(&mut f, &*format) {
+            Ok(METADATA_VERSION) => None,
+            Ok(v) => Some(format!("v{}.bak", v)),
+            Err(_) => Some("bak".to_string()),
+        };
+        if let Some(suffix) = suffix {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(format!(".{}", suffix));
+            let backup = path.with_file_name(name);
+            if !backup.exists() {
+                fs::copy(path, backup)?;
+            }
+        }
+    }
+    let mut f = File::create(path)?;
+    write_metadata_with_format(&mut f, hupas, &*format)
+}
+
 #[cfg(test)]
 mod unit_tests {
-    use std::io::Cursor;
     use super::*;
+    use std::io::Cursor;
+    use test_utils::test_dir;
 
     #[test]
     fn read_metadata_test() {
@@ -84,5 +209,52 @@ mod unit_tests {
         let mut cursor = Cursor::new(json);
         let readed_hupas = read_metadata(&mut cursor).unwrap();
         assert_eq!(hupas, readed_hupas);
+        let mut written = Vec::new();
+        write_metadata(&mut written, &hupas).unwrap();
+        assert_eq!(
+            read_metadata_version(&mut written.as_slice(), &JsonFormat).unwrap(),
+            METADATA_VERSION
+        );
+        assert_eq!(read_metadata(&mut written.as_slice()).unwrap(), hupas);
+    }
+
+    #[test]
+    fn write_metadata_to_config_test() {
+        let tmp = test_dir("metadata-migration");
+        let dir = tmp.path();
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join("metadata.json");
+        let config = Config::new(&path, 3600, dir.join("vars"));
+        let hupas = vec![Hupa::new("a", "b", Vec::new(), "/", "/", false, Vec::new())];
+        let v1 = ::json::stringify(hupas.clone());
+        fs::write(&path, &v1).unwrap();
+        assert_eq!(read_metadata_from_config(&config).unwrap(), hupas);
+        write_metadata_to_config(&config, &hupas).unwrap();
+        let backup = dir.join("metadata.json.v1.bak");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), v1);
+        // The backup is only written before the first rewrite
+        write_metadata_to_config(&config, &[]).unwrap();
+        write_metadata_to_config(&config, &hupas).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), v1);
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn write_metadata_formats_test() {
+        let tmp = test_dir("metadata-formats");
+        let dir = tmp.path();
+        fs::create_dir_all(dir).unwrap();
+        let hupas = vec![Hupa::new("a", "b", vec!["c".to_string()], "/", "/", false, Vec::new())];
+        let mut config = Config::new(dir.join("metadata.toml"), 3600, dir.join("vars"));
+        write_metadata_to_config(&config, &hupas).unwrap();
+        let written = fs::read_to_string(&config.metadata_path).unwrap();
+        assert!(written.contains("[[hupas]]"), "{}", written);
+        assert_eq!(read_metadata_from_config(&config).unwrap(), hupas);
+        // The config format wins over the extension
+        config.metadata_path = dir.join("metadata.json");
+        config.metadata_format = Some("yaml".to_string());
+        write_metadata_to_config(&config, &hupas).unwrap();
+        let mut f = File::open(&config.metadata_path).unwrap();
+        assert_eq!(read_metadata_with_format(&mut f, &YamlFormat).unwrap(), hupas);
     }
 }
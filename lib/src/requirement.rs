@@ -0,0 +1,446 @@
+//! Requirements on vars to backup or restore a hupa
+//!
+//! A requirement is a boolean expression of vars, like `gentoo && !laptop` or
+//! `work || home`. `!` binds tighter than `&&`, which binds tighter than `||`,
+//! and parentheses group sub-expressions. A var alone is met if it is `true`.
+//!
+//! Vars can be compared to a value with `==`, `!=`, `<`, `<=`, `>` and `>=`,
+//! like `distro == gentoo` or `cores >= 8`. Only integers can be ordered, an
+//! ordering of other values is never met. Values can be quoted, like
+//! `screen == "hi dpi"`.
+//!
+//! Built-in facts, like `distro == gentoo` or `bin.git`, are used as vars.
+
+use error::*;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use vars::{Value, VarsHandler};
+
+/// Operator comparing a var to a value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+impl Comparison {
+    /// Compare `value` of a var to the value of a requirement
+    ///
+    /// Only integers are ordered, other values are equal or not.
+    pub fn compare(&self, value: &Value, other: &Value) -> bool {
+        let ordering = match (value, other) {
+            (&Value::Int(a), &Value::Int(b)) => a.cmp(&b),
+            _ => {
+                let equal = value.to_string() == other.to_string();
+                return match *self {
+                    Comparison::Eq => equal,
+                    Comparison::Ne => !equal,
+                    _ => false,
+                };
+            }
+        };
+        match *self {
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ne => ordering != Ordering::Equal,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// Boolean expression of vars
+#[derive(Clone, Debug, PartialEq)]
+pub enum Requirement {
+    /// Var which must be `true`
+    Var(String),
+    /// Var which must compare to a value, unmet if the var is not set
+    Compare(String, Comparison, Value),
+    /// Requirement which must not be met
+    Not(Box<Requirement>),
+    /// Requirements which must all be met
+    And(Vec<Requirement>),
+    /// Requirements of which one must be met
+    Or(Vec<Requirement>),
+}
+
+impl Requirement {
+    /// Check if the requirement is met by vars
+    pub fn is_met(&self, vars_handler: &VarsHandler) -> bool {
+        self.unmet(vars_handler).is_none()
+    }
+
+    /// Find the sub-expression making the requirement unmet
+    ///
+    /// The first unmet operand of `&&` is looked into, `||` and `!` are unmet
+    /// as a whole.
+    pub fn unmet(&self, vars_handler: &VarsHandler) -> Option<&Requirement> {
+        match *self {
+            Requirement::Var(ref name) => match vars_handler.get_var(name) {
+                Some(v) if v.is_true() => None,
+                _ => Some(self),
+            },
+            Requirement::Compare(ref name, op, ref value) => match vars_handler.get_var(name) {
+                Some(ref v) if op.compare(v, value) => None,
+                _ => Some(self),
+            },
+            Requirement::Not(ref r) => match r.unmet(vars_handler) {
+                Some(_) => None,
+                None => Some(self),
+            },
+            Requirement::And(ref rs) => rs.iter().filter_map(|r| r.unmet(vars_handler)).next(),
+            Requirement::Or(ref rs) => if rs.iter().any(|r| r.is_met(vars_handler)) {
+                None
+            } else {
+                Some(self)
+            },
+        }
+    }
+
+    /// Check the requirement, with an error naming what is unmet
+    pub fn check(&self, vars_handler: &VarsHandler) -> Result<()> {
+        match self.unmet(vars_handler) {
+            None => Ok(()),
+            Some(Requirement::Var(name)) => bail!(ErrorKind::MissingNeededVar(name.clone())),
+            Some(unmet) => bail!(ErrorKind::UnmetRequirement(
+                unmet.to_string(),
+                self.to_string(),
+            )),
+        }
+    }
+
+    /// Write an operand of `parent`, with parentheses if it binds less
+    fn fmt_operand(&self, f: &mut fmt::Formatter, parent: u8) -> fmt::Result {
+        if self.precedence() < parent {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match *self {
+            Requirement::Or(_) => 0,
+            Requirement::And(_) => 1,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (rs, op) = match *self {
+            Requirement::Var(ref name) => return write!(f, "{}", name),
+            Requirement::Compare(ref name, op, ref value) => {
+                return write!(f, "{} {} {}", name, op, quote(value));
+            }
+            Requirement::Not(ref r) => {
+                write!(f, "!")?;
+                return r.fmt_operand(f, 2);
+            }
+            Requirement::And(ref rs) => (rs, " && "),
+            Requirement::Or(ref rs) => (rs, " || "),
+        };
+        for (i, r) in rs.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", op)?;
+            }
+            r.fmt_operand(f, self.precedence() + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Requirement> {
+        let invalid = |reason: &str| -> Error {
+            ErrorKind::InvalidRequirement(s.to_string(), reason.to_string()).into()
+        };
+        let tokens = tokenize(s).map_err(|e| invalid(&e))?;
+        let mut parser = Parser { tokens, position: 0 };
+        let requirement = parser.parse_or().map_err(|e| invalid(&e))?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(requirement),
+            Some(t) => Err(invalid(&format!("unexpected {}", t))),
+        }
+    }
+}
+
+/// Write a value so it is parsed back the same
+fn quote(value: &Value) -> String {
+    let s = value.to_string();
+    if !s.is_empty() && s.chars().all(is_name_char) && Value::parse(&s) == *value {
+        return s;
+    }
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Quoted(String),
+    Compare(Comparison),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Name(ref n) => write!(f, "`{}`", n),
+            Token::Quoted(ref s) => write!(f, "`\"{}\"`", s),
+            Token::Compare(op) => write!(f, "`{}`", op),
+            Token::Not => write!(f, "`!`"),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+/// Split a requirement in tokens, errors are the reason
+fn tokenize(s: &str) -> ::std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = match c {
+            '=' if rest.starts_with("==") => (Token::Compare(Comparison::Eq), 2),
+            '!' if rest.starts_with("!=") => (Token::Compare(Comparison::Ne), 2),
+            '<' if rest.starts_with("<=") => (Token::Compare(Comparison::Le), 2),
+            '>' if rest.starts_with(">=") => (Token::Compare(Comparison::Ge), 2),
+            '<' => (Token::Compare(Comparison::Lt), 1),
+            '>' => (Token::Compare(Comparison::Gt), 1),
+            '"' => {
+                let (quoted, len) = read_quoted(rest).ok_or_else(|| "unclosed `\"`".to_string())?;
+                (Token::Quoted(quoted), len)
+            }
+            '!' => (Token::Not, 1),
+            '(' => (Token::Open, 1),
+            ')' => (Token::Close, 1),
+            '&' if rest.starts_with("&&") => (Token::And, 2),
+            '|' if rest.starts_with("||") => (Token::Or, 2),
+            c if is_name_char(c) => {
+                let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+                (Token::Name(rest[..len].to_string()), len)
+            }
+            c => return Err(format!("unexpected `{}`", c)),
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Read a quoted string at the start of `s`, with its length in `s`
+fn read_quoted(s: &str) -> Option<(String, usize)> {
+    let mut quoted = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((quoted, i + 1)),
+            '\\' => quoted.push(chars.next()?.1),
+            c => quoted.push(c),
+        }
+    }
+    None
+}
+
+/// Recursive descent parser of tokens, errors are the reason
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn accept(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.position) == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> ::std::result::Result<Requirement, String> {
+        let mut operands = vec![self.parse_and()?];
+        while self.accept(&Token::Or) {
+            operands.push(self.parse_and()?);
+        }
+        Ok(if operands.len() == 1 {
+            operands.remove(0)
+        } else {
+            Requirement::Or(operands)
+        })
+    }
+
+    fn parse_and(&mut self) -> ::std::result::Result<Requirement, String> {
+        let mut operands = vec![self.parse_not()?];
+        while self.accept(&Token::And) {
+            operands.push(self.parse_not()?);
+        }
+        Ok(if operands.len() == 1 {
+            operands.remove(0)
+        } else {
+            Requirement::And(operands)
+        })
+    }
+
+    fn parse_not(&mut self) -> ::std::result::Result<Requirement, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Requirement::Not(Box::new(self.parse_not()?))),
+            Some(Token::Name(name)) => match self.tokens.get(self.position).cloned() {
+                Some(Token::Compare(op)) => {
+                    self.position += 1;
+                    let value = match self.next() {
+                        Some(Token::Name(v)) => Value::parse(v),
+                        Some(Token::Quoted(v)) => Value::Str(v),
+                        Some(t) => return Err(format!("unexpected {}", t)),
+                        None => return Err("unexpected end".to_string()),
+                    };
+                    Ok(Requirement::Compare(name, op, value))
+                }
+                _ => Ok(Requirement::Var(name)),
+            },
+            Some(Token::Open) => {
+                let requirement = self.parse_or()?;
+                if !self.accept(&Token::Close) {
+                    return Err("missing `)`".to_string());
+                }
+                Ok(requirement)
+            }
+            Some(t) => Err(format!("unexpected {}", t)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn vars() -> VarsHandler {
+        VarsHandler::new(vec![
+            ("gentoo".to_string(), Value::Bool(true)),
+            ("laptop".to_string(), Value::Bool(true)),
+            ("work".to_string(), Value::Bool(false)),
+            ("distro".to_string(), Value::Str("gentoo".to_string())),
+            ("cores".to_string(), Value::Int(16)),
+        ])
+    }
+
+    #[test]
+    fn parse_requirement() {
+        let r = "gentoo && !laptop || (work || home) && x".parse::<Requirement>().unwrap();
+        assert_eq!(
+            r,
+            Requirement::Or(vec![
+                Requirement::And(vec![
+                    Requirement::Var("gentoo".to_string()),
+                    Requirement::Not(Box::new(Requirement::Var("laptop".to_string()))),
+                ]),
+                Requirement::And(vec![
+                    Requirement::Or(vec![
+                        Requirement::Var("work".to_string()),
+                        Requirement::Var("home".to_string()),
+                    ]),
+                    Requirement::Var("x".to_string()),
+                ]),
+            ])
+        );
+        assert_eq!(r.to_string(), "gentoo && !laptop || (work || home) && x");
+        assert_eq!(r.to_string().parse::<Requirement>().unwrap(), r);
+        let r = "distro == gentoo && cores >= 8 && screen != \"hi dpi\" && n < \"16\""
+            .parse::<Requirement>()
+            .unwrap();
+        assert_eq!(
+            r,
+            Requirement::And(vec![
+                Requirement::Compare("distro".to_string(), Comparison::Eq, Value::Str("gentoo".to_string())),
+                Requirement::Compare("cores".to_string(), Comparison::Ge, Value::Int(8)),
+                Requirement::Compare("screen".to_string(), Comparison::Ne, Value::Str("hi dpi".to_string())),
+                Requirement::Compare("n".to_string(), Comparison::Lt, Value::Str("16".to_string())),
+            ])
+        );
+        assert_eq!(r.to_string().parse::<Requirement>().unwrap(), r);
+        for invalid in &["", "a &&", "a & b", "(a", "a)", "!", "a b", "a ++", "a ==", "a == \"b", "== a"] {
+            assert!(invalid.parse::<Requirement>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn unmet_requirement() {
+        let vars = vars();
+        let unmet = |s: &str| {
+            let r = s.parse::<Requirement>().unwrap();
+            r.unmet(&vars).map(|u| u.to_string())
+        };
+        assert_eq!(unmet("gentoo"), None);
+        assert_eq!(unmet("gentoo && !work"), None);
+        assert_eq!(unmet("work || laptop"), None);
+        assert_eq!(unmet("gentoo && !laptop"), Some("!laptop".to_string()));
+        assert_eq!(unmet("work || home"), Some("work || home".to_string()));
+        assert_eq!(unmet("gentoo && home"), Some("home".to_string()));
+        assert_eq!(unmet("distro == gentoo && cores > 8 && cores < 100"), None);
+        assert_eq!(unmet("distro != arch && cores <= 16 && !(laptop == false)"), None);
+        assert_eq!(unmet("cores >= 32"), Some("cores >= 32".to_string()));
+        assert_eq!(unmet("missing != a"), Some("missing != a".to_string()));
+        // Strings are not ordered
+        assert_eq!(unmet("distro > arch"), Some("distro > arch".to_string()));
+        assert_eq!(unmet("distro <= gentoo"), Some("distro <= gentoo".to_string()));
+        assert_eq!(unmet("cores < \"2\""), Some("cores < \"2\"".to_string()));
+        let builtins = VarsHandler::new(Vec::new()).with_builtins();
+        let os = format!("os == {}", ::std::env::consts::OS);
+        assert!(os.parse::<Requirement>().unwrap().is_met(&builtins));
+        assert!(!"bin.hupa-missing-binary".parse::<Requirement>().unwrap().is_met(&builtins));
+        let r = "gentoo && !laptop".parse::<Requirement>().unwrap();
+        match r.check(&vars) {
+            Err(Error(ErrorKind::UnmetRequirement(ref u, ref w), _)) => {
+                assert_eq!(u, "!laptop");
+                assert_eq!(w, "gentoo && !laptop");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Rules selecting which files of an origin are backed up
+//!
+//! Patterns are globs. A pattern containing a `/` is matched against the path
+//! relative to the origin, otherwise it is matched against the file name.
+
+use error::*;
+use glob::{MatchOptions, Pattern};
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+/// Rules of a hupa
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rules {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    max_file_size: Option<u64>,
+    one_file_system: bool,
+    follow_symlinks: bool,
+}
+
+impl Rules {
+    /// Default constructor
+    ///
+    /// `include` - Only files matching one of these patterns are kept, all files if empty
+    ///
+    /// `exclude` - Files and directories matching one of these patterns are skipped
+    ///
+    /// `max_file_size` - Files larger than this size in bytes are skipped
+    ///
+    /// `one_file_system` - Don't cross filesystem boundaries
+    pub fn new<S: AsRef<str>>(
+        include: &[S],
+        exclude: &[S],
+        max_file_size: Option<u64>,
+        one_file_system: bool,
+    ) -> Result<Rules> {
+        Ok(Rules {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+            max_file_size,
+            one_file_system,
+            follow_symlinks: false,
+        })
+    }
+
+    /// Get include patterns
+    pub fn get_include(&self) -> Vec<&str> {
+        self.include.iter().map(|p| p.as_str()).collect()
+    }
+
+    /// Get exclude patterns
+    pub fn get_exclude(&self) -> Vec<&str> {
+        self.exclude.iter().map(|p| p.as_str()).collect()
+    }
+
+    /// Get max file size
+    pub fn get_max_file_size(&self) -> Option<u64> {
+        self.max_file_size
+    }
+
+    /// Check if filesystem boundaries are not crossed
+    pub fn is_one_file_system(&self) -> bool {
+        self.one_file_system
+    }
+
+    /// Check if symlinks are followed instead of copied as symlinks
+    pub fn is_following_symlinks(&self) -> bool {
+        self.follow_symlinks
+    }
+
+    /// Set if symlinks are followed instead of copied as symlinks
+    pub fn set_follow_symlinks(&mut self, follow_symlinks: bool) {
+        self.follow_symlinks = follow_symlinks;
+    }
+
+    /// Get metadata of an entry, following symlinks only if asked
+    ///
+    /// The root is always followed, a dangling symlink is never followed.
+    pub fn metadata(&self, path: &Path, relative: &Path) -> io::Result<Metadata> {
+        if self.follow_symlinks || relative.as_os_str().is_empty() {
+            path.metadata().or_else(|_| path.symlink_metadata())
+        } else {
+            path.symlink_metadata()
+        }
+    }
+
+    /// Check if no rule is set
+    pub fn is_empty(&self) -> bool {
+        *self == Rules::default()
+    }
+
+    /// Check if an entry is skipped
+    ///
+    /// `relative` - Path of the entry relative to the root, the root itself is never skipped
+    ///
+    /// `metadata` - Metadata of the entry
+    ///
+    /// `root_device` - Device of the root, see `device`
+    pub fn is_excluded(&self, relative: &Path, metadata: &Metadata, root_device: Option<u64>) -> bool {
+        if relative.as_os_str().is_empty() {
+            return false;
+        }
+        if matches(&self.exclude, relative) {
+            return true;
+        }
+        if self.one_file_system && root_device.is_some() && device(metadata) != root_device {
+            return true;
+        }
+        if metadata.is_dir() {
+            return false;
+        }
+        if !self.include.is_empty() && !matches(&self.include, relative) {
+            return true;
+        }
+        match self.max_file_size {
+            Some(max) => metadata.len() > max,
+            None => false,
+        }
+    }
+}
+
+/// Get the device of a file, used to detect filesystem boundaries
+#[cfg(unix)]
+pub fn device(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+/// Get the device of a file, used to detect filesystem boundaries
+#[cfg(not(unix))]
+pub fn device(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+fn compile<S: AsRef<str>>(patterns: &[S]) -> Result<Vec<Pattern>> {
+    let mut compiled = Vec::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        match Pattern::new(pattern) {
+            Ok(p) => compiled.push(p),
+            Err(_) => bail!(ErrorKind::InvalidPattern(pattern.to_string())),
+        }
+    }
+    Ok(compiled)
+}
+
+fn matches(patterns: &[Pattern], relative: &Path) -> bool {
+    let options = MatchOptions::new();
+    patterns.iter().any(|p| if p.as_str().contains('/') {
+        p.matches_path_with(relative, options)
+    } else {
+        relative
+            .file_name()
+            .map(|n| p.matches_with(&n.to_string_lossy(), options))
+            .unwrap_or(false)
+    })
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use std::fs;
+    use test_utils::test_dir;
+
+    #[test]
+    fn rules_exclude() {
+        let tmp = test_dir("rules");
+        let dir = tmp.path();
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("big"), "0123456789").unwrap();
+        fs::write(dir.join("small.conf"), "0").unwrap();
+        let file = fs::metadata(dir.join("big")).unwrap();
+        let small = fs::metadata(dir.join("small.conf")).unwrap();
+        let dir_metadata = fs::metadata(dir).unwrap();
+        let rules = Rules::new(&["*.conf"], &["cache", "sub/*.lock"], Some(5), false).unwrap();
+        assert!(!rules.is_excluded(Path::new(""), &file, None));
+        assert!(rules.is_excluded(Path::new("a/cache"), &dir_metadata, None));
+        assert!(rules.is_excluded(Path::new("sub/a.lock"), &small, None));
+        assert!(!rules.is_excluded(Path::new("other/a.conf"), &small, None));
+        assert!(rules.is_excluded(Path::new("other/a"), &small, None));
+        assert!(rules.is_excluded(Path::new("big.conf"), &file, None));
+        assert!(!rules.is_excluded(Path::new("dir"), &dir_metadata, None));
+        assert!(Rules::new(&["[a"], &[], None, false).is_err());
+        assert!(Rules::default().is_empty());
+    }
+}
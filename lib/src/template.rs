@@ -0,0 +1,172 @@
+//! Templates of paths
+//!
+//! Origins and backup parents are stored as written by the user, so one
+//! metadata file works for every user and machine, and expanded when used:
+//!
+//! - `~` at the start is the home of the user running hupa
+//! - `$NAME` or `${NAME}` is the var `NAME` of the vars file, else the
+//!   environment variable `NAME`, else a built-in fact like `hostname`
+//! - `$$` is a `$`
+
+use error::*;
+use facts::get_fact;
+use std::env;
+use std::path::{Path, PathBuf};
+use vars::VarsHandler;
+
+/// Check if a path has something to expand
+pub fn is_template<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref().to_string_lossy();
+    path.starts_with('~') || path.contains('$')
+}
+
+/// Expand a path, fail with `UndefinedVar` if a variable has no value
+pub fn expand_path<P: AsRef<Path>>(template: P, vars_handler: &VarsHandler) -> Result<PathBuf> {
+    let template = template.as_ref();
+    if !is_template(template) {
+        return Ok(template.to_path_buf());
+    }
+    match expand_with(&template.to_string_lossy(), |n| lookup(n, vars_handler), true) {
+        Ok(p) => Ok(PathBuf::from(p)),
+        Err(name) => bail!(ErrorKind::UndefinedVar(name)),
+    }
+}
+
+/// Expand a path, keeping variables without value as they are written
+pub fn expand_path_lossy<P: AsRef<Path>>(template: P, vars_handler: &VarsHandler) -> PathBuf {
+    let template = template.as_ref();
+    if !is_template(template) {
+        return template.to_path_buf();
+    }
+    match expand_with(&template.to_string_lossy(), |n| lookup(n, vars_handler), false) {
+        Ok(p) => PathBuf::from(p),
+        Err(_) => template.to_path_buf(),
+    }
+}
+
+/// Get the value of a variable of a template
+fn lookup(name: &str, vars_handler: &VarsHandler) -> Option<String> {
+    if let Some(value) = vars_handler.get_var(name) {
+        return Some(value.to_string());
+    }
+    if let Ok(value) = env::var(name) {
+        return Some(value);
+    }
+    match name {
+        "HOME" => env::var("USERPROFILE").ok(),
+        _ => get_fact(name).map(|v| v.to_string()),
+    }
+}
+
+/// Expand a template with `lookup`
+///
+/// When `strict`, return the name of the first variable without value as
+/// error, else keep it as written.
+fn expand_with<F: Fn(&str) -> Option<String>>(
+    template: &str,
+    lookup: F,
+    strict: bool,
+) -> ::std::result::Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    if rest == "~" || rest.starts_with("~/") {
+        match lookup("HOME") {
+            Some(home) => expanded.push_str(&home),
+            None if strict => return Err("HOME".to_string()),
+            None => expanded.push('~'),
+        }
+        rest = &rest[1..];
+    }
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(r) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = r;
+            continue;
+        }
+        let (name, written, len) = if rest.starts_with('{') {
+            match rest.find('}') {
+                Some(end) => (&rest[1..end], &rest[..end + 1], end + 1),
+                None => ("", "", 0),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[..end], end)
+        };
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+        match lookup(name) {
+            Some(value) => expanded.push_str(&value),
+            None if strict => return Err(name.to_string()),
+            None => {
+                expanded.push('$');
+                expanded.push_str(written);
+            }
+        }
+        rest = &rest[len..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use vars::Value;
+
+    fn test_lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/user".to_string()),
+            "XDG_CONFIG_HOME" => Some("/home/user/.config".to_string()),
+            "hostname" => Some("box".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expand_templates() {
+        let cases = [
+            ("~/.vimrc", "/home/user/.vimrc"),
+            ("~", "/home/user"),
+            ("/etc/~", "/etc/~"),
+            ("$XDG_CONFIG_HOME/foo", "/home/user/.config/foo"),
+            ("/backups/${hostname}/etc", "/backups/box/etc"),
+            ("/a/$hostname-x", "/a/box-x"),
+            ("/cost/$$5", "/cost/$5"),
+            ("/a/$/b", "/a/$/b"),
+        ];
+        for &(template, path) in &cases {
+            assert_eq!(expand_with(template, test_lookup, true).unwrap(), path);
+        }
+        assert_eq!(
+            expand_with("/a/${NOPE}/b", test_lookup, true),
+            Err("NOPE".to_string())
+        );
+        assert_eq!(
+            expand_with("/a/${NOPE}/$NOPE", test_lookup, false).unwrap(),
+            "/a/${NOPE}/$NOPE"
+        );
+    }
+
+    #[test]
+    fn expand_with_vars() {
+        let vars = VarsHandler::new(vec![
+            ("distro".to_string(), Value::Str("gentoo".to_string())),
+            ("cores".to_string(), Value::Int(16)),
+        ]);
+        assert_eq!(
+            expand_path("/backups/$distro/${cores}", &vars).unwrap(),
+            PathBuf::from("/backups/gentoo/16")
+        );
+        assert!(expand_path("/backups/$hupa_undefined_var", &vars).is_err());
+        assert_eq!(
+            expand_path_lossy("/backups/$hupa_undefined_var", &vars),
+            PathBuf::from("/backups/$hupa_undefined_var")
+        );
+    }
+}
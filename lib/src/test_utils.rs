@@ -0,0 +1,11 @@
+//! Helpers shared by unit tests
+
+use tempfile::{Builder, TempDir};
+
+/// Create a unique temporary directory, removed with its content when dropped
+pub fn test_dir(name: &str) -> TempDir {
+    Builder::new()
+        .prefix(&format!("hupa-test-{}-", name))
+        .tempdir()
+        .unwrap()
+}
@@ -3,23 +3,140 @@
 use APP_INFO;
 use app_dirs::*;
 use error::*;
+use facts::{get_fact, get_facts, hostname};
+use std::fmt;
 use std::io::{Read, Write};
 use std::ops::*;
 use std::path::PathBuf;
 use std::vec::IntoIter;
 
-/// Var type, first arg is var name and second arg is var's state (enabled or disabled)
-pub type Var = (String, bool);
+/// Var type, first arg is var name and second arg is var's value
+pub type Var = (String, Value);
+
+/// Value of a var
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// `true` or `false`
+    Bool(bool),
+    /// Integer, like `16`
+    Int(i64),
+    /// Anything else, like `gentoo`
+    Str(String),
+}
+
+impl Value {
+    /// Parse a value as written in vars file, it is a string if it is neither
+    /// a boolean nor an integer
+    pub fn parse<S: AsRef<str>>(s: S) -> Value {
+        let s = s.as_ref();
+        if let Ok(b) = s.parse::<bool>() {
+            Value::Bool(b)
+        } else if let Ok(i) = s.parse::<i64>() {
+            Value::Int(i)
+        } else {
+            Value::Str(s.to_string())
+        }
+    }
+
+    /// Check if the value is `true`
+    pub fn is_true(&self) -> bool {
+        *self == Value::Bool(true)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Int(i)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Str(s)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Str(ref s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// Place of a var in vars file
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Layout {
+    /// Comment, blank and section lines written before the var
+    comments: Vec<String>,
+    /// Host of the section of the var, `None` if it applies to every host
+    host: Option<String>,
+}
+
+impl Layout {
+    /// Get the number of last comment lines describing the var
+    fn description_len(&self) -> usize {
+        self.comments
+            .iter()
+            .rev()
+            .take_while(|c| c.starts_with('#'))
+            .count()
+    }
+}
 
 /// Handle all vars
+///
+/// Built-in facts, once enabled, are vars too, unless a var of the same name
+/// overrides them.
+///
+/// Vars file has one `name=value` by line. Lines starting with `#` are
+/// comments, the ones right above a var describing it. Vars after a
+/// `[host:name]` line only apply on the host `name`, over vars of every host,
+/// until an `[all]` line.
+#[derive(Clone, Debug, Default)]
 pub struct VarsHandler {
     vars: Vec<Var>,
+    layouts: Vec<Layout>,
+    /// Comment lines at the end of vars file
+    trailing: Vec<String>,
+    /// Name of this machine, selecting sections
+    host: Option<String>,
+    builtins: bool,
 }
 
 impl VarsHandler {
     /// Default constructor
     pub fn new(vars: Vec<Var>) -> VarsHandler {
-        VarsHandler { vars: vars }
+        VarsHandler {
+            layouts: vec![Layout::default(); vars.len()],
+            vars: vars,
+            trailing: Vec::new(),
+            host: hostname(),
+            builtins: false,
+        }
+    }
+
+    /// Enable built-in facts about the machine
+    pub fn with_builtins(mut self) -> VarsHandler {
+        self.builtins = true;
+        self
+    }
+
+    /// Get built-in facts if they are enabled, without `bin.<name>`
+    pub fn get_builtins(&self) -> Vec<Var> {
+        if self.builtins {
+            get_facts()
+        } else {
+            Vec::new()
+        }
     }
 
     /// Get vars file's path
@@ -29,27 +146,47 @@ impl VarsHandler {
 
     /// Read from buffer
     pub fn read_from_buf<S: AsRef<str>>(buf: S) -> Result<VarsHandler> {
-        let buf = buf.as_ref();
-        let lines = buf.split('\n');
-        let mut vars = Vec::new();
-        for line in lines {
+        let mut handler = VarsHandler::new(Vec::new());
+        let mut comments = Vec::new();
+        let mut host = None;
+        for (i, line) in buf.as_ref().lines().enumerate() {
+            let invalid = |reason: &str| ErrorKind::InvalidVarsLine(i + 1, reason.to_string());
             let line = line.trim();
-            if line.is_empty() {
+            if line.is_empty() || line.starts_with('#') {
+                comments.push(line.to_string());
+                continue;
+            }
+            if line.starts_with('[') {
+                let section = match line.strip_suffix(']') {
+                    Some(s) => s[1..].trim(),
+                    None => bail!(invalid("section is not closed")),
+                };
+                host = match section.strip_prefix("host:").map(|h| h.trim()) {
+                    Some("") => bail!(invalid("host is missing")),
+                    Some(h) => Some(h.to_string()),
+                    None if section == "all" => None,
+                    None => bail!(invalid(&format!("unknown section {}", section))),
+                };
+                comments.push(line.to_string());
                 continue;
             }
-            let mut split_eq = line.split('=');
-            let name = split_eq.next().unwrap().to_string();
+            let mut split_eq = line.splitn(2, '=');
+            let name = split_eq.next().unwrap().trim().to_string();
             let value = match split_eq.next() {
-                Some(v) => v,
-                None => bail!(ErrorKind::MissingValue(name)),
+                Some(v) => Value::parse(v.trim()),
+                None => bail!(invalid(&format!("variable {} doesn't have value", name))),
             };
-            let value = match value.parse::<bool>() {
-                Ok(v) => v,
-                Err(_) => bail!(ErrorKind::InvalidValue(value.to_string())),
-            };
-            vars.push((name, value));
+            if name.is_empty() {
+                bail!(invalid("variable doesn't have name"));
+            }
+            handler.vars.push((name, value));
+            handler.layouts.push(Layout {
+                comments: ::std::mem::take(&mut comments),
+                host: host.clone(),
+            });
         }
-        Ok(VarsHandler::new(vars))
+        handler.trailing = comments;
+        Ok(handler)
     }
 
     /// Read from stream
@@ -61,71 +198,144 @@ impl VarsHandler {
 
     /// Write to stream
     pub fn write_to_stream<W: Write>(&self, stream: &mut W) -> Result<()> {
-        for var in &self.vars {
-            write!(stream, "{}={}\n", var.0, var.1)?;
-        }
+        let mut s = String::new();
+        self.write_to_string(&mut s);
+        stream.write_all(s.as_bytes())?;
         Ok(())
     }
 
     /// Write to string
     pub fn write_to_string(&self, s: &mut String) {
-        for var in &self.vars {
+        for (var, layout) in self.vars.iter().zip(&self.layouts) {
+            for comment in &layout.comments {
+                s.push_str(&format!("{}\n", comment));
+            }
             s.push_str(&format!("{}={}\n", var.0, var.1));
         }
+        for comment in &self.trailing {
+            s.push_str(&format!("{}\n", comment));
+        }
     }
 
-    /// Add var
+    /// Add var for every host
     pub fn add_var(&mut self, var: Var) {
-        self.vars.push(var);
+        self.add_host_var(var, None);
+    }
+
+    /// Add var only applying on `host`, or on every host if `None`
+    ///
+    /// The var is added after the last one of the same host, or in a new
+    /// section. Return the index of the var.
+    pub fn add_host_var(&mut self, var: Var, host: Option<String>) -> usize {
+        let last = self.layouts.iter().rposition(|l| l.host == host);
+        let (index, comments) = match (last, host.as_ref()) {
+            (Some(i), _) => (i + 1, Vec::new()),
+            (None, None) => (0, Vec::new()),
+            (None, Some(h)) if self.vars.is_empty() => (0, vec![format!("[host:{}]", h)]),
+            (None, Some(h)) => (self.vars.len(), vec![String::new(), format!("[host:{}]", h)]),
+        };
+        self.vars.insert(index, var);
+        self.layouts.insert(index, Layout { comments, host });
+        index
     }
 
     /// Remove var
+    ///
+    /// The var of this host is removed first if there are many.
     pub fn remove_var<S: AsRef<str>>(&mut self, var_name: S) -> Option<Var> {
-        let var_name = var_name.as_ref();
-        for i in 0..self.vars.len() {
-            if self.vars[i].0 == var_name {
-                return Some(self.vars.remove(i));
-            }
-        }
-        None
+        let index = self.find(var_name.as_ref())?;
+        Some(self.remove_at(index))
     }
 
-    /// Get numbers of var
+    /// Remove var at `index`, keeping the comments which don't describe it
+    pub fn remove_at(&mut self, index: usize) -> Var {
+        let mut layout = self.layouts.remove(index);
+        let len = layout.comments.len() - layout.description_len();
+        layout.comments.truncate(len);
+        let next = match self.layouts.get_mut(index) {
+            Some(l) => &mut l.comments,
+            None => &mut self.trailing,
+        };
+        layout.comments.append(next);
+        *next = layout.comments;
+        self.vars.remove(index)
+    }
+
+    /// Get numbers of var, of every host
     pub fn len(&self) -> usize {
         self.vars.len()
     }
 
-    /// Check if var is present
+    /// Check if var is present on this host
     pub fn is_present<S: AsRef<str>>(&self, name: S) -> bool {
-        let name = name.as_ref();
-        for var in &self.vars {
-            if var.0 == name {
-                return true;
-            }
-        }
-        false
+        self.find(name.as_ref()).is_some()
     }
 
-    /// Get var value
-    pub fn get_var<S: AsRef<str>>(&self, name: S) -> Option<bool> {
+    /// Get var value, or the value of the built-in fact if there is no var
+    pub fn get_var<S: AsRef<str>>(&self, name: S) -> Option<Value> {
         let name = name.as_ref();
-        for var in &self.vars {
-            if var.0 == name {
-                return Some(var.1);
-            }
+        if let Some(i) = self.find(name) {
+            return Some(self.vars[i].1.clone());
+        }
+        if self.builtins {
+            get_fact(name)
+        } else {
+            None
         }
-        None
     }
 
     /// Set var value
-    pub fn set_var<S: AsRef<str>>(&mut self, name: S, value: bool) {
-        let name = name.as_ref();
-        for var in &mut self.vars {
-            if var.0 == name {
-                var.1 = value;
-                return;
+    pub fn set_var<S: AsRef<str>, V: Into<Value>>(&mut self, name: S, value: V) {
+        if let Some(i) = self.find(name.as_ref()) {
+            self.vars[i].1 = value.into();
+        }
+    }
+
+    /// Get the host var at `index` applies on, `None` for every host
+    pub fn get_host(&self, index: usize) -> Option<&str> {
+        self.layouts[index].host.as_deref()
+    }
+
+    /// Get the description of the var at `index`, written in comments above it
+    pub fn get_description(&self, index: usize) -> Option<String> {
+        let layout = &self.layouts[index];
+        let len = layout.description_len();
+        if len == 0 {
+            return None;
+        }
+        Some(
+            layout.comments[layout.comments.len() - len..]
+                .iter()
+                .map(|c| c.trim_start_matches('#').trim())
+                .collect::<Vec<&str>>()
+                .join(" "),
+        )
+    }
+
+    /// Set the description of the var at `index`, removed if empty
+    pub fn set_description<S: AsRef<str>>(&mut self, index: usize, description: S) {
+        let layout = &mut self.layouts[index];
+        let len = layout.comments.len() - layout.description_len();
+        layout.comments.truncate(len);
+        for line in description.as_ref().lines().filter(|l| !l.trim().is_empty()) {
+            layout.comments.push(format!("# {}", line.trim()));
+        }
+    }
+
+    /// Find the var applying on this host, the one of its section first
+    fn find(&self, name: &str) -> Option<usize> {
+        let mut found = None;
+        for (i, var) in self.vars.iter().enumerate() {
+            if var.0 != name {
+                continue;
+            }
+            match self.layouts[i].host {
+                None if found.is_none() => found = Some(i),
+                Some(ref h) if Some(h) == self.host.as_ref() => return Some(i),
+                _ => {}
             }
         }
+        found
     }
 }
 
@@ -206,7 +416,7 @@ mod unit_tests {
             ("he", false),
             ("yo", false),
         ].into_iter()
-            .map(|s| (s.0.to_string(), s.1))
+            .map(|s| (s.0.to_string(), Value::Bool(s.1)))
             .collect()
     }
 
@@ -250,17 +460,89 @@ mod unit_tests {
     #[test]
     fn get_var_test() {
         let handler = VarsHandler::new(set_of_var());
-        assert_eq!(handler.get_var("Hello"), Some(true));
+        assert_eq!(handler.get_var("Hello"), Some(Value::Bool(true)));
         assert_eq!(handler.get_var("hello"), None);
-        assert_eq!(handler.get_var("he"), Some(false));
+        assert_eq!(handler.get_var("he"), Some(Value::Bool(false)));
     }
 
     #[test]
     fn set_var_test() {
         let mut handler = VarsHandler::new(set_of_var());
         handler.set_var("Hello", false);
-        assert_eq!(handler.get_var("Hello"), Some(false));
-        handler.set_var("Hello", true);
-        assert_eq!(handler.get_var("Hello"), Some(true));
+        assert_eq!(handler.get_var("Hello"), Some(Value::Bool(false)));
+        handler.set_var("Hello", 16i64);
+        assert_eq!(handler.get_var("Hello"), Some(Value::Int(16)));
+    }
+
+    #[test]
+    fn builtins_test() {
+        let handler = VarsHandler::new(vec![("os".to_string(), Value::Str("plan9".to_string()))]);
+        assert!(handler.get_builtins().is_empty());
+        assert_eq!(handler.get_var("arch"), None);
+        let handler = handler.with_builtins();
+        assert_eq!(handler.get_var("os"), Some(Value::Str("plan9".to_string())));
+        assert_eq!(
+            handler.get_var("arch"),
+            Some(Value::Str(::std::env::consts::ARCH.to_string()))
+        );
+        assert_eq!(handler.len(), 1);
+    }
+
+    #[test]
+    fn read_sections() {
+        let file = "# Vars of every machine\n\n# Gentoo machines\ngentoo=true\nscreen=normal\n\n[host:box]\n# Big screen\nscreen=hidpi\n[all]\nwork=false\n# end\n";
+        let mut handler = VarsHandler::read_from_buf(file).unwrap();
+        handler.host = Some("box".to_string());
+        assert_eq!(handler.get_var("screen"), Some(Value::Str("hidpi".to_string())));
+        handler.host = Some("other".to_string());
+        assert_eq!(handler.get_var("screen"), Some(Value::Str("normal".to_string())));
+        assert_eq!(handler.get_host(2), Some("box"));
+        assert_eq!(handler.get_host(3), None);
+        assert_eq!(handler.get_description(0), Some("Gentoo machines".to_string()));
+        assert_eq!(handler.get_description(1), None);
+        assert_eq!(handler.get_description(2), Some("Big screen".to_string()));
+        let mut buf = String::new();
+        handler.write_to_string(&mut buf);
+        assert_eq!(buf, file);
+        handler.remove_var("gentoo");
+        handler.add_host_var(("cores".to_string(), Value::Int(16)), Some("box".to_string()));
+        handler.add_var(("distro".to_string(), Value::Str("gentoo".to_string())));
+        let index = handler.add_host_var(("laptop".to_string(), Value::Bool(true)), Some("tp".to_string()));
+        handler.set_description(index, "Laptop");
+        let mut buf = String::new();
+        handler.write_to_string(&mut buf);
+        assert_eq!(
+            buf,
+            "# Vars of every machine\n\nscreen=normal\n\n[host:box]\n# Big screen\nscreen=hidpi\ncores=16\n[all]\nwork=false\ndistro=gentoo\n\n[host:tp]\n# Laptop\nlaptop=true\n# end\n"
+        );
+    }
+
+    #[test]
+    fn read_invalid_lines() {
+        let errors = [
+            ("a=true\n\nb\n", 3),
+            ("[host:a\n", 1),
+            ("a=1\n[host: ]\n", 2),
+            ("[other]\n", 1),
+            ("=1\n", 1),
+        ];
+        for &(file, line) in &errors {
+            match VarsHandler::read_from_buf(file) {
+                Err(Error(ErrorKind::InvalidVarsLine(l, _), _)) => assert_eq!(l, line, "{}", file),
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn read_typed_values() {
+        let handler = VarsHandler::read_from_buf("distro=gentoo\ncores = 16\nlaptop=false\nurl=a=b\n").unwrap();
+        assert_eq!(handler.get_var("distro"), Some(Value::Str("gentoo".to_string())));
+        assert_eq!(handler.get_var("cores"), Some(Value::Int(16)));
+        assert_eq!(handler.get_var("laptop"), Some(Value::Bool(false)));
+        assert_eq!(handler.get_var("url"), Some(Value::Str("a=b".to_string())));
+        let mut buf = String::new();
+        handler.write_to_string(&mut buf);
+        assert_eq!(buf, "distro=gentoo\ncores=16\nlaptop=false\nurl=a=b\n");
     }
 }